@@ -0,0 +1,76 @@
+use std::f32;
+
+use film::{Film, Sample};
+
+// keeps black pixels from never converging
+const MIN_LUMINANCE: f32 = 0.01;
+
+/// Decides which pixels get another sample. A pixel keeps sampling until the
+/// standard deviation of its mean luminance is below `threshold` times the mean.
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveSampler {
+    // maximum relative error of a converged pixel
+    pub threshold: f32,
+    // every pixel gets this many samples before its variance is trusted
+    pub min_samples: f32,
+    // pixels stop sampling here even when they did not converge
+    pub max_samples: f32,
+}
+
+impl AdaptiveSampler {
+    pub fn new(threshold: f32) -> AdaptiveSampler {
+        AdaptiveSampler {
+            threshold: threshold,
+            min_samples: 8.,
+            max_samples: f32::INFINITY,
+        }
+    }
+
+    pub fn relative_error(pixel: &Sample) -> f32 {
+        pixel.variance().sqrt() / (pixel.luminance_mean + MIN_LUMINANCE)
+    }
+
+    /// Marks the pixels that need another sample. The error of a pixel is the largest
+    /// error in its 3x3 neighbourhood, a pixel whose few samples happened to agree
+    /// does not stop while its neighbours are still noisy.
+    pub fn mask(&self, film: &Film) -> Vec<bool> {
+        let errors: Vec<_> = film.pixels.iter().map(|pixel| {
+            if pixel.samples < self.min_samples { f32::INFINITY } else { AdaptiveSampler::relative_error(pixel) }
+        }).collect();
+        let (width, height) = (film.width as i32, film.height as i32);
+        film.pixels.iter().enumerate().map(|(idx, pixel)| {
+            if pixel.samples >= self.max_samples {
+                return false;
+            }
+            let (x, y) = ((idx % film.width) as i32, (idx / film.width) as i32);
+            let mut error: f32 = 0.;
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    if x + dx >= 0 && y + dy >= 0 && x + dx < width && y + dy < height {
+                        error = error.max(errors[(x + dx + (y + dy) * width) as usize]);
+                    }
+                }
+            }
+            error > self.threshold
+        }).collect()
+    }
+}
+
+#[test]
+fn adaptive_mask_converges() {
+    let mut film = Film::new(3, 1);
+    let mut sample = Sample::new();
+    sample.samples = 1.;
+    // constant pixels converge after the minimum number of samples
+    for _ in 0..8 {
+        sample.luminance_mean = 0.5;
+        film.pixels[0].add(&sample);
+        film.pixels[1].add(&sample);
+        film.pixels[2].add(&sample);
+    }
+    let sampler = AdaptiveSampler::new(0.05);
+    assert_eq!(sampler.mask(&film), vec![false, false, false]);
+    // a noisy pixel keeps itself and its neighbour sampling
+    film.pixels[2].add(&Sample { luminance_mean: 5., ..sample });
+    assert_eq!(sampler.mask(&film), vec![false, true, true]);
+}
@@ -0,0 +1,146 @@
+extern crate cgmath;
+use self::cgmath::Point3;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+
+/// The camera at a frame of the animation
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Keyframe {
+    pub frame: f32,
+    pub origin: Point3<f32>,
+    pub target: Point3<f32>,
+    // autofocus when None
+    pub focus_distance: Option<f32>,
+    pub f_number: f32,
+}
+
+/// Keyframes of the camera, interpolated with a Catmull-Rom spline
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    // sorted by frame
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// The frames of a camera path to render, both included
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    pub path: CameraPath,
+    pub first: u32,
+    pub last: u32,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<CameraPath, io::Error> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        CameraPath::parse(&text)
+    }
+
+    /// One keyframe per line, `frame ox oy oz tx ty tz focus f_number` with the origin and
+    /// target of the camera, `auto` as focus distance focuses on the centre of the view.
+    /// Everything after a `#` is a comment.
+    pub fn parse(text: &str) -> Result<CameraPath, io::Error> {
+        let mut keyframes = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid keyframe on line {}", number + 1));
+            if fields.len() != 9 {
+                return Err(invalid());
+            }
+            let mut values = [0.; 7];
+            for (value, field) in values.iter_mut().zip(fields.iter()) {
+                *value = try!(field.parse().map_err(|_| invalid()));
+            }
+            let keyframe = Keyframe {
+                frame: values[0],
+                origin: Point3::new(values[1], values[2], values[3]),
+                target: Point3::new(values[4], values[5], values[6]),
+                focus_distance: if fields[7] == "auto" { None } else { Some(try!(fields[7].parse().map_err(|_| invalid()))) },
+                f_number: try!(fields[8].parse().map_err(|_| invalid())),
+            };
+            // "nan" and "inf" parse as floats, but cannot be sorted or interpolated
+            if !values.iter().chain(keyframe.focus_distance.iter()).chain(Some(keyframe.f_number).iter()).all(|value| value.is_finite()) {
+                return Err(invalid());
+            }
+            keyframes.push(keyframe);
+        }
+        if keyframes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "camera path without keyframes"));
+        }
+        // the frames are finite, so they compare
+        keyframes.sort_by(|a, b| a.frame.partial_cmp(&b.frame).unwrap());
+        Ok(CameraPath { keyframes: keyframes })
+    }
+
+    pub fn first_frame(&self) -> f32 {
+        self.keyframes[0].frame
+    }
+
+    pub fn last_frame(&self) -> f32 {
+        self.keyframes[self.keyframes.len() - 1].frame
+    }
+
+    /// The camera at a frame, held still before the first and after the last keyframe.
+    /// The focus distance is only interpolated between two keyframes that both have one.
+    pub fn at(&self, frame: f32) -> Keyframe {
+        let keys = &self.keyframes;
+        let frame = frame.max(self.first_frame()).min(self.last_frame());
+        let i = match keys.iter().position(|key| key.frame > frame) {
+            Some(i) => i.max(1) - 1,
+            None => return Keyframe { frame: frame, ..keys[keys.len() - 1] },
+        };
+        // the neighbours of the segment, repeated at the ends
+        let (k0, k1, k2, k3) = (&keys[i.max(1) - 1], &keys[i], &keys[i + 1], &keys[(i + 2).min(keys.len() - 1)]);
+        let spline = |value: &Fn(&Keyframe) -> f32| catmull_rom((k0.frame, value(k0)), (k1.frame, value(k1)), (k2.frame, value(k2)), (k3.frame, value(k3)), frame);
+        Keyframe {
+            frame: frame,
+            origin: Point3::new(spline(&|key| key.origin.x), spline(&|key| key.origin.y), spline(&|key| key.origin.z)),
+            target: Point3::new(spline(&|key| key.target.x), spline(&|key| key.target.y), spline(&|key| key.target.z)),
+            focus_distance: match (k1.focus_distance, k2.focus_distance) {
+                (Some(_), Some(_)) => Some(spline(&|key| key.focus_distance.or(k1.focus_distance).unwrap()).max(1e-3)),
+                _ => k1.focus_distance,
+            },
+            f_number: spline(&|key| key.f_number).max(0.1),
+        }
+    }
+}
+
+// Catmull-Rom between the second and third key, the tangents are the slopes
+// over the neighbouring keys, scaled for keys that are not evenly spaced
+fn catmull_rom(p0: (f32, f32), p1: (f32, f32), p2: (f32, f32), p3: (f32, f32), t: f32) -> f32 {
+    let span = p2.0 - p1.0;
+    if span <= 0. {
+        return p1.1;
+    }
+    let slope = |a: (f32, f32), b: (f32, f32)| if b.0 > a.0 { (b.1 - a.1) / (b.0 - a.0) } else { 0. };
+    let m1 = slope(p0, p2) * span;
+    let m2 = slope(p1, p3) * span;
+    let s = (t - p1.0) / span;
+    let (s2, s3) = (s * s, s * s * s);
+    (2. * s3 - 3. * s2 + 1.) * p1.1 + (s3 - 2. * s2 + s) * m1 + (-2. * s3 + 3. * s2) * p2.1 + (s3 - s2) * m2
+}
+
+#[test]
+fn camera_path_interpolation() {
+    let path = CameraPath::parse("# frame origin target focus f-number
+        0   0 0 0   0 0 1   auto 2.8
+        10  1 0 0   1 0 1   2.0  2.8
+        30  3 0 0   3 0 1   4.0  5.6").unwrap();
+    // the spline goes through the keyframes and keeps a straight line straight
+    assert_eq!(path.at(10.).origin, Point3::new(1., 0., 0.));
+    assert!((path.at(20.).origin.x - 2.).abs() < 1e-5);
+    assert!((path.at(5.).target.x - 0.5).abs() < 1e-5);
+    // the camera holds still outside of the keyframes, autofocus until the second keyframe
+    assert_eq!(path.at(40.).origin, Point3::new(3., 0., 0.));
+    assert_eq!(path.at(5.).focus_distance, None);
+    assert!(path.at(20.).focus_distance.unwrap() > 2.);
+    assert!(CameraPath::parse("0 0 0").is_err());
+    assert!(CameraPath::parse("nan 0 0 0 0 0 1 auto 2.8\n0 0 0 0 0 0 1 auto 2.8").is_err());
+    assert!(CameraPath::parse("0 0 0 0 0 0 1 inf 2.8").is_err());
+}
@@ -0,0 +1,166 @@
+extern crate rand;
+use self::rand::Closed01;
+use random::random;
+use std::f32;
+use std::io;
+use std::io::{Read, BufRead, BufReader};
+use std::fs::File;
+use std::path::Path;
+
+/// Shape of the opening of the lens, out of focus highlights take this shape
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circular,
+    // regular polygon of straight blades inside the circle, rotated in radians
+    Polygonal { blades: u32, rotation: f32 },
+    // the opening is as bright as the image
+    Image(BokehImage),
+}
+
+impl Aperture {
+    /// a point on the aperture, the circle or the image fit in `[-1, 1]`
+    pub fn sample(&self) -> (f32, f32) {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        match *self {
+            Aperture::Circular => {
+                let r = r0.sqrt();
+                let phi = 2. * f32::consts::PI * r1;
+                (r * phi.cos(), r * phi.sin())
+            },
+            Aperture::Polygonal { blades, rotation } => {
+                // every blade has the same triangle from the centre, pick one and a point in it
+                let Closed01(r2) = random::<Closed01<f32>>();
+                let blade = ((r2 * blades as f32) as u32).min(blades - 1);
+                let angle = 2. * f32::consts::PI / blades as f32;
+                let (a, b) = (rotation + blade as f32 * angle, rotation + (blade + 1) as f32 * angle);
+                let (r0, r1) = if r0 + r1 > 1. { (1. - r0, 1. - r1) } else { (r0, r1) };
+                (r0 * a.cos() + r1 * b.cos(), r0 * a.sin() + r1 * b.sin())
+            },
+            Aperture::Image(ref image) => image.sample(r0, r1),
+        }
+    }
+}
+
+/// A grey image to sample aperture points from, brighter pixels are more open
+#[derive(Debug, Clone)]
+pub struct BokehImage {
+    width: usize,
+    height: usize,
+    // running sum over the brightness of the rows, from the top
+    rows: Vec<f32>,
+    // running sum over the pixels of every row
+    columns: Vec<f32>,
+}
+
+impl BokehImage {
+    /// reads a binary PGM (P5) with a maximum value below 256
+    pub fn load(path: &Path) -> Result<BokehImage, io::Error> {
+        let mut file = BufReader::new(try!(File::open(path)));
+        // the header is whitespace separated, comments start with #
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            let mut line = String::new();
+            if try!(file.read_line(&mut line)) == 0 {
+                return Err(invalid("truncated PGM header"));
+            }
+            let line = line.split('#').next().unwrap_or("");
+            header.extend(line.split_whitespace().map(|token| token.to_string()));
+        }
+        if header[0] != "P5" {
+            return Err(invalid("bokeh image has to be a binary PGM"));
+        }
+        let number = |token: &str| token.parse::<usize>().map_err(|_| invalid("invalid PGM header"));
+        let (width, height, max) = (try!(number(&header[1])), try!(number(&header[2])), try!(number(&header[3])));
+        if max == 0 || max > 255 {
+            return Err(invalid("only 8 bit PGM images are supported"));
+        }
+        let size = try!(width.checked_mul(height).ok_or_else(|| invalid("PGM image is too large")));
+        let mut pixels = vec![0; size];
+        try!(file.read_exact(&mut pixels));
+        BokehImage::new(width, height, &pixels.iter().map(|&pixel| pixel as f32).collect::<Vec<_>>())
+    }
+
+    pub fn new(width: usize, height: usize, pixels: &[f32]) -> Result<BokehImage, io::Error> {
+        if width == 0 || height == 0 {
+            return Err(invalid("bokeh image is empty"));
+        }
+        if width.checked_mul(height) != Some(pixels.len()) {
+            return Err(invalid("bokeh image size does not match its pixels"));
+        }
+        let mut rows = vec![0.];
+        let mut columns = Vec::with_capacity(height * (width + 1));
+        for row in pixels.chunks(width) {
+            columns.push(0.);
+            for pixel in row {
+                let sum = columns[columns.len() - 1] + pixel.max(0.);
+                columns.push(sum);
+            }
+            let sum = rows[rows.len() - 1] + columns[columns.len() - 1];
+            rows.push(sum);
+        }
+        if rows[height] <= 0. {
+            return Err(invalid("bokeh image is black"));
+        }
+        Ok(BokehImage {
+            width: width,
+            height: height,
+            rows: rows,
+            columns: columns,
+        })
+    }
+
+    /// a point distributed like the brightness, the image covers `[-1, 1]` with its top up
+    pub fn sample(&self, r0: f32, r1: f32) -> (f32, f32) {
+        let (row, t) = sample_running_sum(&self.rows, r0);
+        let (column, s) = sample_running_sum(&self.columns[row * (self.width + 1)..(row + 1) * (self.width + 1)], r1);
+        let x = (column as f32 + s) / self.width as f32;
+        let y = (row as f32 + t) / self.height as f32;
+        (2. * x - 1., 1. - 2. * y)
+    }
+}
+
+// the bin a uniform number falls in and where it falls in that bin, for a running sum that starts at 0
+fn sample_running_sum(sums: &[f32], u: f32) -> (usize, f32) {
+    let bins = sums.len() - 1;
+    let target = u * sums[bins];
+    // the first bin that ends at or after the target
+    let mut i = match sums[1..].binary_search_by(|sum| sum.partial_cmp(&target).unwrap()) {
+        Ok(i) => i,
+        Err(i) => i,
+    }.min(bins - 1);
+    while i > 0 && sums[i] >= target {
+        i -= 1;
+    }
+    // a target of 0 can still land on empty bins at the start
+    let i = (i..bins).find(|&i| sums[i + 1] > sums[i]).unwrap_or(i);
+    let width = sums[i + 1] - sums[i];
+    let t = if width > 0. { ((target - sums[i]) / width).max(0.).min(1.) } else { 0.5 };
+    (i, t)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[test]
+fn aperture_samples_inside_opening() {
+    for _ in 0..1000 {
+        let (x, y) = Aperture::Circular.sample();
+        assert!(x * x + y * y <= 1. + 1e-5);
+        // a square has its corners on the circle and its edges at 1 / sqrt(2)
+        let (x, y) = Aperture::Polygonal { blades: 4, rotation: f32::consts::FRAC_PI_4 }.sample();
+        assert!(x.abs() <= f32::consts::FRAC_1_SQRT_2 + 1e-5 && y.abs() <= f32::consts::FRAC_1_SQRT_2 + 1e-5);
+    }
+    // only the bright top right pixel of a 2x2 image is open
+    let image = BokehImage::new(2, 2, &[0., 1., 0., 0.]).unwrap();
+    for i in 0..100 {
+        let (x, y) = Aperture::Image(image.clone()).sample();
+        assert!(x >= 0. && y >= 0.);
+        let (x, y) = image.sample(i as f32 / 99., 1. - i as f32 / 99.);
+        assert!(x >= 0. && y >= 0.);
+    }
+    // images without pixels are refused
+    assert!(BokehImage::new(0, 2, &[]).is_err());
+    assert!(BokehImage::new(2, 2, &[1., 1.]).is_err());
+}
@@ -1,4 +1,4 @@
-use rand;
+use random::with_generator;
 use std::f32;
 
 use primitive::Primitive;
@@ -6,6 +6,15 @@ use primitive::aabb::AABB;
 
 use ray::{Ray,Intersection};
 
+// the settings of the build, a cached BVH is only reused when they are the same
+// bins along each axis to place split-planes between
+pub const BINS: usize = 8;
+// nodes with more primitives are split when it is cheaper
+pub const LEAF_SIZE: u32 = 2;
+
+// a node as its bounds, left child or first index and count
+pub type NodeParts = (AABB, u32, u32);
+
 #[derive(Debug)]
 struct BVHNode {
     bounds: AABB,
@@ -53,7 +62,7 @@ impl<T: Primitive> BVH<T> {
         let mut node_stack = vec![];
         node_stack.push(0); // root node
         while let Some(node_index) = node_stack.pop() {
-            if self.bvh_nodes[node_index].count > 2 && self.partition(node_index) {
+            if self.bvh_nodes[node_index].count > LEAF_SIZE && self.partition(node_index) {
                 let left = self.bvh_nodes[node_index].left_first as usize;
                 node_stack.push(left + 1);
                 node_stack.push(left);
@@ -110,21 +119,21 @@ impl<T: Primitive> BVH<T> {
             centre_bound = centre_bound.extent(centre);
         }
         let axis_length = centre_bound.size();
-        let axis_delta = axis_length / 8.;
+        let axis_delta = axis_length / BINS as f32;
 
-        let mut bounds = [AABB::new(); 24]; // 8 bounds times 3 axis
-        let mut primitives = [0; 24];
+        let mut bounds = [AABB::new(); 3 * BINS]; // bounds for the bins of all 3 axis
+        let mut primitives = [0; 3 * BINS];
         for index in first..first + count {
             let object = &self.objects[self.indices[index]];
             let centre = object.centre();
             let bound = object.bounds();
 
             for axis in 0..3 {
-                let bin_index = axis * 8 +
+                let bin_index = axis * BINS +
                     // (centre on axis - minimum centre) / axis delta
                     (((centre[axis] - centre_bound.min[axis]) / axis_delta[axis])
-                    // clamp between 0 and 7
-                    .max(0.).min(7.) as usize);
+                    // clamp to the bins
+                    .max(0.).min((BINS - 1) as f32) as usize);
                 bounds[bin_index] = bounds[bin_index].combine(&bound);
                 primitives[bin_index] += 1;
             }
@@ -134,14 +143,14 @@ impl<T: Primitive> BVH<T> {
         let mut min_bin = None;
         let mut min_sah = sah_parent;
         for axis in 0..3 {
-            let axis_offset = axis * 8;
+            let axis_offset = axis * BINS;
 
             // calculate all boundaries and number of primitives from the right side
-            let mut bounds_right = [AABB::new(); 8];
-            let mut primitives_right = [0; 8];
-            bounds_right[7] = bounds[axis_offset + 7];
-            primitives_right[7] = primitives[axis_offset + 7];
-            for bin_offset in (0..7).rev() {
+            let mut bounds_right = [AABB::new(); BINS];
+            let mut primitives_right = [0; BINS];
+            bounds_right[BINS - 1] = bounds[axis_offset + BINS - 1];
+            primitives_right[BINS - 1] = primitives[axis_offset + BINS - 1];
+            for bin_offset in (0..BINS - 1).rev() {
                 bounds_right[bin_offset] = bounds_right[bin_offset + 1].combine(&bounds[axis_offset + bin_offset]);
                 primitives_right[bin_offset] = primitives_right[bin_offset + 1] + primitives[axis_offset + bin_offset];
             }
@@ -149,7 +158,7 @@ impl<T: Primitive> BVH<T> {
             // incrementaly calculate boundaries and number of primitives from the left side
             let mut bounds_left = AABB::new();
             let mut primitives_left = 0;
-            for bin_offset in 0..8 {
+            for bin_offset in 0..BINS {
                 bounds_left = bounds_left.combine(&bounds[axis_offset + bin_offset]);
                 primitives_left += primitives[axis_offset + bin_offset];
                 let sah: f32 = primitives_left as f32 * bounds_left.area() + primitives_right[bin_offset] as f32 * bounds_right[bin_offset].area();
@@ -188,15 +197,26 @@ impl<T: Primitive> BVH<T> {
         }
         closest_intersection
         */
+        self.closest(ray, None)
+    }
+
+    // the closest intersection, counting the nodes and the primitives that get tested
+    fn closest(&self, ray: &mut Ray, mut cost: Option<&mut (u32, u32)>) -> Option<Intersection> {
         let mut closest_intersection = None;
         let mut node_stack = vec![];
         node_stack.push(0); // root node
         while let Some(node_index) = node_stack.pop() {
             let node = &self.bvh_nodes[node_index];
+            if let Some(ref mut cost) = cost {
+                cost.0 += 1;
+            }
             if let Some(_) = node.bounds.intersect(ray) { // prune stack pops that don't get intersected anymore
                 if node.count != 0 {
                     // leaf node
                     for index in node.left_first as usize..(node.left_first + node.count) as usize {
+                        if let Some(ref mut cost) = cost {
+                            cost.1 += 1;
+                        }
                         let object = &self.objects[self.indices[index]];
                         if let Some(intersection) = object.intersect(ray) {
                             closest_intersection = Some(intersection);
@@ -270,14 +290,64 @@ impl<T: Primitive> BVH<T> {
         None
     }
 
+    /// number of nodes and primitives tested when finding the closest intersection
+    pub fn traversal_cost(&self, ray: &mut Ray) -> (u32, u32) {
+        let mut cost = (0, 0);
+        self.closest(ray, Some(&mut cost));
+        cost
+    }
+
+    /// bounds of all objects
+    pub fn bounds(&self) -> AABB {
+        self.bvh_nodes[0].bounds
+    }
+
+    pub fn objects(&self) -> &[T] {
+        &self.objects
+    }
+
+    /// gives the objects back, in the order they were given
+    pub fn into_objects(self) -> Vec<T> {
+        self.objects
+    }
+
+    /// the built tree, as the parts of every node, the order of the objects in the leaves and the objects that are lights
+    pub fn parts(&self) -> (Vec<NodeParts>, &[usize], &[usize]) {
+        let nodes = self.bvh_nodes.iter().map(|node| (node.bounds, node.left_first, node.count)).collect();
+        (nodes, &self.indices, &self.lights)
+    }
+
+    /// a BVH that was built before from its parts, without building it again,
+    /// or none if they don't fit the objects
+    pub fn from_parts(objects: Vec<T>, nodes: Vec<NodeParts>, indices: Vec<usize>, lights: Vec<usize>) -> Option<BVH<T>> {
+        let len = objects.len();
+        if nodes.is_empty() || indices.len() != len || indices.iter().chain(lights.iter()).any(|&index| index >= len) {
+            return None;
+        }
+        // children come after their parent, so the traversal always ends
+        let fits = nodes.iter().enumerate().all(|(index, &(_, left_first, count))| if count != 0 {
+            left_first as usize + count as usize <= len
+        } else {
+            left_first as usize > index && left_first as usize + 1 < nodes.len()
+        });
+        if !fits {
+            return None;
+        }
+        Some(BVH {
+            objects: objects,
+            indices: indices,
+            lights: lights,
+            bvh_nodes: nodes.into_iter().map(|(bounds, left_first, count)| BVHNode { bounds: bounds, left_first: left_first, count: count }).collect(),
+        })
+    }
+
     pub fn random_light(&self) -> Option<(usize, &T)> {
         if self.lights.len() == 0 {
             None
         } else {
             use rand::distributions::*;
-            let mut rng = rand::thread_rng();
             let index_range: Range<usize> = Range::new(0, self.lights.len());
-            let i = index_range.ind_sample(&mut rng);
+            let i = with_generator(|rng| index_range.ind_sample(rng));
             let obj_idx = self.lights[i];
             Some((self.lights.len(), &self.objects[obj_idx]))
         }
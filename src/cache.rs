@@ -0,0 +1,348 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, Point3, Point2};
+extern crate memmap;
+use self::memmap::*;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bvh::{BVH, BINS, LEAF_SIZE};
+use checkpoint;
+use checkpoint::{invalid, write_u32, read_u32, write_u64, read_u64};
+use hair::Hair;
+use material::Material;
+use medium::Medium;
+use mesh;
+use primitive::aabb::AABB;
+use primitive::triangle::Triangle;
+use primitive::triangle_mesh::{Geometry, TriangleMesh, MeshTriangle};
+use spectrum::Dispersion;
+
+// Everything is stored little endian, in this order:
+// the magic bytes, the version, the hash of the source files and the hash of the build settings,
+// the materials as a tag and a fixed number of floats,
+// the meshes as their material, object id and the lengths of their arrays, followed by the arrays,
+// the BVH nodes as bounds, left child or first index and count,
+// the order of the triangles in the leaves and the triangles that are lights.
+const MAGIC: &[u8; 8] = b"PTSCENE\0";
+// has to go up whenever the layout changes
+const VERSION: u32 = 1;
+const MATERIAL_FLOATS: usize = 15;
+
+/// The geometry of the model and the lights and floor made by `extra`, with the BVH over it,
+/// from the cache next to the model when it was made from the same obj and mtl files with the
+/// same settings. Otherwise it is loaded and built, and the cache is written for the next time.
+pub fn load_or_build(path: &Path, backup_material: Material, extra: &Fn(u32) -> Vec<Triangle>) -> Result<BVH<MeshTriangle>, io::Error> {
+    let mut sources = vec![path.to_path_buf()];
+    sources.extend(try!(material_libraries(path)));
+    let source_hash = try!(checkpoint::hash_files(&sources));
+    // the object ids of the extra triangles follow from the source, so those at 0 are enough
+    let settings_hash = checkpoint::hash(&(BINS, LEAF_SIZE, &backup_material, extra(0)));
+    let cache_path = cache_path(path);
+
+    if let Ok(cache) = Mmap::open_path(&cache_path, Protection::Read) {
+        match decode(unsafe { cache.as_slice() }, source_hash, settings_hash) {
+            Ok(bvh) => {
+                println!("reused scene cache {}", cache_path.display());
+                return Ok(bvh);
+            },
+            Err(error) => println!("not using scene cache {}: {}", cache_path.display(), error),
+        }
+    }
+
+    let mut geometry = mesh::load_geometry(path, backup_material);
+    let object_id = geometry.meshes.iter().map(|mesh| mesh.object_id + 1).max().unwrap_or(0);
+    geometry.add_triangles(&extra(object_id));
+    let geometry = Arc::new(geometry);
+    let bvh = BVH::new(Geometry::triangles(&geometry));
+    // written next to the cache and renamed over it, so a cache is never left half written.
+    // Without a cache it still renders, it only has to load again next time
+    let temporary = format!("{}.tmp", cache_path.display());
+    let written = File::create(&temporary).and_then(|file| {
+        let mut file = BufWriter::new(file);
+        try!(encode(&mut file, source_hash, settings_hash, &geometry, &bvh));
+        file.flush()
+    }).and_then(|()| fs::rename(&temporary, &cache_path));
+    match written {
+        Ok(()) => println!("wrote scene cache {}", cache_path.display()),
+        Err(error) => println!("could not write scene cache {}: {}", cache_path.display(), error),
+    }
+    Ok(bvh)
+}
+
+/// where the cache of a model is kept, next to it
+pub fn cache_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.cache", path.display()))
+}
+
+// the mtl files the obj refers to, which are next to it, leaving out those that are missing
+fn material_libraries(path: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut libraries = Vec::new();
+    for line in BufReader::new(try!(File::open(path))).lines() {
+        let line = try!(line);
+        let mut words = line.split_whitespace();
+        if words.next() == Some("mtllib") {
+            libraries.extend(words.map(|name| directory.join(name)).filter(|library| library.is_file()));
+        }
+    }
+    Ok(libraries)
+}
+
+fn encode<W: Write>(file: &mut W, source_hash: u64, settings_hash: u64, geometry: &Geometry, bvh: &BVH<MeshTriangle>) -> Result<(), io::Error> {
+    try!(file.write_all(MAGIC));
+    try!(write_u32(file, VERSION));
+    try!(write_u64(file, source_hash));
+    try!(write_u64(file, settings_hash));
+
+    try!(write_u32(file, geometry.materials.len() as u32));
+    for material in &geometry.materials {
+        let (tag, mut floats) = encode_material(material);
+        floats.resize(MATERIAL_FLOATS, 0.);
+        try!(write_u32(file, tag));
+        try!(write_f32s(file, &floats));
+    }
+
+    try!(write_u32(file, geometry.meshes.len() as u32));
+    for mesh in &geometry.meshes {
+        try!(write_u32(file, mesh.material));
+        try!(write_u32(file, mesh.object_id));
+        try!(write_u32(file, mesh.positions.len() as u32));
+        try!(write_u32(file, mesh.normals.len() as u32));
+        try!(write_u32(file, mesh.uvs.len() as u32));
+        try!(write_u32(file, mesh.indices.len() as u32));
+        for position in &mesh.positions {
+            try!(write_f32s(file, &[position.x, position.y, position.z]));
+        }
+        for normal in &mesh.normals {
+            try!(write_f32s(file, &[normal.x, normal.y, normal.z]));
+        }
+        for uv in &mesh.uvs {
+            try!(write_f32s(file, &[uv.x, uv.y]));
+        }
+        for triangle in &mesh.indices {
+            for &index in triangle {
+                try!(write_u32(file, index));
+            }
+        }
+    }
+
+    let (nodes, indices, lights) = bvh.parts();
+    try!(write_u32(file, nodes.len() as u32));
+    for (bounds, left_first, count) in nodes {
+        try!(write_f32s(file, &[bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z]));
+        try!(write_u32(file, left_first));
+        try!(write_u32(file, count));
+    }
+    for list in &[indices, lights] {
+        try!(write_u32(file, list.len() as u32));
+        for &index in list.iter() {
+            try!(write_u32(file, index as u32));
+        }
+    }
+    Ok(())
+}
+
+fn decode(mut bytes: &[u8], source_hash: u64, settings_hash: u64) -> Result<BVH<MeshTriangle>, io::Error> {
+    // reading from the front of the mapped file, every read fails past its end
+    let reader = &mut bytes;
+    let mut magic = [0; 8];
+    try!(reader.read_exact(&mut magic));
+    if &magic != MAGIC {
+        return Err(invalid("not a scene cache"));
+    }
+    if try!(read_u32(reader)) != VERSION {
+        return Err(invalid("made by another version"));
+    }
+    if try!(read_u64(reader)) != source_hash {
+        return Err(invalid("the source file changed"));
+    }
+    if try!(read_u64(reader)) != settings_hash {
+        return Err(invalid("the build settings changed"));
+    }
+
+    let mut geometry = Geometry::new();
+    for _ in 0..try!(read_u32(reader)) {
+        let tag = try!(read_u32(reader));
+        let floats = try!(read_f32s(reader, MATERIAL_FLOATS));
+        geometry.add_material(try!(decode_material(tag, &floats).ok_or_else(|| invalid("unknown material"))));
+    }
+
+    for _ in 0..try!(read_u32(reader)) {
+        let material = try!(read_u32(reader));
+        let object_id = try!(read_u32(reader));
+        let positions = try!(read_u32(reader)) as usize;
+        let normals = try!(read_u32(reader)) as usize;
+        let uvs = try!(read_u32(reader)) as usize;
+        let indices = try!(read_u32(reader)) as usize;
+        let mesh = TriangleMesh {
+            positions: try!(read_f32s(reader, 3 * positions)).chunks(3).map(|i| Point3::new(i[0], i[1], i[2])).collect(),
+            normals: try!(read_f32s(reader, 3 * normals)).chunks(3).map(|i| Vector3::new(i[0], i[1], i[2])).collect(),
+            uvs: try!(read_f32s(reader, 2 * uvs)).chunks(2).map(|i| Point2::new(i[0], i[1])).collect(),
+            indices: try!(read_u32s(reader, 3 * indices)).chunks(3).map(|i| [i[0], i[1], i[2]]).collect(),
+            material: material,
+            object_id: object_id,
+        };
+        let fits = (mesh.material as usize) < geometry.materials.len()
+            && (normals == 0 || normals == positions) && (uvs == 0 || uvs == positions)
+            && mesh.indices.iter().all(|triangle| triangle.iter().all(|&index| (index as usize) < positions));
+        if !fits {
+            return Err(invalid("a mesh indexes outside of its arrays"));
+        }
+        geometry.add_mesh(mesh);
+    }
+
+    let mut nodes = Vec::new();
+    for _ in 0..try!(read_u32(reader)) {
+        let bounds = try!(read_f32s(reader, 6));
+        let left_first = try!(read_u32(reader));
+        let count = try!(read_u32(reader));
+        nodes.push((AABB { min: Point3::new(bounds[0], bounds[1], bounds[2]), max: Point3::new(bounds[3], bounds[4], bounds[5]) }, left_first, count));
+    }
+    let length = try!(read_u32(reader)) as usize;
+    let indices = try!(read_u32s(reader, length)).into_iter().map(|index| index as usize).collect();
+    let length = try!(read_u32(reader)) as usize;
+    let lights = try!(read_u32s(reader, length)).into_iter().map(|index| index as usize).collect();
+    if !reader.is_empty() {
+        return Err(invalid("trailing bytes"));
+    }
+
+    let objects = Geometry::triangles(&Arc::new(geometry));
+    BVH::from_parts(objects, nodes, indices, lights).ok_or_else(|| invalid("the BVH doesn't fit the geometry"))
+}
+
+// the kind of material and its parameters
+fn encode_material(material: &Material) -> (u32, Vec<f32>) {
+    match *material {
+        Material::Diffuse { speculaty, color } => (0, vec![speculaty, color.x, color.y, color.z]),
+        Material::Dielectric { refraction_index_n1, refraction_index_n2, color, dispersion } => {
+            let mut floats = vec![refraction_index_n1, refraction_index_n2, color.x, color.y, color.z];
+            match dispersion {
+                Dispersion::None => floats.push(0.),
+                Dispersion::Cauchy { b } => floats.extend_from_slice(&[1., b]),
+                Dispersion::Sellmeier { b, c } => floats.extend_from_slice(&[2., b[0], b[1], b[2], c[0], c[1], c[2]]),
+            }
+            (1, floats)
+        },
+        Material::Emissive { color } => (2, vec![color.x, color.y, color.z]),
+        Material::Volume { medium } => (3, vec![medium.absorption.x, medium.absorption.y, medium.absorption.z,
+                                                medium.scattering.x, medium.scattering.y, medium.scattering.z, medium.anisotropy]),
+        Material::Subsurface { refraction_index_n1, refraction_index_n2, albedo, mean_free_path, anisotropy } =>
+            (4, vec![refraction_index_n1, refraction_index_n2, albedo.x, albedo.y, albedo.z,
+                     mean_free_path.x, mean_free_path.y, mean_free_path.z, anisotropy]),
+        Material::Hair { hair } => (5, vec![hair.absorption.x, hair.absorption.y, hair.absorption.z,
+                                            hair.longitudinal_roughness, hair.azimuthal_roughness, hair.scale_angle, hair.refraction_index]),
+    }
+}
+
+fn decode_material(tag: u32, f: &[f32]) -> Option<Material> {
+    Some(match tag {
+        0 => Material::Diffuse { speculaty: f[0], color: Vector3::new(f[1], f[2], f[3]) },
+        1 => Material::Dielectric {
+            refraction_index_n1: f[0],
+            refraction_index_n2: f[1],
+            color: Vector3::new(f[2], f[3], f[4]),
+            dispersion: match f[5] as u32 {
+                0 => Dispersion::None,
+                1 => Dispersion::Cauchy { b: f[6] },
+                2 => Dispersion::Sellmeier { b: [f[6], f[7], f[8]], c: [f[9], f[10], f[11]] },
+                _ => return None,
+            },
+        },
+        2 => Material::Emissive { color: Vector3::new(f[0], f[1], f[2]) },
+        3 => Material::Volume { medium: Medium { absorption: Vector3::new(f[0], f[1], f[2]), scattering: Vector3::new(f[3], f[4], f[5]), anisotropy: f[6] } },
+        4 => Material::Subsurface {
+            refraction_index_n1: f[0],
+            refraction_index_n2: f[1],
+            albedo: Vector3::new(f[2], f[3], f[4]),
+            mean_free_path: Vector3::new(f[5], f[6], f[7]),
+            anisotropy: f[8],
+        },
+        5 => Material::Hair { hair: Hair {
+            absorption: Vector3::new(f[0], f[1], f[2]),
+            longitudinal_roughness: f[3],
+            azimuthal_roughness: f[4],
+            scale_angle: f[5],
+            refraction_index: f[6],
+        } },
+        _ => return None,
+    })
+}
+
+fn write_f32s<W: Write>(file: &mut W, values: &[f32]) -> Result<(), io::Error> {
+    for value in values {
+        try!(write_u32(file, value.to_bits()));
+    }
+    Ok(())
+}
+
+// checks the length first, a damaged length doesn't allocate more than the file holds
+fn read_u32s(bytes: &mut &[u8], length: usize) -> Result<Vec<u32>, io::Error> {
+    if length > bytes.len() / 4 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the scene cache is cut short"));
+    }
+    (0..length).map(|_| read_u32(bytes)).collect()
+}
+
+fn read_f32s(bytes: &mut &[u8], length: usize) -> Result<Vec<f32>, io::Error> {
+    Ok(try!(read_u32s(bytes, length)).into_iter().map(f32::from_bits).collect())
+}
+
+#[test]
+fn scene_cache_round_trip() {
+    use std::f32;
+    use primitive::Primitive;
+    use ray::Ray;
+    let mut geometry = Geometry::new();
+    let material = geometry.add_material(Material::Dielectric {
+        refraction_index_n1: 1.0,
+        refraction_index_n2: 1.5,
+        color: Vector3::new(0.9, 0.9, 0.9),
+        dispersion: ::spectrum::BK7,
+    });
+    geometry.add_mesh(TriangleMesh {
+        positions: vec![Point3::new(-1., -1., 2.), Point3::new(1., -1., 2.), Point3::new(1., 1., 2.), Point3::new(-1., 1., 2.)],
+        normals: Vec::new(),
+        uvs: vec![Point2::new(0., 0.), Point2::new(1., 0.), Point2::new(1., 1.), Point2::new(0., 1.)],
+        indices: vec![[0, 1, 2], [0, 2, 3]],
+        material: material,
+        object_id: 0,
+    });
+    geometry.add_triangles(&[Triangle::light(Point3::new(-1., 2., 0.), Point3::new(1., 2., 0.), Point3::new(0., 2., 1.),
+                                             Vector3::new(0., -1., 0.), Vector3::new(0., -1., 0.), Vector3::new(0., -1., 0.))]);
+    let geometry = Arc::new(geometry);
+    let bvh = BVH::new(Geometry::triangles(&geometry));
+    let mut bytes = Vec::new();
+    encode(&mut bytes, 1, 2, &geometry, &bvh).unwrap();
+
+    let cached = decode(&bytes, 1, 2).expect("read the cache back");
+    assert_eq!(cached.objects().len(), 3);
+    assert_eq!(format!("{:?}", cached.objects()[0].geometry), format!("{:?}", geometry));
+    assert_eq!(format!("{:?}", cached.parts()), format!("{:?}", bvh.parts()));
+    let mut ray = Ray::new(Point3::new(-0.5, 0.5, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(cached.intersect_closest(&mut ray).is_some() && (ray.distance - 2.).abs() < 1e-5);
+    assert!(cached.random_light().map(|(_, light)| light.is_light().is_some()).unwrap_or(false));
+
+    // another source, other settings, a cut off or damaged file are all rejected
+    assert!(decode(&bytes, 3, 2).is_err());
+    assert!(decode(&bytes, 1, 3).is_err());
+    assert!(decode(&bytes[..bytes.len() - 1], 1, 2).is_err());
+    let mut damaged = bytes.clone();
+    let length = damaged.len();
+    damaged[length - 8] = 0xff;
+    assert!(decode(&damaged, 1, 2).is_err());
+}
+
+#[test]
+fn material_libraries_of_obj() {
+    let directory = ::std::env::temp_dir().join("material_libraries_of_obj");
+    fs::create_dir_all(&directory).unwrap();
+    let obj = directory.join("model.obj");
+    File::create(&obj).unwrap().write_all(b"mtllib model.mtl missing.mtl\nv 0 0 0\n").unwrap();
+    File::create(directory.join("model.mtl")).unwrap().write_all(b"newmtl red\nKd 1 0 0\n").unwrap();
+    // the missing library is left out, tobj can't load it either
+    assert_eq!(material_libraries(&obj).unwrap(), vec![directory.join("model.mtl")]);
+}
@@ -1,21 +1,25 @@
 extern crate cgmath;
 extern crate rand;
 extern crate sdl2;
-use self::cgmath::{Vector3, Point3, Array};
+use self::cgmath::{Vector3, Point3};
 use self::cgmath::InnerSpace;
-use self::cgmath::ElementWise;
-use super::ray::{Ray, Intersection};
+use super::ray::Ray;
 use std::f32;
 use std::collections::HashSet;
 use self::sdl2::keyboard::Keycode;
 use self::rand::Closed01;
+use random::random;
 
 use scene::Scene;
 use material::Material;
 use primitive::Primitive;
+use integrator::{reflect, refract, schlick};
+use filter::{Filter, FilterSampler};
+use projection::Projection;
+use aperture::Aperture;
 
 #[derive(Debug)]
-pub struct Camera<T: Primitive> {
+pub struct Camera {
     origin: Point3<f32>,
     target: Point3<f32>,
     focal_distance: f32,
@@ -30,68 +34,47 @@ pub struct Camera<T: Primitive> {
     right: Vector3<f32>,
     width: usize,
     height: usize,
-    lens_size: f32,
-    scene: Scene<T>,
-
-    depth: u32,
-}
-
-fn refract(direction: &Vector3<f32>, normal: &Vector3<f32>, n1: f32, n2: f32) -> Option<Vector3<f32>> {
-    // Refract
-    let div = n1 / n2;
-    let cosi = direction.dot(*normal);
-    let sin_t2 = div * div * (1. - cosi * cosi);
-    if sin_t2 <= 1. {
-        Some(div * direction - (div * cosi + (1. - sin_t2).sqrt()) * normal)
-    } else {
-        None
-    }
-}
-fn reflect(direction: &Vector3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
-    // Reflect
-    direction - 2. * direction.dot(*normal) * normal
-}
-fn schlick(direction: &Vector3<f32>, normal: &Vector3<f32>, n1: f32, n2: f32) -> f32 {
-    // Schlick
-    let div = (n1 - n2) / (n1 + n2);
-    let r0 = div * div;
-    let cosi = -direction.dot(*normal); // ray direction is towards normal invert answer
-    r0 + (1. - r0) * (1. - cosi).powi(5)
-}
-fn diffuse(normal: &Vector3<f32>) -> Vector3<f32> {
-    // Diffuse
-    let Closed01(r0) = rand::random::<Closed01<f32>>();
-    let r = (1. - r0 * r0).sqrt();
-    let Closed01(r1) = rand::random::<Closed01<f32>>();
-    let phi = 2. * f32::consts::PI * r1;
-    let diffuse_dir = Vector3::new(phi.cos() * r, phi.sin() * r, r0);
-    if diffuse_dir.dot(*normal) < 0. {
-        -1. * diffuse_dir
-    } else {
-        diffuse_dir
-    }
-}
-fn cosine_weighted_diffuse(normal: &Vector3<f32>) -> Vector3<f32> {
-    // Cosine weighted Diffuse
-    let Closed01(r0) = rand::random::<Closed01<f32>>();
-    let r = r0.sqrt();
-    let Closed01(r1) = rand::random::<Closed01<f32>>();
-    let phi = 2. * f32::consts::PI * r1;
-    from_tangent_to_local(normal, &Vector3::new(phi.cos() * r, phi.sin() * r, (1. - r0).sqrt()))
-}
-fn from_tangent_to_local(normal: &Vector3<f32>, tangent: &Vector3<f32>) -> Vector3<f32> {
-    let t = (normal.cross(if normal.x.abs() > 0.99 { Vector3::new(0.0,1.0,0.0) } else { Vector3::new(1.0,0.0,0.0) })).normalize();
-    let b = normal.cross(t);
-    tangent.x * t + tangent.y * b + tangent.z * normal
+    // width of the sensor and focal length in millimetres, together they give the field of view
+    pub sensor_width: f32,
+    pub focal_length: f32,
+    // focal length over the diameter of the aperture
+    pub f_number: f32,
+    // distance to the plane in focus, focuses on what is in the centre of the view when None
+    pub focus_distance: Option<f32>,
+    pub aperture: Aperture,
+    // Offset of the front of the lens barrel towards the image corners, relative to the aperture radius.
+    // The aperture gets cut off towards the corners, giving cat's eye bokeh and vignetting. 0 disables it.
+    pub cat_eye: f32,
+    // the shutter is open from and to these times in the frame, the motion of the scene goes from 0 to 1
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    // how far the camera moves from the start to the end of the frame
+    pub motion: Vector3<f32>,
+    // how far the target moves over the frame, the camera turns to follow it
+    pub target_motion: Vector3<f32>,
+    // distributes the camera rays around the pixel centres
+    pub filter: FilterSampler,
+    pub projection: Projection,
 }
 
-impl<T: Primitive> Camera<T> {
-    pub fn new(width: usize, height: usize, scene: Scene<T>) -> Camera<T> {
+impl Camera {
+    pub fn new<T: Primitive>(width: usize, height: usize, scene: &Scene<T>) -> Camera {
         let mut camera = Camera {
             width: width,
             height: height,
-            depth: 512,
-            lens_size: 0.04,
+            // a full frame sensor with the field of view of the old fixed screen plane
+            sensor_width: 36.0,
+            focal_length: 27.0,
+            f_number: 2.8,
+            focus_distance: None,
+            aperture: Aperture::Circular,
+            cat_eye: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            motion: Vector3::new(0.0, 0.0, 0.0),
+            target_motion: Vector3::new(0.0, 0.0, 0.0),
+            filter: FilterSampler::new(Filter::Box),
+            projection: Projection::Perspective,
             origin: Point3::new(-1.6, 0.0, -1.3),//normal
             //origin: Point3::new(-0.94, -0.037, -3.342),//normal
             //origin: Point3::new(150.94, 150.037, -3.342),//rungholt
@@ -105,13 +88,12 @@ impl<T: Primitive> Camera<T> {
             p3: Point3::new(0.0, 0.0, 0.0),
             right: Vector3::new(0.0, 0.0, 0.0),
             up: Vector3::new(0.0, 0.0, 0.0),
-            scene: scene,
         };
-        camera.update();
+        camera.update(scene);
         camera
     }
 
-    pub fn handle_input(&mut self, key_presses: &HashSet<Keycode>) -> bool {
+    pub fn handle_input<T: Primitive>(&mut self, key_presses: &HashSet<Keycode>, scene: &Scene<T>) -> bool {
         self.target = self.origin + self.direction;
 
         let changed = if key_presses.contains(&Keycode::A) {
@@ -148,13 +130,6 @@ impl<T: Primitive> Camera<T> {
         } else {
             changed
         };
-        let changed = if key_presses.contains(&Keycode::H) {
-            self.depth = if self.depth == 512 { 2 } else { 512 };
-            println!("depth: {:?}", 2);
-            true
-        } else {
-            changed
-        };
         if key_presses.contains(&Keycode::P) {
             println!("origin: {:?}, direction: {:?}", self.origin, self.direction);
         }
@@ -203,7 +178,7 @@ impl<T: Primitive> Camera<T> {
             changed
         };
         if changed {
-            self.update();
+            self.update(scene);
             true
         } else {
             false
@@ -211,9 +186,9 @@ impl<T: Primitive> Camera<T> {
 
     }
 
-    fn focus(&self, mut ray : &mut Ray, distance: f32, depth: u8) -> f32 {
+    fn focus<T: Primitive>(&self, scene: &Scene<T>, mut ray : &mut Ray, distance: f32, depth: u8) -> f32 {
         if depth == 0 { return distance }
-        match self.scene.bvh.intersect_closest(&mut ray) {
+        match scene.bvh.intersect_closest(&mut ray) {
             Some(ref intersection) => {
                 let distance = distance + ray.distance;
                 match intersection.material {
@@ -221,7 +196,7 @@ impl<T: Primitive> Camera<T> {
                         let reflect = reflect(&ray.direction, &intersection.normal);
                         let intersection_point = ray.intersection();
                         ray.reset(intersection_point, reflect, f32::INFINITY);
-                        return self.focus(ray, distance, depth - 1);
+                        return self.focus(scene, ray, distance, depth - 1);
                     },
                     &Material::Dielectric{refraction_index_n1, refraction_index_n2, ..} => {
                         let normal = intersection.normal;
@@ -259,7 +234,13 @@ impl<T: Primitive> Camera<T> {
                             };
                             ray.reset(intersection_point, reflected_dir, f32::INFINITY);
                         }
-                        return self.focus(ray, distance, depth - 1);
+                        return self.focus(scene, ray, distance, depth - 1);
+                    },
+                    &Material::Volume{..} => {
+                        let intersection_point = ray.intersection();
+                        let direction = ray.direction;
+                        ray.reset(intersection_point, direction, f32::INFINITY);
+                        return self.focus(scene, ray, distance, depth - 1);
                     },
                     _ => return if distance == 0. { f32::INFINITY } else { distance },
                 }
@@ -268,7 +249,15 @@ impl<T: Primitive> Camera<T> {
         }
 
     }
-    fn update(&mut self) {
+    /// moves the camera to `origin` looking at `target`
+    pub fn look_at<T: Primitive>(&mut self, origin: Point3<f32>, target: Point3<f32>, scene: &Scene<T>) {
+        self.origin = origin;
+        self.target = target;
+        self.update(scene);
+    }
+
+    /// places the screen plane, call it after changing the lens or the focus
+    pub fn update<T: Primitive>(&mut self, scene: &Scene<T>) {
         self.direction = (self.target - self.origin).normalize();
         let unit_y = Vector3::new(0.0, 1.0, 0.0);
         self.right = unit_y.cross(self.direction);
@@ -278,173 +267,106 @@ impl<T: Primitive> Camera<T> {
 
         let aspect_ratio = (self.width as f32) / (self.height as f32);
 
-        self.focal_distance = f32::min(20.0, self.focus(&mut ray, 0.0, 5));
+        self.focal_distance = match self.focus_distance {
+            Some(distance) => distance,
+            None => f32::min(20.0, self.focus(scene, &mut ray, 0.0, 5)),
+        };
 
         let c = self.origin + self.focal_distance * self.direction;
+        // the sensor has the aspect ratio of the film, its size over the focal length is the size of the screen plane at distance 1
+        let half_width = 0.5 * self.focal_distance * self.sensor_width / self.focal_length;
+        let half_height = half_width / aspect_ratio;
 
-        self.p1 = c + (-0.5 * self.focal_distance * aspect_ratio * self.right) +
-                  (0.5 * self.focal_distance * self.up);
-        self.p2 = c + (0.5 * self.focal_distance * aspect_ratio * self.right) +
-                  (0.5 * self.focal_distance * self.up);
-        self.p3 = c + (-0.5 * self.focal_distance * aspect_ratio * self.right) +
-                  (-0.5 * self.focal_distance * self.up);
+        self.p1 = c + (-half_width * self.right) + (half_height * self.up);
+        self.p2 = c + (half_width * self.right) + (half_height * self.up);
+        self.p3 = c + (-half_width * self.right) + (-half_height * self.up);
 
     }
 
-    /// sample a ray by shooting it through the scene
-    pub fn sample(&self, ray: &mut Ray, depth: u32) -> Vector3<f32> {
-        let mut accumalated_color = Vector3::new(0.,0.,0.);
-        let mut transport = Vector3::new(1., 1., 1.);
-        let mut already_intersected = None;
-        for _ in 0..depth {
-            match if let Some(intersection) = already_intersected { already_intersected = None; intersection } else { self.scene.bvh.intersect_closest(ray) } {
-                None => {
-                    accumalated_color += transport.mul_element_wise(0.1 * self.scene.sample_skybox(ray.direction));;
-                    break;
-                },
-                Some(Intersection{normal, inside, area:_, material}) => {
-                    let intersection_point = ray.intersection();
-                    match material {
-                        &Material::Emissive { color } => {
-                            accumalated_color += transport.mul_element_wise(color);
-                            break;
-                        },
-                        &Material::Diffuse { speculaty, color} => {
-                            if inside { break };
-                            if let Some((nr_ligths, random_light)) = self.scene.bvh.random_light() {
-                                let point_on_light = random_light.random_point();
-                                let light_dir = (point_on_light - intersection_point).normalize();
-                                let mut god_ray = Ray::new(intersection_point + 20. * f32::EPSILON * light_dir, light_dir, f32::INFINITY);
-                                if let Some(intersection_on_light) = random_light.intersect(&mut god_ray) {
-                                    let cos_intersection = normal.dot(light_dir);
-                                    let cos_light = -intersection_on_light.normal.dot(light_dir);
-                                    if cos_intersection > 0.0 && cos_light > 0.0 {
-                                        // light is not behind surface point, trace shadow ray
-                                        god_ray.distance -= f32::EPSILON; // ray should not hit the light in the intersect_any test
-                                        if let None = self.scene.bvh.intersect_any(&mut god_ray) {
-                                            let brdf = f32::consts::FRAC_1_PI * color;
-                                            let area = random_light.area();
-                                            let light_color = random_light.is_light().unwrap(); // we selected a light
-                                            let solid_angle = (cos_light * area) / (god_ray.distance * god_ray.distance);
-                                            let light_pdf = 1.0 / solid_angle;
-                                            let hemisphere_pdf = f32::consts::FRAC_1_PI * cos_intersection;
-                                            let multiple_important_sampling_pdf = light_pdf + hemisphere_pdf;
-                                            // the estimated times this light gets sampled is 1 / nr_ligths, so we multiply this sample by nr_ligths
-                                            let nee_estimate = (1. - speculaty).min(0.0) * nr_ligths as f32 * transport.mul_element_wise((cos_intersection / multiple_important_sampling_pdf) * light_color.mul_element_wise(brdf));
-                                            accumalated_color += nee_estimate;
-                                        }
-                                    }
-                                }
-                            }
-
-                            let Closed01(r0) = rand::random::<Closed01<f32>>();
-                            if r0 < speculaty {
-                                // Specular sampling
-                                let reflected_dir = reflect(&ray.direction, &normal);
-                                transport = transport.mul_element_wise(color);
-                                ray.reset(intersection_point, reflected_dir, f32::INFINITY);
-                            } else {
-                                // russian_roulette only rays on a diffuse surface which already sent their nex_event_estimation ray
-                                let Closed01(russian_roulette) = rand::random::<Closed01<f32>>();
-                                let survival = transport.max().max(0.1); //minimum of 0.1 chance to survive and maximum of transport
-                                if russian_roulette < survival {
-                                    transport /= survival;
-                                } else {
-                                    break;
-                                }
-                                // Diffuse sampling
-                                let diffuse_dir = cosine_weighted_diffuse(&normal);
-                                ray.reset(intersection_point, diffuse_dir, f32::INFINITY);
-                                let cos_intersection = diffuse_dir.dot(normal);
-                                let brdf = f32::consts::FRAC_1_PI * color;
-                                let hemisphere_pdf = f32::consts::FRAC_1_PI * cos_intersection;
-                                let light_pdf = {
-                                    let already_intersected = Some(self.scene.bvh.intersect_closest(ray));
-                                    if let Some(Some(Intersection{normal, inside:_, area, material: &Material::Emissive { color:_ }})) = already_intersected {
-                                        let cos_light = -normal.dot(ray.direction);
-                                        let solid_angle = (cos_light * area) / (ray.distance * ray.distance);
-                                        1.0 / solid_angle
-                                    } else {
-                                        0.0
-                                    }
-                                };
-                                let multiple_important_sampling_pdf = light_pdf + hemisphere_pdf;
-                                transport = transport.mul_element_wise((cos_intersection / multiple_important_sampling_pdf) * brdf);
-                            }
-                        }
-                        &Material::Dielectric { refraction_index_n1, refraction_index_n2, color } => {
-                            if inside {
-                                let absorbance = (Vector3::new(-1.,-1.,-1.) + color) * ray.distance;
-                                let transparency = Vector3::new(absorbance.x.exp(), absorbance.y.exp(), absorbance.z.exp());
-                                transport = transport.mul_element_wise(transparency);
-                            }
-                            let refracted_dir = if inside {
-                                    refract(&ray.direction, &-normal, refraction_index_n2, refraction_index_n1)
-                                } else {
-                                    refract(&ray.direction, &normal, refraction_index_n1, refraction_index_n2)
-                            };
-                            if let Some(refracted_dir) = refracted_dir {
-                                let schlick_reflection = if inside {
-                                        schlick(&ray.direction, &-normal, refraction_index_n2, refraction_index_n1)
-                                    } else {
-                                        schlick(&ray.direction, &normal, refraction_index_n1, refraction_index_n2)
-                                };
-                                let Closed01(r0) = rand::random::<Closed01<f32>>();
-                                if r0 < schlick_reflection {
-                                    // Reflected ray
-                                    if !inside {
-                                        transport = transport.mul_element_wise(color);
-                                    }
-                                    let reflected_dir = if inside {
-                                            reflect(&ray.direction, &-normal)
-                                        } else {
-                                            reflect(&ray.direction, &normal)
-                                    };
-                                    ray.reset(intersection_point, reflected_dir, f32::INFINITY);
-                                } else {
-                                    // Refraction sampling
-                                    ray.reset(intersection_point, refracted_dir, f32::INFINITY);
-                                }
-                            } else {
-                                // Full internal reflection
-                                let reflected_dir = if inside {
-                                        reflect(&ray.direction, &-normal)
-                                    } else {
-                                        reflect(&ray.direction, &normal)
-                                };
-                                ray.reset(intersection_point, reflected_dir, f32::INFINITY);
-                            }
-                        }
-                    }
-                }
-            };
-        }
-        accumalated_color
+    /// radius of the aperture in scene units, which are metres. The default lens at f/2.8 is
+    /// about an eighth of the old fixed radius of 0.04, f/0.34 matches it
+    pub fn aperture_radius(&self) -> f32 {
+        0.5 * self.focal_length / self.f_number / 1000.
     }
 
-    /// generates a nice Ray (TODO better integer type)
-    pub fn generate(&self, x: usize, y: usize) -> Ray {
+
+    /// generates a nice Ray (TODO better integer type), and its reconstruction filter weight
+    pub fn generate(&self, x: usize, y: usize) -> (Ray, f32) {
         // NOTE: we do not have to keep track of a
         // pool of random number generators, each
         // thread in rust has its own random
         // number generator by default :)
-        let Closed01(r0) = rand::random::<Closed01<f32>>();
-        let Closed01(r1) = rand::random::<Closed01<f32>>();
-        let Closed01(r2) = rand::random::<Closed01<f32>>();
-        let r2 = r2 - 0.5;
-        let Closed01(r3) = rand::random::<Closed01<f32>>();
-        let r3 = r3 - 0.5;
+        let (r0, r1, weight) = self.filter.sample();
+        let Closed01(r2) = random::<Closed01<f32>>();
+        let time = self.shutter_open + r2 * (self.shutter_close - self.shutter_open);
 
         // calculate sub-pixel ray target position on screen plane
-        let u = ((x as f32) + r0) / (self.width as f32);
-        let v = ((y as f32) + r1) / (self.height as f32);
+        let u = ((x as f32) + 0.5 + r0) / (self.width as f32);
+        let v = ((y as f32) + 0.5 + r1) / (self.height as f32);
+        if self.projection != Projection::Perspective {
+            // the other projections have no lens
+            let aspect_ratio = (self.width as f32) / (self.height as f32);
+            let to_world = |v: Vector3<f32>| v.x * self.right + v.y * self.up + v.z * self.direction;
+            // the offsets are relative to the height of the screen plane
+            let screen_height = (self.p1 - self.p3).magnitude();
+            return match self.projection.map(u, v, aspect_ratio) {
+                Some((offset, direction)) => (self.moved(self.origin + screen_height * to_world(offset), to_world(direction).normalize(), time), weight),
+                // outside of the image, black
+                None => (Ray::new(self.origin, self.direction, f32::INFINITY), 0.),
+            };
+        }
         let target = self.p1 + u * (self.p2 - self.p1) + v * (self.p3 - self.p1);
-        let origin = self.origin + self.lens_size * (r2 * self.right + r3 * self.up);
+        let (lens_x, lens_y) = self.aperture.sample();
+        if self.cat_eye > 0. {
+            // the opening of the barrel is the aperture circle, shifted with the position on the sensor
+            let aspect_ratio = (self.width as f32) / (self.height as f32);
+            let corner = (aspect_ratio * aspect_ratio + 1.).sqrt();
+            let (sx, sy) = (self.cat_eye * (2. * u - 1.) * aspect_ratio / corner, self.cat_eye * (1. - 2. * v) / corner);
+            if (lens_x - sx) * (lens_x - sx) + (lens_y - sy) * (lens_y - sy) > 1. {
+                return (Ray::new(self.origin, self.direction, f32::INFINITY), 0.);
+            }
+        }
+        let origin = self.origin + self.aperture_radius() * (lens_x * self.right + lens_y * self.up);
         let direction = (target - origin).normalize();
 
         // hmm all directions are the same. that seems to be a bug =)
 
-        Ray::new(origin, direction, f32::INFINITY)
+        (self.moved(origin, direction, time), weight)
 
     }
+
+    /// a ray of the camera at the start of the frame, moved and turned along with the camera to `time`
+    fn moved(&self, origin: Point3<f32>, direction: Vector3<f32>, time: f32) -> Ray {
+        let displacement = time * self.motion;
+        if self.target_motion == Vector3::new(0.0, 0.0, 0.0) {
+            return Ray::new(origin + displacement, direction, f32::INFINITY).with_time(time);
+        }
+        // the rotation between the orthonormal frames of the camera at the start and at `time`
+        let frame = |direction: Vector3<f32>| {
+            let right = Vector3::new(0.0, 1.0, 0.0).cross(direction).normalize();
+            (right, direction.cross(right), direction)
+        };
+        let (right, up, forward) = frame(self.direction);
+        let (right_t, up_t, forward_t) = frame((self.target + time * self.target_motion - (self.origin + displacement)).normalize());
+        let turn = |v: Vector3<f32>| v.dot(right) * right_t + v.dot(up) * up_t + v.dot(forward) * forward_t;
+        Ray::new(self.origin + displacement + turn(origin - self.origin), turn(direction), f32::INFINITY).with_time(time)
+    }
+}
+
+#[test]
+fn camera_turns_with_target() {
+    use primitive::sphere::Sphere;
+    let scene = Scene::without_skybox(vec![Sphere::light(Point3::new(0.0, 0.0, 5.0), 1.0)]);
+    let mut camera = Camera::new(4, 4, &scene);
+    camera.focus_distance = Some(1.0);
+    camera.look_at(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 1.0), &scene);
+    camera.motion = Vector3::new(1.0, 0.0, 0.0);
+    camera.target_motion = Vector3::new(-1.0, 0.0, 0.0);
+    // the centre ray follows the moving target, halfway through the frame it looks straight at it
+    let ray = camera.moved(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0), 0.5);
+    assert!((ray.origin - Point3::new(0.5, 0.0, 0.0)).magnitude() < 1e-5);
+    assert!((ray.direction - (Point3::new(-0.5, 0.0, 1.0) - ray.origin).normalize()).magnitude() < 1e-5);
+    // a ray through the top of the lens stays at the top
+    let ray = camera.moved(Point3::new(0.0, 0.1, 0.0), Vector3::new(0.0, 0.0, 1.0), 0.5);
+    assert!((ray.origin.y - 0.1).abs() < 1e-5);
 }
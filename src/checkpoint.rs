@@ -0,0 +1,241 @@
+extern crate cgmath;
+use self::cgmath::Vector3;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::fs;
+use std::fs::File;
+use std::hash::Hasher;
+use std::path::PathBuf;
+
+use film::{Film, Sample, BUCKETS};
+
+const MAGIC: &'static [u8; 4] = b"PTCK";
+const VERSION: u32 = 2;
+// floats stored per pixel, seven colour layers, depth and the statistics, and the median of means buckets
+const FLOATS_PER_PIXEL: usize = 7 * 3 + 4 + 3 * BUCKETS;
+
+/// The state of a progressive render besides the film. Every pass reseeds the random
+/// generators from the seed and its number, so the seed and the number of passes are the
+/// position of the generators, and a resumed render takes the samples it would have taken
+/// without the interruption.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub passes: u32,
+    pub seed: u64,
+    pub camera_hash: u64,
+    pub scene_hash: u64,
+    pub integrator_hash: u64,
+}
+
+impl Checkpoint {
+    /// Writes the header and every layer of the film as raw little endian floats. The file is
+    /// written next to the path and renamed over it, an interrupted save keeps the old checkpoint.
+    pub fn save(&self, path: &str, film: &Film) -> Result<(), io::Error> {
+        let temporary = format!("{}.tmp", path);
+        {
+            let mut file = BufWriter::new(try!(File::create(&temporary)));
+            try!(file.write_all(MAGIC));
+            for value in [VERSION, film.width as u32, film.height as u32, self.passes].iter() {
+                try!(write_u32(&mut file, *value));
+            }
+            for value in [self.seed, self.camera_hash, self.scene_hash, self.integrator_hash].iter() {
+                try!(write_u64(&mut file, *value));
+            }
+            for pixel in &film.pixels {
+                for value in to_floats(pixel) {
+                    try!(write_u32(&mut file, value.to_bits()));
+                }
+            }
+            try!(file.flush());
+        }
+        fs::rename(&temporary, path)
+    }
+
+    /// Reads a checkpoint into the film and returns it. Refuses to resume, and leaves the
+    /// film alone, when the checkpoint was made with a different film size, camera, scene or
+    /// integrator than the ones of this checkpoint.
+    pub fn resume(&self, path: &str, film: &mut Film) -> Result<Checkpoint, io::Error> {
+        let mut file = BufReader::new(try!(File::open(path)));
+        let mut magic = [0; 4];
+        try!(file.read_exact(&mut magic));
+        if &magic != MAGIC || try!(read_u32(&mut file)) != VERSION {
+            return Err(invalid("not a checkpoint of this version"));
+        }
+        let (width, height) = (try!(read_u32(&mut file)) as usize, try!(read_u32(&mut file)) as usize);
+        let passes = try!(read_u32(&mut file));
+        let seed = try!(read_u64(&mut file));
+        let camera_hash = try!(read_u64(&mut file));
+        let scene_hash = try!(read_u64(&mut file));
+        let integrator_hash = try!(read_u64(&mut file));
+        if (width, height) != (film.width, film.height) {
+            return Err(invalid(&format!("checkpoint is {}x{}, the film {}x{}", width, height, film.width, film.height)));
+        }
+        if camera_hash != self.camera_hash {
+            return Err(invalid("checkpoint was made with a different camera"));
+        }
+        if scene_hash != self.scene_hash {
+            return Err(invalid("checkpoint was made with a different scene"));
+        }
+        if integrator_hash != self.integrator_hash {
+            return Err(invalid("checkpoint was made with different integrator settings"));
+        }
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut floats = [0.; FLOATS_PER_PIXEL];
+        for _ in 0..width * height {
+            for value in floats.iter_mut() {
+                *value = f32::from_bits(try!(read_u32(&mut file)));
+            }
+            pixels.push(from_floats(&floats));
+        }
+        film.pixels = pixels;
+        Ok(Checkpoint {
+            passes: passes,
+            seed: seed,
+            camera_hash: camera_hash,
+            scene_hash: scene_hash,
+            integrator_hash: integrator_hash,
+        })
+    }
+}
+
+/// FNV-1a hash of the debug representation, which holds every field.
+/// Unlike the hasher of the standard library it is the same for every build.
+pub fn hash<D: fmt::Debug + ?Sized>(value: &D) -> u64 {
+    let mut hasher = Fnv::new();
+    fmt::write(&mut hasher, format_args!("{:?}", value)).expect("hash");
+    hasher.finish()
+}
+
+/// hash of the paths and the contents of the files a scene is made from
+pub fn hash_files(paths: &[PathBuf]) -> Result<u64, io::Error> {
+    let mut hasher = Fnv::new();
+    let mut buffer = vec![0; 1 << 16];
+    for path in paths {
+        hasher.write(path.to_string_lossy().as_bytes());
+        let mut file = try!(File::open(path));
+        loop {
+            let read = try!(file.read(&mut buffer));
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buffer[..read]);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// FNV-1a, a hash that stays the same between builds and versions of Rust
+pub struct Fnv(u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Write for Fnv {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_u32<W: Write>(file: &mut W, value: u32) -> Result<(), io::Error> {
+    file.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+pub fn read_u32<R: Read>(file: &mut R) -> Result<u32, io::Error> {
+    let mut bytes = [0; 4];
+    try!(file.read_exact(&mut bytes));
+    Ok(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+}
+
+// low half first
+pub fn write_u64<W: Write>(file: &mut W, value: u64) -> Result<(), io::Error> {
+    try!(write_u32(file, value as u32));
+    write_u32(file, (value >> 32) as u32)
+}
+
+pub fn read_u64<R: Read>(file: &mut R) -> Result<u64, io::Error> {
+    Ok(try!(read_u32(file)) as u64 | (try!(read_u32(file)) as u64) << 32)
+}
+
+fn to_floats(pixel: &Sample) -> Vec<f32> {
+    let mut floats = Vec::with_capacity(FLOATS_PER_PIXEL);
+    for v in [pixel.beauty, pixel.albedo, pixel.normal, pixel.position, pixel.object_id, pixel.direct, pixel.indirect].iter() {
+        floats.extend_from_slice(&[v.x, v.y, v.z]);
+    }
+    floats.extend_from_slice(&[pixel.depth, pixel.samples, pixel.luminance_mean, pixel.luminance_m2]);
+    for v in pixel.buckets.iter() {
+        floats.extend_from_slice(&[v.x, v.y, v.z]);
+    }
+    floats
+}
+
+fn from_floats(floats: &[f32]) -> Sample {
+    let vector = |offset: usize| Vector3::new(floats[offset], floats[offset + 1], floats[offset + 2]);
+    let mut pixel = Sample::new();
+    pixel.beauty = vector(0);
+    pixel.albedo = vector(3);
+    pixel.normal = vector(6);
+    pixel.position = vector(9);
+    pixel.object_id = vector(12);
+    pixel.direct = vector(15);
+    pixel.indirect = vector(18);
+    pixel.depth = floats[21];
+    pixel.samples = floats[22];
+    pixel.luminance_mean = floats[23];
+    pixel.luminance_m2 = floats[24];
+    for (bucket, v) in pixel.buckets.iter_mut().enumerate() {
+        *v = vector(25 + 3 * bucket);
+    }
+    pixel
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    let path = ::std::env::temp_dir().join("checkpoint_round_trip.ptck");
+    let path = path.to_str().unwrap();
+    let mut film = Film::new(2, 2);
+    let mut sample = Sample::new();
+    sample.samples = 1.;
+    sample.beauty = Vector3::new(1., 2., 3.);
+    sample.depth = 4.;
+    film.pixels[1].add(&sample);
+    film.pixels[1].add(&sample);
+    let checkpoint = Checkpoint { passes: 2, seed: 1 << 40 | 7, camera_hash: hash(&"camera"), scene_hash: hash(&"scene"), integrator_hash: hash(&"integrator") };
+    checkpoint.save(path, &film).unwrap();
+    // the temporary file got renamed into place
+    assert!(!::std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+    let mut resumed = Film::new(2, 2);
+    assert_eq!(checkpoint.resume(path, &mut resumed).unwrap(), checkpoint);
+    assert_eq!(resumed.pixels[1].samples, 2.);
+    assert_eq!(resumed.pixels[1].buckets[1], Vector3::new(1., 2., 3.));
+    assert_eq!(resumed.pixels[1].mean(::film::Aov::Depth), film.pixels[1].mean(::film::Aov::Depth));
+    // a different scene is refused and leaves the film empty
+    let other = Checkpoint { scene_hash: hash(&"other scene"), ..checkpoint };
+    let mut refused = Film::new(2, 2);
+    assert!(other.resume(path, &mut refused).is_err());
+    assert_eq!(refused.pixels[1].samples, 0.);
+    // and so are other integrator settings
+    let other = Checkpoint { integrator_hash: hash(&"other integrator"), ..checkpoint };
+    assert!(other.resume(path, &mut refused).is_err());
+}
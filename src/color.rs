@@ -0,0 +1,205 @@
+extern crate cgmath;
+use self::cgmath::Vector3;
+
+// colour temperature of the sRGB white point
+pub const NEUTRAL_TEMPERATURE: f32 = 6504.0;
+
+// row major 3x3 matrices
+type Matrix = [[f32; 3]; 3];
+
+const SRGB_TO_XYZ: Matrix = [[0.4124564, 0.3575761, 0.1804375],
+                             [0.2126729, 0.7151522, 0.0721750],
+                             [0.0193339, 0.1191920, 0.9503041]];
+const XYZ_TO_SRGB: Matrix = [[ 3.2404542, -1.5371385, -0.4985314],
+                             [-0.9692660,  1.8760108,  0.0415560],
+                             [ 0.0556434, -0.2040259,  1.0572252]];
+// cone responses for chromatic adaptation
+const BRADFORD: Matrix = [[ 0.8951,  0.2664, -0.1614],
+                          [-0.7502,  1.7135,  0.0367],
+                          [ 0.0389, -0.0685,  1.0296]];
+const BRADFORD_INVERSE: Matrix = [[ 0.9869929, -0.1470543, 0.1599627],
+                                  [ 0.4323053,  0.5183603, 0.0492912],
+                                  [-0.0085287,  0.0400428, 0.9684867]];
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+const ACES_INPUT: Matrix = [[0.59719, 0.35458, 0.04823],
+                            [0.07600, 0.90834, 0.01566],
+                            [0.02840, 0.13383, 0.83777]];
+const ACES_OUTPUT: Matrix = [[ 1.60475, -0.53108, -0.07367],
+                             [-0.10208,  1.10813, -0.00605],
+                             [-0.00327, -0.07276,  1.07602]];
+// inset and outset of the AgX base look
+const AGX_INSET: Matrix = [[0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+                           [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+                           [0.0423756549057051, 0.0784336, 0.879142973793104]];
+const AGX_OUTSET: Matrix = [[1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+                            [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+                            [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]];
+// range of exposures AgX maps to the display, in stops around middle grey
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapper {
+    Linear,
+    Reinhard,
+    Aces,
+    Agx,
+}
+
+pub const TONE_MAPPERS: [ToneMapper; 4] = [ToneMapper::Linear, ToneMapper::Reinhard, ToneMapper::Aces, ToneMapper::Agx];
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        TONE_MAPPERS.iter().find(|tone_mapper| tone_mapper.name() == name).cloned()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ToneMapper::Linear => "linear",
+            ToneMapper::Reinhard => "reinhard",
+            ToneMapper::Aces => "aces",
+            ToneMapper::Agx => "agx",
+        }
+    }
+
+    /// maps linear scene colours to linear display colours in `[0, 1]`
+    pub fn map(&self, color: Vector3<f32>) -> Vector3<f32> {
+        let mapped = match *self {
+            ToneMapper::Linear => color,
+            ToneMapper::Reinhard => Vector3::new(color.x / (1. + color.x), color.y / (1. + color.y), color.z / (1. + color.z)),
+            ToneMapper::Aces => {
+                let v = transform(&ACES_INPUT, color);
+                let fit = |v: f32| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+                transform(&ACES_OUTPUT, Vector3::new(fit(v.x), fit(v.y), fit(v.z)))
+            },
+            ToneMapper::Agx => {
+                let v = transform(&AGX_INSET, color);
+                let encode = |v: f32| (v.max(1e-10).log2().max(AGX_MIN_EV).min(AGX_MAX_EV) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+                // polynomial fit of the default contrast curve, its result is display encoded
+                let contrast = |x: f32| {
+                    let x2 = x * x;
+                    let x4 = x2 * x2;
+                    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+                };
+                let v = transform(&AGX_OUTSET, Vector3::new(contrast(encode(v.x)), contrast(encode(v.y)), contrast(encode(v.z))));
+                let linearize = |v: f32| v.max(0.).powf(2.2);
+                Vector3::new(linearize(v.x), linearize(v.y), linearize(v.z))
+            },
+        };
+        Vector3::new(mapped.x.max(0.).min(1.), mapped.y.max(0.).min(1.), mapped.z.max(0.).min(1.))
+    }
+}
+
+/// Turns linear radiance into display pixels: exposure, white balance,
+/// tone mapping and the sRGB transfer function, in that order.
+#[derive(Debug, Copy, Clone)]
+pub struct ColorPipeline {
+    // in stops, every stop doubles the brightness
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+    // colour temperature in Kelvin that becomes white
+    white_balance: f32,
+    white_balance_matrix: Matrix,
+}
+
+impl ColorPipeline {
+    pub fn new() -> ColorPipeline {
+        let mut pipeline = ColorPipeline {
+            exposure: 1.0,
+            tone_mapper: ToneMapper::Aces,
+            white_balance: NEUTRAL_TEMPERATURE,
+            white_balance_matrix: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+        };
+        pipeline.set_white_balance(NEUTRAL_TEMPERATURE);
+        pipeline
+    }
+
+    pub fn white_balance(&self) -> f32 {
+        self.white_balance
+    }
+
+    /// Adapts the white of a black body at the given temperature to the sRGB white
+    /// with the Bradford transform, lower temperatures make the image cooler.
+    pub fn set_white_balance(&mut self, temperature: f32) {
+        let temperature = temperature.max(1667.).min(25000.);
+        let source = transform(&BRADFORD, planckian_white(temperature));
+        let target = transform(&BRADFORD, planckian_white(NEUTRAL_TEMPERATURE));
+        let scale = [[target.x / source.x, 0., 0.], [0., target.y / source.y, 0.], [0., 0., target.z / source.z]];
+        self.white_balance = temperature;
+        self.white_balance_matrix = multiply(&XYZ_TO_SRGB, &multiply(&BRADFORD_INVERSE, &multiply(&scale, &multiply(&BRADFORD, &SRGB_TO_XYZ))));
+    }
+
+    /// the display colour of linear radiance, still linear
+    pub fn display(&self, radiance: Vector3<f32>) -> Vector3<f32> {
+        let exposed = 2f32.powf(self.exposure) * radiance;
+        self.tone_mapper.map(transform(&self.white_balance_matrix, exposed))
+    }
+
+    /// the 8 bit sRGB pixel of linear radiance
+    pub fn apply(&self, radiance: Vector3<f32>) -> Vector3<u8> {
+        let display = self.display(radiance);
+        let encode = |value: f32| (255. * srgb_oetf(value) + 0.5) as u8;
+        Vector3::new(encode(display.x), encode(display.y), encode(display.z))
+    }
+}
+
+/// the sRGB transfer function from linear light to encoded values, both in `[0, 1]`
+pub fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// XYZ of a black body with a luminance of 1, with Kim et al.'s fit of the Planckian locus
+fn planckian_white(temperature: f32) -> Vector3<f32> {
+    let t = temperature;
+    let x = if t < 4000. {
+        -0.2661239e9 / (t * t * t) - 0.2343589e6 / (t * t) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / (t * t * t) + 2.1070379e6 / (t * t) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t < 2222. {
+        -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+    } else if t < 4000. {
+        -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x * x * x - 5.87338670 * x * x + 3.75112997 * x - 0.37001483
+    };
+    Vector3::new(x / y, 1., (1. - x - y) / y)
+}
+
+fn transform(matrix: &Matrix, v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(matrix[0][0] * v.x + matrix[0][1] * v.y + matrix[0][2] * v.z,
+                 matrix[1][0] * v.x + matrix[1][1] * v.y + matrix[1][2] * v.z,
+                 matrix[2][0] * v.x + matrix[2][1] * v.y + matrix[2][2] * v.z)
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            product[i][j] = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+    }
+    product
+}
+
+#[test]
+fn color_pipeline_neutral() {
+    let mut pipeline = ColorPipeline::new();
+    pipeline.exposure = 0.;
+    pipeline.tone_mapper = ToneMapper::Linear;
+    // middle of the sRGB curve, and clamped highlights
+    assert_eq!(pipeline.apply(Vector3::new(0.5, 0.5, 0.5)), Vector3::new(188, 188, 188));
+    assert_eq!(pipeline.apply(Vector3::new(2.0, 0.0, 1.0)), Vector3::new(255, 0, 255));
+    // a warm light balanced as white makes grey bluish
+    pipeline.set_white_balance(3000.);
+    let grey = pipeline.apply(Vector3::new(0.2, 0.2, 0.2));
+    assert!(grey.z > grey.x);
+    // every tone mapper keeps the order of brightness
+    for tone_mapper in TONE_MAPPERS.iter() {
+        assert!(tone_mapper.map(Vector3::new(0.2, 0.2, 0.2)).x < tone_mapper.map(Vector3::new(0.8, 0.8, 0.8)).x);
+    }
+}
@@ -0,0 +1,181 @@
+extern crate cgmath;
+extern crate scoped_threadpool;
+use self::cgmath::{Vector3, InnerSpace, ElementWise};
+use self::scoped_threadpool::Pool;
+
+use film::{Film, Aov, luminance};
+
+// B3 spline of the à-trous wavelet transform, indexed by the distance to the centre tap
+const KERNEL: [f32; 3] = [3. / 8., 1. / 4., 1. / 16.];
+// keeps black albedo from dividing by zero
+const MIN_ALBEDO: f32 = 0.01;
+
+/// Edge-avoiding à-trous wavelet filter, as in spatiotemporal variance-guided filtering
+/// without the temporal part. The illumination is filtered with a 5x5 kernel whose taps
+/// get further apart every iteration. Taps are weighted down when the normal, the plane
+/// or the luminance differs, the luminance by how many standard deviations of noise apart.
+#[derive(Debug, Copy, Clone)]
+pub struct Denoiser {
+    pub iterations: u32,
+    // number of standard deviations of noise two luminances may differ
+    pub sigma_color: f32,
+    // exponent on the cosine between two normals
+    pub sigma_normal: f32,
+    // distance to the tangent plane relative to the depth
+    pub sigma_depth: f32,
+}
+
+// first hit properties of the film that guide the filter
+struct Guide {
+    width: usize,
+    height: usize,
+    normal: Vec<Vector3<f32>>,
+    position: Vec<Vector3<f32>>,
+    depth: Vec<f32>,
+}
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.05,
+        }
+    }
+
+    /// Filters the beauty of the film, the film itself is not changed.
+    /// The albedo of the first hit is divided out before filtering and multiplied
+    /// back in afterwards, so textures and material edges stay sharp.
+    pub fn denoise(&self, film: &Film, pool: &mut Pool) -> Vec<Vector3<f32>> {
+        let guide = Guide {
+            width: film.width,
+            height: film.height,
+            normal: film.layer(Aov::Normal).into_iter().map(|normal| if normal.magnitude2() > 0. { normal.normalize() } else { normal }).collect(),
+            position: film.layer(Aov::Position),
+            depth: film.layer(Aov::Depth).into_iter().map(|depth| depth.x).collect(),
+        };
+        let albedo: Vec<_> = film.layer(Aov::Albedo).into_iter()
+            .map(|albedo| Vector3::new(albedo.x.max(MIN_ALBEDO), albedo.y.max(MIN_ALBEDO), albedo.z.max(MIN_ALBEDO)))
+            .collect();
+        let mut color: Vec<_> = film.layer(Aov::Beauty).into_iter().zip(albedo.iter())
+            .map(|(beauty, albedo)| beauty.div_element_wise(*albedo))
+            .collect();
+        let mut variance: Vec<_> = albedo.iter().enumerate()
+            .map(|(idx, albedo)| film.variance(idx) / (luminance(*albedo) * luminance(*albedo)))
+            .collect();
+
+        let rows = (film.height + pool.thread_count() as usize - 1) / pool.thread_count() as usize;
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let mut filtered_color = vec![Vector3::new(0., 0., 0.); color.len()];
+            let mut filtered_variance = vec![0.; variance.len()];
+            {
+                let guide = &guide;
+                let color = &color;
+                let variance = &variance;
+                pool.scoped(|scope| {
+                    let chunks = filtered_color.chunks_mut(film.width * rows).zip(filtered_variance.chunks_mut(film.width * rows));
+                    for (chunk_id, (color_chunk, variance_chunk)) in chunks.enumerate() {
+                        scope.execute(move||{
+                            for idx in 0..color_chunk.len() {
+                                let (filtered, filtered_variance) = self.filter(chunk_id * rows * guide.width + idx, step, guide, color, variance);
+                                color_chunk[idx] = filtered;
+                                variance_chunk[idx] = filtered_variance;
+                            }
+                        });
+                    }
+                });
+            }
+            color = filtered_color;
+            variance = filtered_variance;
+        }
+
+        color.into_iter().zip(albedo.iter()).map(|(color, albedo)| color.mul_element_wise(*albedo)).collect()
+    }
+
+    // one à-trous iteration for a single pixel, returns the filtered colour and variance
+    fn filter(&self, p: usize, step: i32, guide: &Guide, color: &[Vector3<f32>], variance: &[f32]) -> (Vector3<f32>, f32) {
+        let (x, y) = ((p % guide.width) as i32, (p / guide.width) as i32);
+        // the variance of a single pixel is noisy itself, blur it over a 3x3 neighbourhood
+        let mut local_variance = 0.;
+        let mut taps = 0.;
+        for dy in -1..2 {
+            for dx in -1..2 {
+                if let Some(q) = guide.index(x + dx, y + dy) {
+                    local_variance += variance[q];
+                    taps += 1.;
+                }
+            }
+        }
+        let sigma_luminance = self.sigma_color * (local_variance / taps).sqrt() + 1e-4;
+        let luminance_p = luminance(color[p]);
+
+        let mut sum_color = Vector3::new(0., 0., 0.);
+        let mut sum_variance = 0.;
+        let mut sum_weight = 0.;
+        for dy in -2..3 {
+            for dx in -2..3 {
+                let q = match guide.index(x + dx * step, y + dy * step) {
+                    Some(q) => q,
+                    None => continue,
+                };
+                let kernel = KERNEL[dx.abs() as usize] * KERNEL[dy.abs() as usize];
+                let weight_color = (-(luminance_p - luminance(color[q])).abs() / sigma_luminance).exp();
+                let weight = kernel * weight_color * self.geometry_weight(p, q, guide);
+                sum_color += weight * color[q];
+                sum_variance += weight * weight * variance[q];
+                sum_weight += weight;
+            }
+        }
+        // the centre tap always has a weight, so the sum is never 0
+        (sum_color / sum_weight, sum_variance / (sum_weight * sum_weight))
+    }
+
+    fn geometry_weight(&self, p: usize, q: usize, guide: &Guide) -> f32 {
+        // the ray of a pixel without a depth hit nothing
+        match (guide.depth[p] > 0., guide.depth[q] > 0.) {
+            (false, false) => 1.,
+            (true, true) => {
+                let weight_normal = guide.normal[p].dot(guide.normal[q]).max(0.).powf(self.sigma_normal);
+                let plane_distance = guide.normal[p].dot(guide.position[q] - guide.position[p]).abs();
+                let weight_depth = (-plane_distance / (self.sigma_depth * guide.depth[p])).exp();
+                weight_normal * weight_depth
+            },
+            _ => 0.,
+        }
+    }
+}
+
+impl Guide {
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            None
+        } else {
+            Some(x as usize + y as usize * self.width)
+        }
+    }
+}
+
+#[test]
+fn denoise_flat_noise() {
+    use film::Sample;
+    // a flat grey wall with noisy radiance converges towards the mean
+    let mut film = Film::new(32, 32);
+    for (idx, pixel) in film.pixels.iter_mut().enumerate() {
+        for s in 0..2 {
+            let noise = if (idx * 7 + s * 13) % 3 == 0 { 0.4 } else { -0.2 };
+            let mut sample = Sample::new();
+            sample.beauty = Vector3::new(0.5 + noise, 0.5 + noise, 0.5 + noise);
+            sample.samples = 1.;
+            sample.luminance_mean = luminance(sample.beauty);
+            sample.albedo = Vector3::new(1., 1., 1.);
+            sample.normal = Vector3::new(0., 0., 1.);
+            sample.depth = 1.;
+            pixel.add(&sample);
+        }
+    }
+    let error = |image: &[Vector3<f32>]| image.iter().map(|color| (color.x - 0.5).abs()).sum::<f32>() / image.len() as f32;
+    let denoised = Denoiser::new().denoise(&film, &mut Pool::new(2));
+    assert!(error(&denoised) < 0.5 * error(&film.layer(Aov::Beauty)));
+}
@@ -0,0 +1,315 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, EuclideanSpace};
+use std::io;
+use std::io::{Write, BufWriter};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+use ray::Ray;
+use scene::Scene;
+use primitive::Primitive;
+use integrator::Integrator;
+use color::ColorPipeline;
+
+// number of means the median of means takes the median of, odd so there is a middle one
+pub const BUCKETS: usize = 7;
+
+/// Arbitrary output variables, the layers of the film
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    Beauty,
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    ObjectId,
+    Direct,
+    Indirect,
+}
+
+pub const AOVS: [Aov; 8] = [Aov::Beauty, Aov::Albedo, Aov::Normal, Aov::Depth,
+                            Aov::Position, Aov::ObjectId, Aov::Direct, Aov::Indirect];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Aov::Beauty => "beauty",
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+}
+
+/// What a camera ray sees, the radiance and the properties of its first hit.
+/// A pixel of the film is the sum of all its samples, together with their
+/// number and the running mean and variance of their luminance. The beauty
+/// of the samples is also dealt round robin into buckets for the median of means.
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    pub beauty: Vector3<f32>,
+    pub albedo: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    // distance to the first hit, 0 when the ray hits nothing
+    pub depth: f32,
+    pub position: Vector3<f32>,
+    // colour of the object, so antialiased edges blend like a matte
+    pub object_id: Vector3<f32>,
+    pub direct: Vector3<f32>,
+    pub indirect: Vector3<f32>,
+    pub samples: f32,
+    pub luminance_mean: f32,
+    // sum of squared differences to the mean luminance
+    pub luminance_m2: f32,
+    // sums of the beauty of every BUCKETS-th sample
+    pub buckets: [Vector3<f32>; BUCKETS],
+}
+
+impl Sample {
+    pub fn new() -> Sample {
+        Sample {
+            beauty: Vector3::new(0., 0., 0.),
+            albedo: Vector3::new(0., 0., 0.),
+            normal: Vector3::new(0., 0., 0.),
+            depth: 0.,
+            position: Vector3::new(0., 0., 0.),
+            object_id: Vector3::new(0., 0., 0.),
+            direct: Vector3::new(0., 0., 0.),
+            indirect: Vector3::new(0., 0., 0.),
+            samples: 0.,
+            luminance_mean: 0.,
+            luminance_m2: 0.,
+            buckets: [Vector3::new(0., 0., 0.); BUCKETS],
+        }
+    }
+
+    /// Traces a camera ray, the feature layers come from the first hit the integrator found.
+    /// The radiance layers are scaled by the weight of the reconstruction filter. The feature
+    /// layers are not, they are plain averages so negative filter lobes cannot corrupt them.
+    pub fn trace<T: Primitive>(integrator: &Integrator<T>, scene: &Scene<T>, ray: &mut Ray, weight: f32) -> Sample {
+        let mut sample = Sample::new();
+        let (first_hit, direct, indirect) = integrator.radiance_first_hit(scene, ray);
+        if let Some(first_hit) = first_hit {
+            sample.albedo = first_hit.albedo;
+            sample.normal = first_hit.normal;
+            sample.depth = first_hit.distance;
+            sample.position = first_hit.position.to_vec();
+            sample.object_id = object_color(first_hit.object_id);
+        }
+        sample.beauty = weight * (direct + indirect);
+        sample.direct = weight * direct;
+        sample.indirect = weight * indirect;
+        sample.samples = 1.;
+        sample.luminance_mean = luminance(sample.beauty);
+        sample
+    }
+
+    pub fn add(&mut self, sample: &Sample) {
+        self.beauty += sample.beauty;
+        self.albedo += sample.albedo;
+        self.normal += sample.normal;
+        self.depth += sample.depth;
+        self.position += sample.position;
+        self.object_id += sample.object_id;
+        self.direct += sample.direct;
+        self.indirect += sample.indirect;
+        if sample.samples == 1. {
+            self.buckets[self.samples as usize % BUCKETS] += sample.beauty;
+        } else {
+            for (bucket, other) in self.buckets.iter_mut().zip(sample.buckets.iter()) {
+                *bucket += *other;
+            }
+        }
+        // combine the mean and variance of both, Chan et al.
+        let samples = self.samples + sample.samples;
+        if samples > 0. {
+            let delta = sample.luminance_mean - self.luminance_mean;
+            self.luminance_mean += delta * sample.samples / samples;
+            self.luminance_m2 += sample.luminance_m2 + delta * delta * self.samples * sample.samples / samples;
+        }
+        self.samples = samples;
+    }
+
+    /// the average of a layer over the samples
+    pub fn mean(&self, aov: Aov) -> Vector3<f32> {
+        if self.samples > 0. { self.aov(aov) / self.samples } else { Vector3::new(0., 0., 0.) }
+    }
+
+    /// the mean of a layer, or the median of means for the beauty when asked for
+    pub fn estimate(&self, aov: Aov, median_of_means: bool) -> Vector3<f32> {
+        if aov == Aov::Beauty && median_of_means { self.median_of_means() } else { self.mean(aov) }
+    }
+
+    /// Median of means of the beauty, an outlier that lands in one bucket only moves the
+    /// estimate to a neighbouring mean. Biased towards the common case, so fireflies and
+    /// rare bright paths like caustics get darker. The mean until every bucket has a sample.
+    pub fn median_of_means(&self) -> Vector3<f32> {
+        let samples = self.samples as usize;
+        if samples < BUCKETS {
+            return self.mean(Aov::Beauty);
+        }
+        // a NaN or infinite sample spoils its bucket, the median is taken over the others
+        let mut means: Vec<_> = self.buckets.iter().enumerate()
+            .map(|(bucket, sum)| sum / ((samples - bucket + BUCKETS - 1) / BUCKETS) as f32)
+            .filter(|mean| luminance(*mean).is_finite())
+            .collect();
+        if means.is_empty() {
+            return self.mean(Aov::Beauty);
+        }
+        means.sort_by(|a, b| luminance(*a).partial_cmp(&luminance(*b)).unwrap());
+        means[means.len() / 2]
+    }
+
+    /// variance of the mean luminance, 0 until there are two samples
+    pub fn variance(&self) -> f32 {
+        if self.samples < 2. {
+            return 0.;
+        }
+        self.luminance_m2 / (self.samples - 1.) / self.samples
+    }
+
+    pub fn aov(&self, aov: Aov) -> Vector3<f32> {
+        match aov {
+            Aov::Beauty => self.beauty,
+            Aov::Albedo => self.albedo,
+            Aov::Normal => self.normal,
+            Aov::Depth => Vector3::new(self.depth, self.depth, self.depth),
+            Aov::Position => self.position,
+            Aov::ObjectId => self.object_id,
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+        }
+    }
+}
+
+/// The accumulated samples of all pixels, every layer of a pixel has the same sample count
+#[derive(Debug)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Sample>,
+    // estimate the beauty with the median of means instead of the mean
+    pub median_of_means: bool,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film {
+            width: width,
+            height: height,
+            pixels: vec![Sample::new(); width * height],
+            median_of_means: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in &mut self.pixels[..] {
+            *pixel = Sample::new();
+        }
+    }
+
+    /// the average of a layer over the samples of every pixel
+    pub fn layer(&self, aov: Aov) -> Vec<Vector3<f32>> {
+        self.pixels.iter().map(|pixel| pixel.estimate(aov, self.median_of_means)).collect()
+    }
+
+    pub fn variance(&self, idx: usize) -> f32 {
+        self.pixels[idx].variance()
+    }
+
+    /// Writes the beauty through the colour pipeline as `<output>.ppm` and every layer in full precision
+    /// as `<output>.<aov>.pfm`, the sample counts and luminance variance of
+    /// the pixels go to `<output>.samples.pfm` and `<output>.variance.pfm`.
+    pub fn write(&self, output: &str, pipeline: &ColorPipeline) -> Result<(), io::Error> {
+        try!(write_ppm(&format!("{}.ppm", output), self.width, self.height, &self.layer(Aov::Beauty), pipeline));
+        for aov in AOVS.iter() {
+            try!(write_pfm(&format!("{}.{}.pfm", output, aov.name()), self.width, self.height, &self.layer(*aov)));
+        }
+        let samples: Vec<_> = self.pixels.iter().map(|pixel| Vector3::new(pixel.samples, pixel.samples, pixel.samples)).collect();
+        try!(write_pfm(&format!("{}.samples.pfm", output), self.width, self.height, &samples));
+        let variance: Vec<_> = self.pixels.iter().map(|pixel| Vector3::new(pixel.variance(), pixel.variance(), pixel.variance())).collect();
+        try!(write_pfm(&format!("{}.variance.pfm", output), self.width, self.height, &variance));
+        Ok(())
+    }
+}
+
+/// relative luminance of a linear sRGB colour
+pub fn luminance(color: Vector3<f32>) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// a random but fixed colour for every object id
+pub fn object_color(object_id: u32) -> Vector3<f32> {
+    let mut hasher = DefaultHasher::new();
+    object_id.hash(&mut hasher);
+    let hash = hasher.finish();
+    Vector3::new((hash & 0xff) as f32 / 255.,
+                 ((hash >> 8) & 0xff) as f32 / 255.,
+                 ((hash >> 16) & 0xff) as f32 / 255.)
+}
+
+/// binary 8 bit sRGB image
+pub fn write_ppm(path: &str, width: usize, height: usize, pixels: &[Vector3<f32>], pipeline: &ColorPipeline) -> Result<(), io::Error> {
+    let mut file = BufWriter::new(try!(File::create(path)));
+    try!(write!(file, "P6\n{} {}\n255\n", width, height));
+    for pixel in pixels {
+        let rgb = pipeline.apply(*pixel);
+        try!(file.write_all(&[rgb.x, rgb.y, rgb.z]));
+    }
+    Ok(())
+}
+
+/// linear floating point image, little endian with the rows from bottom to top
+pub fn write_pfm(path: &str, width: usize, height: usize, pixels: &[Vector3<f32>]) -> Result<(), io::Error> {
+    let mut file = BufWriter::new(try!(File::create(path)));
+    try!(write!(file, "PF\n{} {}\n-1.0\n", width, height));
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            for value in [pixel.x, pixel.y, pixel.z].iter() {
+                let bits = value.to_bits();
+                try!(file.write_all(&[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8]));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn film_layer_average() {
+    let mut film = Film::new(2, 1);
+    let mut sample = Sample::new();
+    sample.samples = 1.;
+    sample.depth = 2.;
+    sample.direct = Vector3::new(1., 0., 0.);
+    film.pixels[0].add(&sample);
+    sample.depth = 4.;
+    film.pixels[0].add(&sample);
+    let depth = film.layer(Aov::Depth);
+    assert_eq!(depth[0], Vector3::new(3., 3., 3.));
+    assert_eq!(depth[1], Vector3::new(0., 0., 0.));
+    assert_eq!(film.layer(Aov::Direct)[0], Vector3::new(1., 0., 0.));
+}
+
+#[test]
+fn median_of_means_rejects_outlier() {
+    let mut pixel = Sample::new();
+    let mut sample = Sample::new();
+    sample.samples = 1.;
+    for i in 0..7 * BUCKETS {
+        sample.beauty = if i == 3 { Vector3::new(1000., 1000., 1000.) } else { Vector3::new(1., 1., 1.) };
+        pixel.add(&sample);
+    }
+    // the firefly dominates the mean but not the median of the bucket means
+    assert!(pixel.mean(Aov::Beauty).x > 10.);
+    assert_eq!(pixel.median_of_means(), Vector3::new(1., 1., 1.));
+
+    // a NaN sample spoils a bucket, which is left out of the median
+    sample.beauty = Vector3::new(f32::NAN, f32::NAN, f32::NAN);
+    pixel.add(&sample);
+    assert_eq!(pixel.median_of_means(), Vector3::new(1., 1., 1.));
+}
@@ -0,0 +1,159 @@
+extern crate rand;
+use self::rand::Closed01;
+use random::random;
+use std::f32;
+
+// number of bins of the tabulated filter per dimension
+const TABLE_SIZE: usize = 64;
+
+/// Pixel reconstruction filters, the 2D filter is the product of the 1D filter in x and y
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    MitchellNetravali,
+    Lanczos,
+}
+
+pub const FILTERS: [Filter; 5] = [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::MitchellNetravali, Filter::Lanczos];
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        FILTERS.iter().find(|filter| filter.name() == name).cloned()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::MitchellNetravali => "mitchell",
+            Filter::Lanczos => "lanczos",
+        }
+    }
+
+    /// half of the width of the filter in pixels
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::MitchellNetravali => 2.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    /// the 1D filter at a distance in pixels from the pixel centre
+    pub fn evaluate(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.;
+        }
+        match *self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - x,
+            Filter::Gaussian => {
+                // shifted down so it reaches 0 at the radius
+                let alpha = 2.;
+                (-alpha * x * x).exp() - (-alpha * self.radius() * self.radius()).exp()
+            },
+            Filter::MitchellNetravali => {
+                // the recommended B = C = 1/3
+                let (b, c) = (1. / 3., 1. / 3.);
+                if x < 1. {
+                    ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)) / 6.
+                } else {
+                    ((-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)) / 6.
+                }
+            },
+            Filter::Lanczos => sinc(x) * sinc(x / self.radius()),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        1.
+    } else {
+        let x = f32::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+/// Filter importance sampling. Instead of weighting samples by the filter, the offsets
+/// of the camera rays are distributed like the absolute value of the filter. What is left
+/// is a weight of about 1, negative for the negative lobes of Mitchell-Netravali and Lanczos.
+#[derive(Debug, Clone)]
+pub struct FilterSampler {
+    pub filter: Filter,
+    // absolute value of the filter at the bin centres
+    table: Vec<f32>,
+    // running sum over the table
+    cdf: Vec<f32>,
+    // integral of the filter over its integral of the table
+    scale: f32,
+}
+
+impl FilterSampler {
+    pub fn new(filter: Filter) -> FilterSampler {
+        let radius = filter.radius();
+        let bin = 2. * radius / TABLE_SIZE as f32;
+        let table: Vec<_> = (0..TABLE_SIZE).map(|i| filter.evaluate(-radius + (i as f32 + 0.5) * bin).abs()).collect();
+        let mut cdf = Vec::with_capacity(TABLE_SIZE + 1);
+        cdf.push(0.);
+        for i in 0..TABLE_SIZE {
+            let sum = cdf[i] + table[i];
+            cdf.push(sum);
+        }
+        let steps = 16 * TABLE_SIZE;
+        let integral: f32 = (0..steps).map(|i| filter.evaluate(-radius + (i as f32 + 0.5) * 2. * radius / steps as f32)).sum::<f32>() * 2. * radius / steps as f32;
+        FilterSampler {
+            filter: filter,
+            scale: cdf[TABLE_SIZE] * bin / integral,
+            table: table,
+            cdf: cdf,
+        }
+    }
+
+    /// offset from the pixel centre in x and y, and the weight of the sample
+    pub fn sample(&self) -> (f32, f32, f32) {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let (x, weight_x) = self.sample_1d(r0);
+        let (y, weight_y) = self.sample_1d(r1);
+        (x, y, weight_x * weight_y)
+    }
+
+    /// maps a uniform number to an offset distributed like the table, with its weight
+    pub fn sample_1d(&self, u: f32) -> (f32, f32) {
+        let target = u * self.cdf[TABLE_SIZE];
+        // the last bin with a running sum below the target
+        let i = match self.cdf.binary_search_by(|sum| sum.partial_cmp(&target).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }.min(TABLE_SIZE - 1);
+        let t = if self.table[i] > 0. { ((target - self.cdf[i]) / self.table[i]).max(0.).min(1.) } else { 0.5 };
+        let radius = self.filter.radius();
+        let x = -radius + (i as f32 + t) * 2. * radius / TABLE_SIZE as f32;
+        // f(x) / pdf(x), normalised by the integral of the filter
+        let weight = if self.table[i] > 0. { self.scale * self.filter.evaluate(x) / self.table[i] } else { 0. };
+        (x, weight)
+    }
+}
+
+#[test]
+fn filter_importance_sampling_weights() {
+    // the weighted samples of every filter integrate a constant to itself
+    for filter in FILTERS.iter() {
+        let sampler = FilterSampler::new(*filter);
+        let steps = 10000;
+        let mut sum = 0.;
+        for i in 0..steps {
+            let (x, weight) = sampler.sample_1d((i as f32 + 0.5) / steps as f32);
+            assert!(x.abs() <= filter.radius());
+            sum += weight;
+        }
+        assert!((sum / steps as f32 - 1.).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,195 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, Quaternion, Matrix, Matrix4, SquareMatrix, One, InnerSpace};
+use self::cgmath::Transform as TransformTrait;
+use std::sync::Arc;
+
+use bvh::BVH;
+use material::Material;
+use primitive::Primitive;
+use primitive::aabb::AABB;
+use primitive::instance::Instance;
+use primitive::triangle::Triangle;
+
+/// A node of the scene graph, its transform places its mesh and its children inside its parent
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    // hidden nodes leave out their children too
+    pub visible: bool,
+    // replaces the materials of the meshes of this node and its children
+    pub material: Option<Material>,
+    // index in the meshes of the graph
+    pub mesh: Option<usize>,
+    pub children: Vec<Node>,
+}
+
+/// Named nodes with transforms over meshes that are shared between nodes.
+/// The graph is flattened or instanced into a BVH when the scene is built.
+#[derive(Debug)]
+pub struct SceneGraph {
+    pub meshes: Vec<Vec<Triangle>>,
+    pub root: Node,
+}
+
+impl Node {
+    pub fn new(name: &str) -> Node {
+        Node {
+            name: name.to_string(),
+            translation: Vector3::new(0., 0., 0.),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1., 1., 1.),
+            visible: true,
+            material: None,
+            mesh: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_mesh(self, mesh: usize) -> Node {
+        Node { mesh: Some(mesh), ..self }
+    }
+
+    pub fn with_translation(self, translation: Vector3<f32>) -> Node {
+        Node { translation: translation, ..self }
+    }
+
+    pub fn with_rotation(self, rotation: Quaternion<f32>) -> Node {
+        Node { rotation: rotation, ..self }
+    }
+
+    pub fn with_scale(self, scale: Vector3<f32>) -> Node {
+        Node { scale: scale, ..self }
+    }
+
+    pub fn with_material(self, material: Material) -> Node {
+        Node { material: Some(material), ..self }
+    }
+
+    pub fn with_child(mut self, child: Node) -> Node {
+        self.children.push(child);
+        self
+    }
+
+    /// scale, then rotation, then translation, relative to the parent
+    pub fn transform(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation) * Matrix4::from(self.rotation) * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// the first node with the name, depth first
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Node> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter_mut().filter_map(|child| child.find_mut(name)).next()
+    }
+
+    // calls `place` with the world transform and material of every visible node with a mesh
+    fn visit<F: FnMut(&Node, Matrix4<f32>, Option<Material>)>(&self, parent: Matrix4<f32>, material: Option<Material>, place: &mut F) {
+        if !self.visible {
+            return;
+        }
+        let transform = parent * self.transform();
+        let material = self.material.or(material);
+        if self.mesh.is_some() {
+            place(self, transform, material);
+        }
+        for child in &self.children {
+            child.visit(transform, material, place);
+        }
+    }
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph {
+            meshes: Vec::new(),
+            root: Node::new("root"),
+        }
+    }
+
+    /// adds a mesh for nodes to refer to by the returned index
+    pub fn add_mesh(&mut self, triangles: Vec<Triangle>) -> usize {
+        self.meshes.push(triangles);
+        self.meshes.len() - 1
+    }
+
+    /// bounds of a mesh before any transform, to place it in the scene
+    pub fn mesh_bounds(&self, mesh: usize) -> AABB {
+        self.meshes[mesh].iter().fold(AABB::new(), |sum, triangle| sum.combine(&triangle.bounds()))
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.root.find_mut(name)
+    }
+
+    /// copies of the triangles of every visible node, transformed into the world
+    pub fn flatten(&self) -> Vec<Triangle> {
+        let mut triangles = Vec::new();
+        self.root.visit(Matrix4::identity(), None, &mut |node, transform, material| {
+            // normals go through the inverse transpose, a mirroring transform turns the triangles around
+            let normal_matrix = transform.invert().map_or(transform, |inverse| inverse.transpose());
+            let mirrored = transform.determinant() < 0.;
+            let normal = |normal| normal_matrix.transform_vector(normal).normalize();
+            for triangle in &self.meshes[node.mesh.unwrap()] {
+                let (position1, position2, normal1, normal2) = if mirrored {
+                    (triangle.position2, triangle.position1, triangle.normal2, triangle.normal1)
+                } else {
+                    (triangle.position1, triangle.position2, triangle.normal1, triangle.normal2)
+                };
+                triangles.push(Triangle {
+                    position0: transform.transform_point(triangle.position0),
+                    position1: transform.transform_point(position1),
+                    position2: transform.transform_point(position2),
+                    normal0: normal(triangle.normal0),
+                    normal1: normal(normal1),
+                    normal2: normal(normal2),
+                    material: material.unwrap_or(triangle.material),
+                    object_id: triangle.object_id,
+                });
+            }
+        });
+        triangles
+    }
+
+    /// an instance for every visible node, every mesh gets a single BVH
+    pub fn instances(&self) -> Vec<Instance<Triangle>> {
+        let mut bvhs: Vec<Option<Arc<BVH<Triangle>>>> = vec![None; self.meshes.len()];
+        let mut instances = Vec::new();
+        self.root.visit(Matrix4::identity(), None, &mut |node, transform, material| {
+            let mesh = node.mesh.unwrap();
+            let bvh = bvhs[mesh].get_or_insert_with(|| Arc::new(BVH::new(self.meshes[mesh].clone()))).clone();
+            if let Some(instance) = Instance::new(bvh, transform) {
+                instances.push(match material {
+                    Some(material) => instance.with_material(material),
+                    None => instance,
+                });
+            }
+        });
+        instances
+    }
+}
+
+#[test]
+fn graph_transforms_children() {
+    use self::cgmath::{Point3, Rotation3, Rad};
+    use std::f32;
+    let mut graph = SceneGraph::new();
+    let mesh = graph.add_mesh(vec![Triangle::light(Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(0., 0., 1.),
+                                                   Vector3::new(0., 1., 0.), Vector3::new(0., 1., 0.), Vector3::new(0., 1., 0.))]);
+    let diffuse = Material::Diffuse { speculaty: 0., color: Vector3::new(0.5, 0.5, 0.5) };
+    graph.root = graph.root.clone()
+        .with_child(Node::new("parent").with_translation(Vector3::new(0., 1., 0.)).with_material(diffuse)
+            .with_child(Node::new("child").with_mesh(mesh).with_rotation(Quaternion::from_angle_z(Rad(f32::consts::PI)))))
+        .with_child(Node::new("hidden").with_mesh(mesh));
+    graph.find_mut("hidden").unwrap().visible = false;
+    let triangles = graph.flatten();
+    assert_eq!(triangles.len(), 1);
+    // turned upside down under the parent, which is one up and overrides the material
+    assert!((triangles[0].position1 - Point3::new(-1., 1., 0.)).magnitude() < 1e-5);
+    assert!((triangles[0].normal0 - Vector3::new(0., -1., 0.)).magnitude() < 1e-5);
+    assert!(triangles[0].is_light().is_none());
+    assert_eq!(graph.instances().len(), 1);
+}
@@ -0,0 +1,312 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, InnerSpace, ElementWise};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+// the lobes are R, TT, TRT and the rest together
+const LOBES: usize = 4;
+
+/// Hair fibres after Chiang et al. 2016, a rough dielectric cylinder with tilted scales on
+/// its surface that light reflects off (R), passes through (TT) or reflects inside of (TRT),
+/// absorbed by the pigment on the way through.
+#[derive(Debug, Copy, Clone)]
+pub struct Hair {
+    // per unit of the fibre diameter
+    pub absorption: Vector3<f32>,
+    // widths of the lobes along and around the fibre, between 0 and 1
+    pub longitudinal_roughness: f32,
+    pub azimuthal_roughness: f32,
+    // tilt of the scales in radians, about 2 degrees for human hair
+    pub scale_angle: f32,
+    pub refraction_index: f32,
+}
+
+impl Hair {
+    /// absorption by the concentrations of the dark eumelanin and the red pheomelanin,
+    /// 8 is black, 1.3 brown and 0.3 blond with little pheomelanin
+    pub fn from_melanin(eumelanin: f32, pheomelanin: f32) -> Hair {
+        Hair {
+            absorption: eumelanin * Vector3::new(0.419, 0.697, 1.37) + pheomelanin * Vector3::new(0.187, 0.4, 1.05),
+            longitudinal_roughness: 0.3,
+            azimuthal_roughness: 0.3,
+            scale_angle: 2f32.to_radians(),
+            refraction_index: 1.55,
+        }
+    }
+}
+
+/// The scattering at a point on a fibre, with the tangent along it, the normal towards the
+/// ray and the offset `h` of the point across the width, -1 to 1.
+pub struct HairBsdf {
+    hair: Hair,
+    h: f32,
+    gamma_o: f32,
+    // variance of the longitudinal lobes and scale of the azimuthal logistic
+    v: [f32; LOBES],
+    s: f32,
+    // the scale angle rotated by 2^k for the lobes
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+    tangent: Vector3<f32>,
+    bitangent: Vector3<f32>,
+    normal: Vector3<f32>,
+}
+
+impl HairBsdf {
+    pub fn new(hair: Hair, h: f32, tangent: Vector3<f32>, normal: Vector3<f32>) -> HairBsdf {
+        let beta_m = hair.longitudinal_roughness;
+        let beta_n = hair.azimuthal_roughness;
+        let v0 = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        let mut sin_2k_alpha = [hair.scale_angle.sin(), 0., 0.];
+        let mut cos_2k_alpha = [safe_sqrt(1. - sin_2k_alpha[0] * sin_2k_alpha[0]), 0., 0.];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2. * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1] * cos_2k_alpha[i - 1] - sin_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+        }
+        // the normal across the fibre, it may have been bent for shading
+        let normal = (normal - normal.dot(tangent) * tangent).normalize();
+        let h = h.max(-1.).min(1.);
+        HairBsdf {
+            hair: hair,
+            h: h,
+            gamma_o: safe_asin(h),
+            v: [v0, 0.25 * v0, 4. * v0, 4. * v0],
+            s: (f32::consts::PI / 8.).sqrt() * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22)),
+            sin_2k_alpha: sin_2k_alpha,
+            cos_2k_alpha: cos_2k_alpha,
+            tangent: tangent,
+            bitangent: normal.cross(tangent),
+            normal: normal,
+        }
+    }
+
+    /// the sine and cosine of the angle with the normal plane and the angle around the fibre
+    fn angles(&self, w: Vector3<f32>) -> (f32, f32, f32) {
+        let sin_theta = w.dot(self.tangent).max(-1.).min(1.);
+        (sin_theta, safe_sqrt(1. - sin_theta * sin_theta), w.dot(self.normal).atan2(w.dot(self.bitangent)))
+    }
+
+    /// the angle of the refracted ray around the fibre and the transmittance through it once
+    fn transmittance(&self, sin_theta_o: f32, cos_theta_o: f32) -> (f32, Vector3<f32>) {
+        let eta = self.hair.refraction_index;
+        let sin_theta_t = sin_theta_o / eta;
+        let cos_theta_t = safe_sqrt(1. - sin_theta_t * sin_theta_t);
+        let eta_p = (eta * eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = self.h / eta_p;
+        let cos_gamma_t = safe_sqrt(1. - sin_gamma_t * sin_gamma_t);
+        let length = 2. * cos_gamma_t / cos_theta_t;
+        let absorbed = -self.hair.absorption * length;
+        (safe_asin(sin_gamma_t), Vector3::new(absorbed.x.exp(), absorbed.y.exp(), absorbed.z.exp()))
+    }
+
+    /// the fraction of light each lobe carries
+    fn attenuation(&self, cos_theta_o: f32, transmittance: Vector3<f32>) -> [Vector3<f32>; LOBES] {
+        let cos_gamma_o = safe_sqrt(1. - self.h * self.h);
+        let f = fresnel(cos_theta_o * cos_gamma_o, self.hair.refraction_index);
+        let r = Vector3::new(f, f, f);
+        let tt = (1. - f) * (1. - f) * transmittance;
+        let trt = f * tt.mul_element_wise(transmittance);
+        let rest = (f * trt.mul_element_wise(transmittance)).div_element_wise(Vector3::new(1., 1., 1.) - f * transmittance);
+        [r, tt, trt, rest]
+    }
+
+    /// the outgoing angle with the normal plane turned by the scales for a lobe
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin, cos) = match p {
+            0 => (self.sin_2k_alpha[1], self.cos_2k_alpha[1]),
+            1 => (-self.sin_2k_alpha[0], self.cos_2k_alpha[0]),
+            2 => (-self.sin_2k_alpha[2], self.cos_2k_alpha[2]),
+            _ => return (sin_theta_o, cos_theta_o),
+        };
+        (sin_theta_o * cos - cos_theta_o * sin, (cos_theta_o * cos + sin_theta_o * sin).abs())
+    }
+
+    /// chance of picking each lobe when sampling
+    fn lobe_pdf(&self, sin_theta_o: f32, cos_theta_o: f32) -> [f32; LOBES] {
+        let (_, transmittance) = self.transmittance(sin_theta_o, cos_theta_o);
+        let attenuation = self.attenuation(cos_theta_o, transmittance);
+        let weight = |a: Vector3<f32>| (a.x + a.y + a.z) / 3.;
+        let sum = attenuation.iter().fold(0., |sum, &a| sum + weight(a));
+        let mut pdf = [0.; LOBES];
+        for (pdf, &a) in pdf.iter_mut().zip(attenuation.iter()) {
+            *pdf = weight(a) / sum;
+        }
+        pdf
+    }
+
+    /// scattering from the incoming direction `wi` towards `wo`, both pointing away from the
+    /// fibre, including the cosine with the normal
+    pub fn eval(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+        let (sin_theta_o, cos_theta_o, phi_o) = self.angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = self.angles(wi);
+        let (gamma_t, transmittance) = self.transmittance(sin_theta_o, cos_theta_o);
+        let attenuation = self.attenuation(cos_theta_o, transmittance);
+        let phi = phi_i - phi_o;
+        let mut sum = Vector3::new(0., 0., 0.);
+        for p in 0..3 {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            sum += longitudinal(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, self.v[p]) *
+                   azimuthal(phi, p, self.s, self.gamma_o, gamma_t) * attenuation[p];
+        }
+        sum + longitudinal(cos_theta_i, cos_theta_o, sin_theta_i, sin_theta_o, self.v[3]) * attenuation[3] / (2. * f32::consts::PI)
+    }
+
+    pub fn pdf(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        let (sin_theta_o, cos_theta_o, phi_o) = self.angles(wo);
+        let (sin_theta_i, cos_theta_i, phi_i) = self.angles(wi);
+        let (gamma_t, _) = self.transmittance(sin_theta_o, cos_theta_o);
+        let lobe_pdf = self.lobe_pdf(sin_theta_o, cos_theta_o);
+        let phi = phi_i - phi_o;
+        let mut pdf = 0.;
+        for p in 0..3 {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += longitudinal(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, self.v[p]) *
+                   lobe_pdf[p] * azimuthal(phi, p, self.s, self.gamma_o, gamma_t);
+        }
+        pdf + longitudinal(cos_theta_i, cos_theta_o, sin_theta_i, sin_theta_o, self.v[3]) * lobe_pdf[3] / (2. * f32::consts::PI)
+    }
+
+    /// an incoming direction for the outgoing one by picking a lobe and sampling it,
+    /// with the scattering and the pdf
+    pub fn sample(&self, wo: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>, f32) {
+        let (sin_theta_o, cos_theta_o, phi_o) = self.angles(wo);
+        let lobe_pdf = self.lobe_pdf(sin_theta_o, cos_theta_o);
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let Closed01(r2) = random::<Closed01<f32>>();
+        let Closed01(r3) = random::<Closed01<f32>>();
+        let mut p = 0;
+        let mut pick = r0;
+        while p < LOBES - 1 && pick >= lobe_pdf[p] {
+            pick -= lobe_pdf[p];
+            p += 1;
+        }
+        // the angle with the normal plane around the tilted reflection
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let v = self.v[p];
+        let r1 = r1.max(1e-5);
+        let cos_theta = 1. + v * (r1 + (1. - r1) * (-2. / v).exp()).ln();
+        let sin_theta = safe_sqrt(1. - cos_theta * cos_theta);
+        let cos_phi = (2. * f32::consts::PI * r2).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1. - sin_theta_i * sin_theta_i);
+        // and around the fibre
+        let (gamma_t, _) = self.transmittance(sin_theta_o, cos_theta_o);
+        let phi = if p < 3 {
+            phi_lobe(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(r3, self.s, -f32::consts::PI, f32::consts::PI)
+        } else {
+            2. * f32::consts::PI * r3
+        };
+        let phi_i = phi_o + phi;
+        let wi = sin_theta_i * self.tangent + cos_theta_i * (phi_i.cos() * self.bitangent + phi_i.sin() * self.normal);
+        (wi, self.eval(wo, wi), self.pdf(wo, wi))
+    }
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.).sqrt()
+}
+
+fn safe_asin(x: f32) -> f32 {
+    x.max(-1.).min(1.).asin()
+}
+
+// unpolarized reflectance of a dielectric seen from outside
+fn fresnel(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.max(0.).min(1.);
+    let sin_theta_t = safe_sqrt(1. - cos_theta_i * cos_theta_i) / eta;
+    if sin_theta_t >= 1. {
+        return 1.;
+    }
+    let cos_theta_t = safe_sqrt(1. - sin_theta_t * sin_theta_t);
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+// modified Bessel function of the first kind
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 0.;
+    let mut term = 1.;
+    let x2 = x * x;
+    for i in 0..10 {
+        if i > 0 {
+            term *= x2 / (4. * (i * i) as f32);
+        }
+        sum += term;
+    }
+    sum
+}
+
+fn log_bessel_i0(x: f32) -> f32 {
+    if x > 12. {
+        x + 0.5 * (-(2. * f32::consts::PI).ln() + (1. / x).ln() + 1. / (8. * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+// the longitudinal lobe, a spherical Gaussian around the reflection with variance v
+fn longitudinal(cos_theta_i: f32, cos_theta_o: f32, sin_theta_i: f32, sin_theta_o: f32, v: f32) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_bessel_i0(a) - b - 1. / v + f32::consts::LN_2 + (1. / (2. * v)).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1. / v).sinh() * 2. * v)
+    }
+}
+
+// the angle around the fibre a lobe leaves at without roughness
+fn phi_lobe(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    2. * p as f32 * gamma_t - 2. * gamma_o + p as f32 * f32::consts::PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1. + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1. / (1. + (-x / s).exp())
+}
+
+// the azimuthal lobe, a logistic distribution around the angle of the lobe on the circle
+fn azimuthal(phi: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut difference = phi - phi_lobe(p, gamma_o, gamma_t);
+    while difference > f32::consts::PI {
+        difference -= 2. * f32::consts::PI;
+    }
+    while difference < -f32::consts::PI {
+        difference += 2. * f32::consts::PI;
+    }
+    logistic(difference, s) / (logistic_cdf(f32::consts::PI, s) - logistic_cdf(-f32::consts::PI, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1. / (u * k + logistic_cdf(a, s)) - 1.).ln();
+    x.max(a).min(b)
+}
+
+#[test]
+fn hair_sampling_weights() {
+    // without absorption the lobes are sampled in proportion to what they scatter
+    let tangent = Vector3::new(1., 0., 0.);
+    let normal = Vector3::new(0., 0., 1.);
+    for &roughness in &[0.2, 0.5, 0.9] {
+        let hair = Hair { absorption: Vector3::new(0., 0., 0.), longitudinal_roughness: roughness, azimuthal_roughness: roughness, ..Hair::from_melanin(0., 0.) };
+        for i in 0..100 {
+            let bsdf = HairBsdf::new(hair, -1. + 2. * (i as f32 + 0.5) / 100., tangent, normal);
+            let angle = i as f32 * 0.3;
+            let wo = Vector3::new(0.8 * angle.cos(), 0.6, 0.8 * angle.sin()).normalize();
+            let (wi, value, pdf) = bsdf.sample(wo);
+            if pdf > 0. {
+                let weight = value.y / pdf;
+                assert!(weight > 0.99 && weight < 1.01, "weight {} with roughness {}", weight, roughness);
+                assert!((bsdf.pdf(wo, wi) - pdf).abs() <= 1e-4 * pdf);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+extern crate cgmath;
+use self::cgmath::Vector3;
+use self::cgmath::InnerSpace;
+use std::f32;
+
+use ray::{Ray, Intersection};
+use scene::Scene;
+use primitive::Primitive;
+use checkpoint;
+use super::{Integrator, cosine_weighted_diffuse};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    ShadingNormal,
+    GeometricNormal,
+    Depth,
+    AmbientOcclusion,
+    Albedo,
+    Barycentrics,
+    MaterialId,
+    TraversalCost,
+}
+
+/// Shows a single property of the first intersection instead of light transport
+#[derive(Debug, Copy, Clone)]
+pub struct DebugIntegrator {
+    pub view: DebugView,
+    // distance that is shown black in the depth view
+    pub far: f32,
+    // maximum distance of an occluder in the ambient occlusion view
+    pub occlusion_distance: f32,
+    // number of tested nodes and primitives that is shown red in the traversal cost view
+    pub max_cost: u32,
+}
+
+impl DebugIntegrator {
+    pub fn new(view: DebugView) -> DebugIntegrator {
+        DebugIntegrator {
+            view: view,
+            far: 10.0,
+            occlusion_distance: 0.5,
+            max_cost: 200,
+        }
+    }
+}
+
+fn normal_to_color(normal: Vector3<f32>) -> Vector3<f32> {
+    0.5 * (normal + Vector3::new(1., 1., 1.))
+}
+
+/// blue for low, green for medium and red for high values in `[0, 1]`
+fn heat(value: f32) -> Vector3<f32> {
+    let value = value.max(0.).min(1.);
+    if value < 0.5 {
+        Vector3::new(0., 2. * value, 1. - 2. * value)
+    } else {
+        Vector3::new(2. * value - 1., 2. - 2. * value, 0.)
+    }
+}
+
+impl<T: Primitive> Integrator<T> for DebugIntegrator {
+    fn radiance(&self, scene: &Scene<T>, ray: &mut Ray) -> Vector3<f32> {
+        if self.view == DebugView::TraversalCost {
+            let (nodes, primitives) = scene.bvh.traversal_cost(ray);
+            return heat((nodes + primitives) as f32 / self.max_cost as f32);
+        }
+        match scene.bvh.intersect_closest(ray) {
+            None => Vector3::new(0., 0., 0.),
+            Some(Intersection{normal, geometric_normal, uv, material, ..}) => {
+                match self.view {
+                    DebugView::ShadingNormal => normal_to_color(normal),
+                    DebugView::GeometricNormal => normal_to_color(geometric_normal),
+                    DebugView::Depth => {
+                        let depth = 1. - (ray.distance / self.far).min(1.);
+                        Vector3::new(depth, depth, depth)
+                    },
+                    DebugView::AmbientOcclusion => {
+                        let intersection_point = ray.intersection();
+                        // face the normal towards the camera
+                        let normal = if normal.dot(ray.direction) > 0. { -normal } else { normal };
+                        let direction = cosine_weighted_diffuse(&normal);
+                        let mut occlusion_ray = Ray::new(intersection_point + 20. * f32::EPSILON * direction, direction, self.occlusion_distance).with_time(ray.time);
+                        match scene.bvh.intersect_any(&mut occlusion_ray) {
+                            Some(_) => Vector3::new(0., 0., 0.),
+                            None => Vector3::new(1., 1., 1.),
+                        }
+                    },
+                    DebugView::Albedo => material.albedo(),
+                    DebugView::Barycentrics => Vector3::new(1. - uv.x - uv.y, uv.x, uv.y),
+                    DebugView::MaterialId => {
+                        // equal materials get the same colour, whether they are in the table of a
+                        // mesh or stored with the primitive, hashed without building a string
+                        let hash = checkpoint::hash(material);
+                        Vector3::new((hash & 0xff) as f32 / 255.,
+                                     ((hash >> 8) & 0xff) as f32 / 255.,
+                                     ((hash >> 16) & 0xff) as f32 / 255.)
+                    },
+                    DebugView::TraversalCost => unreachable!(),
+                }
+            },
+        }
+    }
+}
+
+#[test]
+fn debug_views() {
+    extern crate cgmath;
+    use self::cgmath::Point3;
+    use primitive::sphere::Sphere;
+    use material::Material;
+    let color = Vector3::new(0.2, 0.4, 0.6);
+    let sphere = Sphere { position: Point3::new(0., 0., 3.), radius: 1., material: Material::Diffuse { speculaty: 0., color: color }, object_id: 0 };
+    let scene = Scene::without_skybox(vec![sphere]);
+    // the sphere is hit head on at a distance of 2, where it faces the camera
+    let view = |view: DebugView, direction: Vector3<f32>| {
+        let mut ray = Ray::new(Point3::new(0., 0., 0.), direction, f32::INFINITY);
+        DebugIntegrator::new(view).radiance(&scene, &mut ray)
+    };
+    let forward = Vector3::new(0., 0., 1.);
+    assert!((view(DebugView::ShadingNormal, forward) - Vector3::new(0.5, 0.5, 0.)).magnitude() < 1e-5);
+    assert!((view(DebugView::GeometricNormal, forward) - Vector3::new(0.5, 0.5, 0.)).magnitude() < 1e-5);
+    assert!((view(DebugView::Depth, forward) - Vector3::new(0.8, 0.8, 0.8)).magnitude() < 1e-5);
+    assert!((view(DebugView::Albedo, forward) - color).magnitude() < 1e-5);
+    // nothing else is near the sphere to occlude it
+    assert_eq!(view(DebugView::AmbientOcclusion, forward), Vector3::new(1., 1., 1.));
+    // misses are black
+    let backward = Vector3::new(0., 0., -1.);
+    for debug_view in &[DebugView::ShadingNormal, DebugView::Depth, DebugView::Albedo, DebugView::AmbientOcclusion] {
+        assert_eq!(view(*debug_view, backward), Vector3::new(0., 0., 0.));
+    }
+    // a single sphere costs little to traverse, so it shows blue
+    let cost = view(DebugView::TraversalCost, forward);
+    assert!(cost.z > cost.x && cost.x == 0.);
+}
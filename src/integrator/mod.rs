@@ -0,0 +1,133 @@
+extern crate cgmath;
+extern crate rand;
+use self::cgmath::{Vector3, Point3};
+use self::cgmath::InnerSpace;
+use self::rand::Closed01;
+use random::random;
+use std::f32;
+use std::fmt;
+
+pub mod path;
+pub mod debug;
+
+use ray::{Ray, Intersection};
+use scene::Scene;
+use primitive::Primitive;
+
+/// The first hit of a camera ray, for the feature layers of the film
+#[derive(Debug, Copy, Clone)]
+pub struct FirstHit {
+    pub albedo: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+    pub position: Point3<f32>,
+    pub object_id: u32,
+}
+
+impl FirstHit {
+    // the ray has been cut off at the intersection
+    pub fn new(intersection: &Intersection, ray: &Ray) -> FirstHit {
+        FirstHit {
+            albedo: intersection.material.albedo(),
+            normal: intersection.normal,
+            distance: ray.distance,
+            position: ray.intersection(),
+            object_id: intersection.object_id,
+        }
+    }
+}
+
+/// A light transport algorithm, computes what a camera ray sees in the scene.
+/// Integrators are shared by all render threads, their debug output holds their settings.
+pub trait Integrator<T: Primitive>: Sync + fmt::Debug {
+    fn radiance(&self, scene: &Scene<T>, ray: &mut Ray) -> Vector3<f32>;
+
+    /// radiance split into direct and indirect light,
+    /// integrators that do not trace light paths only return direct light
+    fn radiance_split(&self, scene: &Scene<T>, ray: &mut Ray) -> (Vector3<f32>, Vector3<f32>) {
+        (self.radiance(scene, ray), Vector3::new(0., 0., 0.))
+    }
+
+    /// the split radiance together with the first hit, None when the ray hits nothing.
+    /// Intersects the first hit separately, integrators that find it anyway return theirs.
+    fn radiance_first_hit(&self, scene: &Scene<T>, ray: &mut Ray) -> (Option<FirstHit>, Vector3<f32>, Vector3<f32>) {
+        let mut first_ray = Ray::new(ray.origin, ray.direction, ray.distance).with_time(ray.time);
+        let first_hit = scene.bvh.intersect_closest(&mut first_ray).map(|intersection| FirstHit::new(&intersection, &first_ray));
+        let (direct, indirect) = self.radiance_split(scene, ray);
+        (first_hit, direct, indirect)
+    }
+}
+
+pub fn refract(direction: &Vector3<f32>, normal: &Vector3<f32>, n1: f32, n2: f32) -> Option<Vector3<f32>> {
+    // Refract
+    let div = n1 / n2;
+    let cosi = direction.dot(*normal);
+    let sin_t2 = div * div * (1. - cosi * cosi);
+    if sin_t2 <= 1. {
+        Some(div * direction - (div * cosi + (1. - sin_t2).sqrt()) * normal)
+    } else {
+        None
+    }
+}
+pub fn reflect(direction: &Vector3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+    // Reflect
+    direction - 2. * direction.dot(*normal) * normal
+}
+pub fn schlick(direction: &Vector3<f32>, normal: &Vector3<f32>, n1: f32, n2: f32) -> f32 {
+    // Schlick
+    let div = (n1 - n2) / (n1 + n2);
+    let r0 = div * div;
+    let cosi = -direction.dot(*normal); // ray direction is towards normal invert answer
+    r0 + (1. - r0) * (1. - cosi).powi(5)
+}
+/// Chooses between reflection and refraction at a smooth boundary using Schlick,
+/// returns the new direction and whether the ray got refracted
+pub fn sample_boundary(direction: &Vector3<f32>, normal: &Vector3<f32>, inside: bool, n1: f32, n2: f32) -> (Vector3<f32>, bool) {
+    let (normal, n1, n2) = if inside { (-*normal, n2, n1) } else { (*normal, n1, n2) };
+    if let Some(refracted_dir) = refract(direction, &normal, n1, n2) {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        if r0 < schlick(direction, &normal, n1, n2) {
+            (reflect(direction, &normal), false)
+        } else {
+            (refracted_dir, true)
+        }
+    } else {
+        // Full internal reflection
+        (reflect(direction, &normal), false)
+    }
+}
+pub fn diffuse(normal: &Vector3<f32>) -> Vector3<f32> {
+    // Diffuse
+    let Closed01(r0) = random::<Closed01<f32>>();
+    let r = (1. - r0 * r0).sqrt();
+    let Closed01(r1) = random::<Closed01<f32>>();
+    let phi = 2. * f32::consts::PI * r1;
+    let diffuse_dir = Vector3::new(phi.cos() * r, phi.sin() * r, r0);
+    if diffuse_dir.dot(*normal) < 0. {
+        -1. * diffuse_dir
+    } else {
+        diffuse_dir
+    }
+}
+pub fn cosine_weighted_diffuse(normal: &Vector3<f32>) -> Vector3<f32> {
+    // Cosine weighted Diffuse
+    let Closed01(r0) = random::<Closed01<f32>>();
+    let r = r0.sqrt();
+    let Closed01(r1) = random::<Closed01<f32>>();
+    let phi = 2. * f32::consts::PI * r1;
+    from_tangent_to_local(normal, &Vector3::new(phi.cos() * r, phi.sin() * r, (1. - r0).sqrt()))
+}
+pub fn uniform_cone(axis: &Vector3<f32>, cos_max: f32) -> Vector3<f32> {
+    // Uniform inside a cone around the axis, the pdf is 1 / (2 pi (1 - cos_max))
+    let Closed01(r0) = random::<Closed01<f32>>();
+    let cos_theta = 1. - r0 * (1. - cos_max);
+    let r = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let Closed01(r1) = random::<Closed01<f32>>();
+    let phi = 2. * f32::consts::PI * r1;
+    from_tangent_to_local(axis, &Vector3::new(phi.cos() * r, phi.sin() * r, cos_theta))
+}
+pub fn from_tangent_to_local(normal: &Vector3<f32>, tangent: &Vector3<f32>) -> Vector3<f32> {
+    let t = (normal.cross(if normal.x.abs() > 0.99 { Vector3::new(0.0,1.0,0.0) } else { Vector3::new(1.0,0.0,0.0) })).normalize();
+    let b = normal.cross(t);
+    tangent.x * t + tangent.y * b + tangent.z * normal
+}
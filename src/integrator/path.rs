@@ -0,0 +1,529 @@
+extern crate cgmath;
+extern crate rand;
+use self::cgmath::{Vector3, Point3, Array};
+use self::cgmath::InnerSpace;
+use self::cgmath::ElementWise;
+use self::rand::Closed01;
+use random::random;
+use std::f32;
+
+use ray::{Ray, Intersection};
+use scene::Scene;
+use material::Material;
+use medium::{Medium, henyey_greenstein, sample_henyey_greenstein};
+use hair::{Hair, HairBsdf};
+use spectrum::Wavelengths;
+use primitive::Primitive;
+use super::{Integrator, FirstHit, reflect, refract, schlick, sample_boundary, cosine_weighted_diffuse, uniform_cone, from_tangent_to_local};
+
+// maximum number of scatter events of a random walk inside a subsurface material
+const MAX_RANDOM_WALK: u32 = 256;
+
+// light that scattered at most once on its way to the camera is direct light
+fn add_light(direct: &mut Vector3<f32>, indirect: &mut Vector3<f32>, scatter_events: u32, light: Vector3<f32>) {
+    if scatter_events <= 1 {
+        *direct += light;
+    } else {
+        *indirect += light;
+    }
+}
+
+/// Unidirectional path tracer with next event estimation through media
+#[derive(Debug, Copy, Clone)]
+pub struct PathTracer {
+    // maximum number of bounces
+    pub depth: u32,
+    pub spectral: bool,
+    // largest component of the indirect light of a sample, clamping removes fireflies but darkens
+    pub clamp: Option<f32>,
+    // half angle in radians that specular bounces get roughened by after a diffuse bounce
+    pub regularize: Option<f32>,
+}
+
+impl PathTracer {
+    pub fn new(depth: u32) -> PathTracer {
+        PathTracer {
+            depth: depth,
+            spectral: false,
+            clamp: None,
+            regularize: None,
+        }
+    }
+
+    /// transmittance along a shadow ray through all media,
+    /// None when the ray gets blocked by a surface
+    fn transmittance<T: Primitive>(&self, scene: &Scene<T>, mut ray: Ray, medium: Option<Medium>, wavelengths: &Wavelengths) -> Option<Vector3<f32>> {
+        let mut transmittance = Vector3::new(1., 1., 1.);
+        let mut medium = medium;
+        let (origin, direction, distance) = (ray.origin, ray.direction, ray.distance);
+        loop {
+            let remaining = ray.distance;
+            match scene.bvh.intersect_closest(&mut ray) {
+                Some(Intersection{normal:_, inside, area:_, material: &Material::Volume { medium: volume }, ..}) => {
+                    if let Some(medium) = medium {
+                        transmittance = transmittance.mul_element_wise(medium.transmittance(ray.distance));
+                    }
+                    medium = if inside { scene.fog } else { Some(volume) }.map(|medium| wavelengths.medium(medium));
+                    let boundary_point = ray.intersection();
+                    ray.reset(boundary_point, direction, remaining - ray.distance);
+                },
+                Some(_) => return None,
+                None => {
+                    if let Some(medium) = medium {
+                        transmittance = transmittance.mul_element_wise(medium.transmittance(ray.distance));
+                    }
+                    let volumes = scene.volumes.iter().fold(1., |product, volume| product * volume.transmittance(origin, direction, distance));
+                    return Some(volumes * transmittance);
+                },
+            }
+        }
+    }
+
+    /// Next event estimation for a lobe that gets sampled perfectly, so its value
+    /// in a direction is also the pdf of sampling it. Weighted against hitting the light by sampling the lobe.
+    fn sample_light<T: Primitive>(&self, scene: &Scene<T>, medium: Option<Medium>, point: Point3<f32>, time: f32, wavelengths: &Wavelengths, lobe: &Fn(Vector3<f32>) -> f32) -> Vector3<f32> {
+        self.sample_light_scattering(scene, medium, point, time, wavelengths, &|light_dir| {
+            let pdf = lobe(light_dir);
+            (Vector3::new(pdf, pdf, pdf), pdf)
+        })
+    }
+
+    /// Next event estimation for scattering that gives the light it passes on from a direction,
+    /// cosine included, and the pdf of sampling that direction.
+    fn sample_light_scattering<T: Primitive>(&self, scene: &Scene<T>, medium: Option<Medium>, point: Point3<f32>, time: f32, wavelengths: &Wavelengths, scattering: &Fn(Vector3<f32>) -> (Vector3<f32>, f32)) -> Vector3<f32> {
+        if let Some((nr_ligths, random_light)) = scene.bvh.random_light() {
+            let point_on_light = random_light.random_point(time);
+            let light_dir = (point_on_light - point).normalize();
+            let (value, lobe_pdf) = scattering(light_dir);
+            if lobe_pdf <= 0. {
+                return Vector3::new(0., 0., 0.);
+            }
+            let mut god_ray = Ray::new(point, light_dir, f32::INFINITY).with_time(time);
+            if let Some(intersection_on_light) = random_light.intersect(&mut god_ray) {
+                let cos_light = -intersection_on_light.normal.dot(light_dir);
+                if cos_light > 0.0 {
+                    // ray should not hit the light in the transmittance test
+                    if let Some(transmittance) = self.transmittance(scene, Ray::new(point, light_dir, god_ray.distance - f32::EPSILON).with_time(time), medium, wavelengths) {
+                        let light_color = wavelengths.upsample(random_light.is_light().unwrap()); // we selected a light
+                        let solid_angle = (cos_light * intersection_on_light.area) / (god_ray.distance * god_ray.distance);
+                        let light_pdf = 1.0 / solid_angle;
+                        let multiple_important_sampling_pdf = light_pdf + lobe_pdf;
+                        return (nr_ligths as f32 / multiple_important_sampling_pdf) * value.mul_element_wise(transmittance).mul_element_wise(light_color);
+                    }
+                }
+            }
+        }
+        Vector3::new(0., 0., 0.)
+    }
+
+    /// Path regularization of a specular bounce, the mirror direction is widened into a cone
+    /// of `regularize` radians with a constant lobe. Returns the new direction, the light
+    /// reaching the point through the cone and the pdf of the direction. Biased, the
+    /// caustics it makes visible to next event estimation get blurred.
+    fn regularized_bounce<T: Primitive>(&self, scene: &Scene<T>, medium: Option<Medium>, point: Point3<f32>, time: f32, specular_dir: Vector3<f32>, wavelengths: &Wavelengths) -> (Vector3<f32>, Vector3<f32>, f32) {
+        let angle = self.regularize.unwrap_or(0.).max(1e-3).min(f32::consts::PI);
+        let cos_max = angle.cos();
+        let pdf = 1. / (2. * f32::consts::PI * (1. - cos_max));
+        let light = self.sample_light(scene, medium, point + 20. * f32::EPSILON * specular_dir, time, wavelengths, &|light_dir| {
+            if light_dir.dot(specular_dir) >= cos_max { pdf } else { 0. }
+        });
+        (uniform_cone(&specular_dir, cos_max), light, pdf)
+    }
+
+    /// Random walk through the medium inside a subsurface material, the ray has just
+    /// been refracted into it. Returns false when the walk got absorbed, otherwise
+    /// the ray leaves the material through its boundary.
+    fn random_walk<T: Primitive>(&self, scene: &Scene<T>, ray: &mut Ray, transport: &mut Vector3<f32>, medium: Medium) -> bool {
+        for _ in 0..MAX_RANDOM_WALK {
+            let intersection = scene.bvh.intersect_closest(ray);
+            let flight = medium.sample_distance(ray.distance);
+            *transport = transport.mul_element_wise(flight.weight);
+            if flight.scattered {
+                let Closed01(russian_roulette) = random::<Closed01<f32>>();
+                let survival = transport.max().max(0.1); //minimum of 0.1 chance to survive and maximum of transport
+                if russian_roulette < survival {
+                    *transport /= survival;
+                } else {
+                    return false;
+                }
+                let scatter_point = ray.origin + flight.distance * ray.direction;
+                let phase_dir = from_tangent_to_local(&ray.direction, &medium.sample_phase());
+                ray.reset(scatter_point, phase_dir, f32::INFINITY);
+                continue;
+            }
+            match intersection {
+                Some(Intersection{normal, inside: true, area:_, material: &Material::Subsurface { refraction_index_n1, refraction_index_n2, .. }, ..}) => {
+                    let intersection_point = ray.intersection();
+                    let (direction, refracted) = sample_boundary(&ray.direction, &normal, true, refraction_index_n1, refraction_index_n2);
+                    ray.reset(intersection_point, direction, f32::INFINITY);
+                    if refracted {
+                        return true;
+                    }
+                },
+                // open mesh or an other surface inside of the material
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// sample a ray by shooting it through the scene, returns the direct and indirect light
+    fn sample<T: Primitive>(&self, scene: &Scene<T>, ray: &mut Ray) -> (Vector3<f32>, Vector3<f32>) {
+        let (_, direct, indirect) = self.sample_first_hit(scene, ray);
+        (direct, indirect)
+    }
+
+    /// like `sample`, also returns the first surface the ray hits, whether or not a medium scatters before it
+    fn sample_first_hit<T: Primitive>(&self, scene: &Scene<T>, ray: &mut Ray) -> (Option<FirstHit>, Vector3<f32>, Vector3<f32>) {
+        let mut first_hit = None;
+        let mut direct = Vector3::new(0.,0.,0.);
+        let mut indirect = Vector3::new(0.,0.,0.);
+        // number of scatter events, reflections and refractions so far
+        let mut bounces = 0;
+        let mut transport = Vector3::new(1., 1., 1.);
+        // pdf of the direction sampled at the last diffuse or medium vertex,
+        // emission hit by that direction is weighted against next event estimation
+        let mut scatter_pdf = None;
+        // set after the first diffuse or medium scatter event, from then on specular bounces may be regularized
+        let mut diffuse_bounced = false;
+        let wavelengths = if self.spectral { Wavelengths::sample() } else { Wavelengths::Rgb };
+        // set once a dispersive refraction separated the wavelengths
+        let mut dispersed = false;
+        let fog = scene.fog.map(|fog| wavelengths.medium(fog));
+        // the camera is assumed to be outside of all volumes
+        let mut medium = fog;
+        for depth in 0..self.depth {
+            let intersection = scene.bvh.intersect_closest(ray);
+            if depth == 0 {
+                first_hit = intersection.as_ref().map(|intersection| FirstHit::new(intersection, ray));
+            }
+            // first real collision in a heterogeneous volume before the surface
+            let mut volume_collision = None;
+            for volume in &scene.volumes {
+                let max_distance = volume_collision.map_or(ray.distance, |(distance, _)| distance);
+                if let Some(distance) = volume.sample_collision(ray.origin, ray.direction, max_distance) {
+                    volume_collision = Some((distance, volume));
+                }
+            }
+            // distance and phase function anisotropy of a scatter event
+            let mut scatter = None;
+            if let Some(current_medium) = medium {
+                let flight = current_medium.sample_distance(volume_collision.map_or(ray.distance, |(distance, _)| distance));
+                transport = transport.mul_element_wise(flight.weight);
+                if flight.scattered {
+                    scatter = Some((flight.distance, current_medium.anisotropy));
+                }
+            }
+            if let (None, Some((distance, volume))) = (scatter, volume_collision) {
+                transport = transport.mul_element_wise(wavelengths.upsample(volume.albedo));
+                scatter = Some((distance, volume.anisotropy));
+            }
+            if let Some((distance, anisotropy)) = scatter {
+                let scatter_point = ray.origin + distance * ray.direction;
+                let direction = ray.direction;
+                // next event estimation, the phase function is sampled perfectly so its pdf equals the phase function
+                let phase = |light_dir: Vector3<f32>| henyey_greenstein(direction.dot(light_dir), anisotropy);
+                let light = transport.mul_element_wise(self.sample_light(scene, medium, scatter_point, ray.time, &wavelengths, &phase));
+                add_light(&mut direct, &mut indirect, bounces + 1, light);
+                bounces += 1;
+
+                let Closed01(russian_roulette) = random::<Closed01<f32>>();
+                let survival = transport.max().max(0.1); //minimum of 0.1 chance to survive and maximum of transport
+                if russian_roulette < survival {
+                    transport /= survival;
+                } else {
+                    break;
+                }
+                // Phase function sampling
+                let phase_dir = from_tangent_to_local(&direction, &sample_henyey_greenstein(anisotropy));
+                scatter_pdf = Some(henyey_greenstein(direction.dot(phase_dir), anisotropy));
+                diffuse_bounced = true;
+                ray.reset(scatter_point, phase_dir, f32::INFINITY);
+                continue;
+            }
+            match intersection {
+                None => {
+                    let light = transport.mul_element_wise(0.1 * wavelengths.upsample(scene.sample_skybox(ray.direction)));
+                    add_light(&mut direct, &mut indirect, bounces, light);
+                    break;
+                },
+                Some(Intersection{normal, uv, tangent, inside, area, material, ..}) => {
+                    let intersection_point = ray.intersection();
+                    match material {
+                        &Material::Emissive { color } => {
+                            let cos_light = -normal.dot(ray.direction);
+                            let weight = match scatter_pdf {
+                                Some(scatter_pdf) if cos_light > 0.0 => {
+                                    let solid_angle = (cos_light * area) / (ray.distance * ray.distance);
+                                    let light_pdf = 1.0 / solid_angle;
+                                    scatter_pdf / (light_pdf + scatter_pdf)
+                                },
+                                _ => 1.0,
+                            };
+                            add_light(&mut direct, &mut indirect, bounces, weight * transport.mul_element_wise(wavelengths.upsample(color)));
+                            break;
+                        },
+                        &Material::Volume { medium: volume } => {
+                            // index matched boundary, only the medium changes
+                            medium = if inside { fog } else { Some(wavelengths.medium(volume)) };
+                            let direction = ray.direction;
+                            ray.reset(intersection_point, direction, f32::INFINITY);
+                        },
+                        &Material::Diffuse { speculaty, color} => {
+                            if inside { break };
+                            let color = wavelengths.upsample(color);
+                            if let Some((nr_ligths, random_light)) = scene.bvh.random_light() {
+                                let point_on_light = random_light.random_point(ray.time);
+                                let light_dir = (point_on_light - intersection_point).normalize();
+                                let mut god_ray = Ray::new(intersection_point + 20. * f32::EPSILON * light_dir, light_dir, f32::INFINITY).with_time(ray.time);
+                                if let Some(intersection_on_light) = random_light.intersect(&mut god_ray) {
+                                    let cos_intersection = normal.dot(light_dir);
+                                    let cos_light = -intersection_on_light.normal.dot(light_dir);
+                                    if cos_intersection > 0.0 && cos_light > 0.0 {
+                                        // light is not behind surface point, trace shadow ray
+                                        // ray should not hit the light in the transmittance test
+                                        if let Some(transmittance) = self.transmittance(scene, Ray::new(god_ray.origin, light_dir, god_ray.distance - f32::EPSILON).with_time(ray.time), medium, &wavelengths) {
+                                            let brdf = f32::consts::FRAC_1_PI * color;
+                                            let area = intersection_on_light.area;
+                                            let light_color = wavelengths.upsample(random_light.is_light().unwrap()); // we selected a light
+                                            let solid_angle = (cos_light * area) / (god_ray.distance * god_ray.distance);
+                                            let light_pdf = 1.0 / solid_angle;
+                                            let hemisphere_pdf = f32::consts::FRAC_1_PI * cos_intersection;
+                                            let multiple_important_sampling_pdf = light_pdf + hemisphere_pdf;
+                                            // the estimated times this light gets sampled is 1 / nr_ligths, so we multiply this sample by nr_ligths
+                                            let nee_estimate = (1. - speculaty).min(0.0) * nr_ligths as f32 * transport.mul_element_wise((cos_intersection / multiple_important_sampling_pdf) * light_color.mul_element_wise(brdf));
+                                            add_light(&mut direct, &mut indirect, bounces + 1, nee_estimate.mul_element_wise(transmittance));
+                                        }
+                                    }
+                                }
+                            }
+
+                            bounces += 1;
+                            let Closed01(r0) = random::<Closed01<f32>>();
+                            if r0 < speculaty {
+                                // Specular sampling
+                                let reflected_dir = reflect(&ray.direction, &normal);
+                                transport = transport.mul_element_wise(color);
+                                scatter_pdf = None;
+                                let reflected_dir = if self.regularize.is_some() && diffuse_bounced {
+                                    let (direction, light, pdf) = self.regularized_bounce(scene, medium, intersection_point, ray.time, reflected_dir, &wavelengths);
+                                    add_light(&mut direct, &mut indirect, bounces, transport.mul_element_wise(light));
+                                    scatter_pdf = Some(pdf);
+                                    direction
+                                } else {
+                                    reflected_dir
+                                };
+                                ray.reset(intersection_point, reflected_dir, f32::INFINITY);
+                            } else {
+                                // russian_roulette only rays on a diffuse surface which already sent their nex_event_estimation ray
+                                let Closed01(russian_roulette) = random::<Closed01<f32>>();
+                                let survival = transport.max().max(0.1); //minimum of 0.1 chance to survive and maximum of transport
+                                if russian_roulette < survival {
+                                    transport /= survival;
+                                } else {
+                                    break;
+                                }
+                                // Diffuse sampling
+                                let diffuse_dir = cosine_weighted_diffuse(&normal);
+                                ray.reset(intersection_point, diffuse_dir, f32::INFINITY);
+                                let cos_intersection = diffuse_dir.dot(normal);
+                                let brdf = f32::consts::FRAC_1_PI * color;
+                                let hemisphere_pdf = f32::consts::FRAC_1_PI * cos_intersection;
+                                // the light pdf is added when this ray hits a light
+                                scatter_pdf = Some(hemisphere_pdf);
+                                diffuse_bounced = true;
+                                transport = transport.mul_element_wise((cos_intersection / hemisphere_pdf) * brdf);
+                            }
+                        }
+                        &Material::Hair { hair } => {
+                            let hair = Hair { absorption: wavelengths.upsample(hair.absorption), ..hair };
+                            // only curves know the direction of the fibre, elsewhere any direction along the surface will do
+                            let tangent = tangent.unwrap_or_else(|| from_tangent_to_local(&normal, &Vector3::new(1., 0., 0.)));
+                            let bsdf = HairBsdf::new(hair, 2. * uv.y - 1., tangent, normal);
+                            let wo = -ray.direction;
+                            let light = transport.mul_element_wise(self.sample_light_scattering(scene, medium, intersection_point, ray.time, &wavelengths, &|light_dir| {
+                                (bsdf.eval(wo, light_dir), bsdf.pdf(wo, light_dir))
+                            }));
+                            add_light(&mut direct, &mut indirect, bounces + 1, light);
+                            bounces += 1;
+
+                            let Closed01(russian_roulette) = random::<Closed01<f32>>();
+                            let survival = transport.max().max(0.1); //minimum of 0.1 chance to survive and maximum of transport
+                            if russian_roulette < survival {
+                                transport /= survival;
+                            } else {
+                                break;
+                            }
+                            let (direction, value, pdf) = bsdf.sample(wo);
+                            if pdf <= 0. {
+                                break;
+                            }
+                            transport = transport.mul_element_wise(value / pdf);
+                            scatter_pdf = Some(pdf);
+                            diffuse_bounced = true;
+                            ray.reset(intersection_point, direction, f32::INFINITY);
+                        }
+                        &Material::Subsurface { refraction_index_n1, refraction_index_n2, albedo, mean_free_path, anisotropy } => {
+                            scatter_pdf = None;
+                            diffuse_bounced = true;
+                            bounces += 1;
+                            let (direction, refracted) = sample_boundary(&ray.direction, &normal, inside, refraction_index_n1, refraction_index_n2);
+                            ray.reset(intersection_point, direction, f32::INFINITY);
+                            if refracted && !inside {
+                                let interior = wavelengths.medium(Medium::subsurface(albedo, mean_free_path, anisotropy));
+                                if !self.random_walk(scene, ray, &mut transport, interior) {
+                                    break;
+                                }
+                            }
+                        }
+                        &Material::Dielectric { refraction_index_n1, refraction_index_n2, color, dispersion } => {
+                            let color = wavelengths.upsample(color);
+                            let refraction_index_n2 = match wavelengths.hero() {
+                                Some(hero) if !dispersion.is_none() => {
+                                    if !dispersed {
+                                        // only the hero wavelength continues
+                                        transport = Vector3::new(3. * transport.x, 0., 0.);
+                                        dispersed = true;
+                                    }
+                                    dispersion.refraction_index(refraction_index_n2, hero)
+                                },
+                                _ => refraction_index_n2,
+                            };
+                            if inside {
+                                let absorbance = (Vector3::new(-1.,-1.,-1.) + color) * ray.distance;
+                                let transparency = Vector3::new(absorbance.x.exp(), absorbance.y.exp(), absorbance.z.exp());
+                                transport = transport.mul_element_wise(transparency);
+                            }
+                            scatter_pdf = None;
+                            bounces += 1;
+                            let refracted_dir = if inside {
+                                    refract(&ray.direction, &-normal, refraction_index_n2, refraction_index_n1)
+                                } else {
+                                    refract(&ray.direction, &normal, refraction_index_n1, refraction_index_n2)
+                            };
+                            let direction = if let Some(refracted_dir) = refracted_dir {
+                                let schlick_reflection = if inside {
+                                        schlick(&ray.direction, &-normal, refraction_index_n2, refraction_index_n1)
+                                    } else {
+                                        schlick(&ray.direction, &normal, refraction_index_n1, refraction_index_n2)
+                                };
+                                let Closed01(r0) = random::<Closed01<f32>>();
+                                if r0 < schlick_reflection {
+                                    // Reflected ray
+                                    if !inside {
+                                        transport = transport.mul_element_wise(color);
+                                    }
+                                    if inside {
+                                        reflect(&ray.direction, &-normal)
+                                    } else {
+                                        reflect(&ray.direction, &normal)
+                                    }
+                                } else {
+                                    // Refraction sampling, the inside of a dielectric only absorbs
+                                    medium = if inside { fog } else { None };
+                                    refracted_dir
+                                }
+                            } else {
+                                // Full internal reflection
+                                if inside {
+                                    reflect(&ray.direction, &-normal)
+                                } else {
+                                    reflect(&ray.direction, &normal)
+                                }
+                            };
+                            let direction = if self.regularize.is_some() && diffuse_bounced {
+                                let (direction, light, pdf) = self.regularized_bounce(scene, medium, intersection_point, ray.time, direction, &wavelengths);
+                                add_light(&mut direct, &mut indirect, bounces, transport.mul_element_wise(light));
+                                scatter_pdf = Some(pdf);
+                                direction
+                            } else {
+                                direction
+                            };
+                            ray.reset(intersection_point, direction, f32::INFINITY);
+                        }
+                    }
+                }
+            };
+        }
+        let mut indirect = wavelengths.to_rgb(indirect);
+        if let Some(clamp) = self.clamp {
+            // scale instead of clamping every component, so the hue stays the same
+            let max = indirect.max();
+            if max > clamp {
+                indirect *= clamp / max;
+            }
+        }
+        (first_hit, wavelengths.to_rgb(direct), indirect)
+    }
+}
+
+impl<T: Primitive> Integrator<T> for PathTracer {
+    fn radiance(&self, scene: &Scene<T>, ray: &mut Ray) -> Vector3<f32> {
+        let (direct, indirect) = self.sample(scene, ray);
+        direct + indirect
+    }
+
+    fn radiance_split(&self, scene: &Scene<T>, ray: &mut Ray) -> (Vector3<f32>, Vector3<f32>) {
+        self.sample(scene, ray)
+    }
+
+    fn radiance_first_hit(&self, scene: &Scene<T>, ray: &mut Ray) -> (Option<FirstHit>, Vector3<f32>, Vector3<f32>) {
+        self.sample_first_hit(scene, ray)
+    }
+}
+
+#[test]
+fn random_walk_leaves_subsurface_sphere() {
+    use primitive::sphere::Sphere;
+    // index matched, so the walk leaves the first time it reaches the boundary head on
+    let albedo = Vector3::new(1., 0.5, 0.);
+    let material = Material::Subsurface { refraction_index_n1: 1., refraction_index_n2: 1., albedo: albedo, mean_free_path: Vector3::new(1., 1., 1.), anisotropy: 0. };
+    let scene = Scene::without_skybox(vec![Sphere { position: Point3::new(0., 0., 0.), radius: 1., material: material, object_id: 0 }]);
+    let path_tracer = PathTracer::new(8);
+    let medium = Medium::subsurface(albedo, Vector3::new(1., 1., 1.), 0.);
+    let walks = 20000;
+    let mut exits = 0;
+    let mut mean = Vector3::new(0., 0., 0.);
+    for _ in 0..walks {
+        // just refracted into the sphere, heading for its centre
+        let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+        ray.reset(Point3::new(0., 0., -1.), Vector3::new(0., 0., 1.), f32::INFINITY);
+        let mut transport = Vector3::new(1., 1., 1.);
+        if path_tracer.random_walk(&scene, &mut ray, &mut transport, medium) {
+            exits += 1;
+            mean += transport / walks as f32;
+            // it leaves from the surface, outwards
+            let radius = (ray.origin - Point3::new(0., 0., 0.)).magnitude();
+            assert!((radius - 1.).abs() < 1e-3 && ray.direction.dot(ray.origin - Point3::new(0., 0., 0.)) > 0.);
+        }
+    }
+    // without absorption all light comes out again
+    assert!(exits as f32 > 0.999 * walks as f32);
+    assert!((mean.x - 1.).abs() < 0.01);
+    // every scatter event keeps half, without scattering only the light that goes straight through is left
+    let unscattered = (-2f32).exp();
+    assert!((mean.z - unscattered).abs() < 0.015);
+    assert!(mean.y > unscattered + 0.05 && mean.y < 0.9);
+}
+
+#[test]
+fn path_tracer_sees_light_directly() {
+    use primitive::sphere::Sphere;
+    let color = Vector3::new(4., 2., 1.);
+    let scene = Scene::without_skybox(vec![Sphere { position: Point3::new(0., 0., 3.), radius: 1., material: Material::Emissive { color: color }, object_id: 0 }]);
+    let path_tracer = PathTracer::new(8);
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert_eq!(path_tracer.radiance(&scene, &mut ray), color);
+    // seen without scattering, so it is all direct light
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let (direct, indirect) = path_tracer.sample(&scene, &mut ray);
+    assert_eq!((direct, indirect), (color, Vector3::new(0., 0., 0.)));
+    // the first hit is the one the path started with
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let (first_hit, _, _) = path_tracer.radiance_first_hit(&scene, &mut ray);
+    let first_hit = first_hit.expect("hit the light");
+    assert!((first_hit.distance - 2.).abs() < 1e-5 && (first_hit.position - Point3::new(0., 0., 2.)).magnitude() < 1e-5);
+    // the empty background is black
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., -1.), f32::INFINITY);
+    assert_eq!(path_tracer.radiance(&scene, &mut ray), Vector3::new(0., 0., 0.));
+}
@@ -16,8 +16,7 @@ use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
 use std::collections::HashSet;
-
-use cgmath::{Vector3};
+use std::f32;
 
 mod ray;
 mod material;
@@ -26,10 +25,41 @@ mod scene;
 mod camera;
 mod bvh;
 mod mesh;
+mod medium;
+mod hair;
+mod volume;
+mod spectrum;
+mod integrator;
+mod film;
+mod denoise;
+mod adaptive;
+mod filter;
+mod color;
+mod checkpoint;
+mod projection;
+mod aperture;
+mod transform;
+mod animation;
+mod graph;
+mod cache;
+mod random;
 
 use camera::Camera;
 use scene::Scene;
-use primitive::triangle::Triangle;
+use primitive::Primitive;
+use primitive::triangle_mesh::MeshTriangle;
+use integrator::Integrator;
+use integrator::path::PathTracer;
+use integrator::debug::{DebugIntegrator, DebugView};
+use film::{Film, Sample, Aov};
+use denoise::Denoiser;
+use adaptive::AdaptiveSampler;
+use filter::{Filter, FilterSampler, FILTERS};
+use color::{ColorPipeline, ToneMapper, TONE_MAPPERS};
+use checkpoint::Checkpoint;
+use projection::{Projection, PROJECTIONS};
+use aperture::{Aperture, BokehImage};
+use animation::{CameraPath, Sequence};
 
 //const WIDTH: usize = 800;
 //const HEIGHT: usize = 600;
@@ -68,14 +98,319 @@ fn deinterleave_morton(z: u32) -> (u32, u32) {
 
 }
 
-fn vec_to_rgb(vec : Vector3<f32>) -> Vector3<u8> {
-    Vector3::new((255.0 as f32).min( 256.0 * 1.5 * vec.x.sqrt()) as u8,
-                 (255.0 as f32).min( 256.0 * 1.5 * vec.y.sqrt()) as u8,
-                 (255.0 as f32).min( 256.0 * 1.5 * vec.z.sqrt()) as u8)
+/// the integrator selected by a number key, 0 is the path tracer
+fn select_integrator<T: Primitive>(keycode: Keycode, path_tracer: PathTracer) -> Option<Box<Integrator<T>>> {
+    let view = match keycode {
+        Keycode::Num0 => {
+            println!("integrator: path tracer");
+            return Some(Box::new(path_tracer))
+        },
+        Keycode::Num1 => DebugView::ShadingNormal,
+        Keycode::Num2 => DebugView::GeometricNormal,
+        Keycode::Num3 => DebugView::Depth,
+        Keycode::Num4 => DebugView::AmbientOcclusion,
+        Keycode::Num5 => DebugView::Albedo,
+        Keycode::Num6 => DebugView::Barycentrics,
+        Keycode::Num7 => DebugView::MaterialId,
+        Keycode::Num8 => DebugView::TraversalCost,
+        _ => return None,
+    };
+    println!("integrator: {:?}", view);
+    Some(Box::new(DebugIntegrator::new(view)))
+}
+
+/// settings of a render without a window, from the command line
+#[derive(Clone)]
+struct Batch {
+    output: String,
+    // samples per pixel, the maximum when sampling adaptively
+    samples: u32,
+    denoiser: Option<Denoiser>,
+    sampler: Option<AdaptiveSampler>,
+    pipeline: ColorPipeline,
+    // where the accumulation state gets saved during and after the render
+    checkpoint: Option<String>,
+    // frames of a camera path to render into the output directory
+    sequence: Option<Sequence>,
+}
+
+/// Renders the film without a window and writes it, stops early when adaptive sampling converged.
+/// A resumed render continues after the passes of its checkpoint.
+fn render_headless<T: Primitive + Sync>(pool: &mut scoped_threadpool::Pool, camera: &Camera, scene: &Scene<T>, integrator: &Integrator<T>, film: &mut Film, state: Checkpoint, batch: &Batch) {
+    const ROWS: usize = 4;
+    // passes between two checkpoints
+    const CHECKPOINT_PASSES: u32 = 16;
+    let width = film.width;
+    let seed = state.seed;
+    let mut passes = state.passes;
+    for pass in state.passes..batch.samples {
+        let mask = match batch.sampler {
+            Some(sampler) => sampler.mask(film),
+            None => vec![true; film.pixels.len()],
+        };
+        let active = mask.iter().filter(|&&active| active).count();
+        if active == 0 {
+            println!("converged after {} passes", pass);
+            break;
+        }
+        let mask = &mask;
+        pool.scoped(|scope| {
+            for (chunk_id, chunk) in film.pixels.chunks_mut(width * ROWS).enumerate() {
+                scope.execute(move||{
+                    random::seed(seed, pass, chunk_id as u32);
+                    for (idx, pixel) in chunk.iter_mut().enumerate() {
+                        if mask[chunk_id * width * ROWS + idx] {
+                            let (mut ray, weight) = camera.generate(idx % width, chunk_id * ROWS + idx / width);
+                            pixel.add(&Sample::trace(integrator, scene, &mut ray, weight));
+                        }
+                    }
+                });
+            }
+        });
+        println!("pass {}/{}, {} pixels sampled", pass + 1, batch.samples, active);
+        passes = pass + 1;
+        if let Some(ref path) = batch.checkpoint {
+            if passes % CHECKPOINT_PASSES == 0 {
+                Checkpoint { passes: passes, ..state }.save(path, film).expect("write checkpoint");
+            }
+        }
+    }
+    if let Some(ref path) = batch.checkpoint {
+        Checkpoint { passes: passes, ..state }.save(path, film).expect("write checkpoint");
+    }
+    film.write(&batch.output, &batch.pipeline).expect("write film");
+    if let Some(denoiser) = batch.denoiser {
+        let denoised = denoiser.denoise(film, pool);
+        film::write_ppm(&format!("{}.denoised.ppm", batch.output), film.width, film.height, &denoised, &batch.pipeline).expect("write denoised");
+        film::write_pfm(&format!("{}.denoised.pfm", batch.output), film.width, film.height, &denoised).expect("write denoised");
+    }
+}
+
+/// Renders the frames of the sequence as `<output>/frame_<number>`, every frame with the samples of
+/// the batch. A frame with an image and without a checkpoint is done, an interrupted frame
+/// resumes from its checkpoint, which goes away once the frame is written.
+fn render_sequence<T: Primitive + Sync>(pool: &mut scoped_threadpool::Pool, camera: &mut Camera, scene: &Scene<T>, integrator: &Integrator<T>, film: &mut Film, scene_hash: u64, batch: &Batch) {
+    let sequence = batch.sequence.as_ref().expect("sequence");
+    std::fs::create_dir_all(&batch.output).expect("create output directory");
+    for frame in sequence.first..sequence.last + 1 {
+        let output = format!("{}/frame_{:04}", batch.output, frame);
+        let checkpoint_path = format!("{}.ptck", output);
+        let checkpoint_exists = std::path::Path::new(&checkpoint_path).exists();
+        if std::path::Path::new(&format!("{}.ppm", output)).exists() && !checkpoint_exists {
+            println!("frame {} already rendered", frame);
+            continue;
+        }
+        let key = sequence.path.at(frame as f32);
+        camera.focus_distance = key.focus_distance;
+        camera.f_number = key.f_number;
+        // the camera moves and turns towards the next frame while the shutter is open
+        let next = sequence.path.at(frame as f32 + 1.);
+        camera.motion = next.origin - key.origin;
+        camera.target_motion = next.target - key.target;
+        camera.look_at(key.origin, key.target, scene);
+        film.clear();
+        let mut state = Checkpoint { passes: 0, seed: rand::random(), camera_hash: checkpoint::hash(camera), scene_hash: scene_hash, integrator_hash: checkpoint::hash(integrator) };
+        if checkpoint_exists {
+            // a checkpoint of an edited camera path starts the frame over
+            match state.resume(&checkpoint_path, film) {
+                Ok(resumed) => state = resumed,
+                Err(error) => {
+                    println!("frame {} starts over: {}", frame, error);
+                    film.clear();
+                },
+            }
+        }
+        println!("frame {}/{}", frame, sequence.last);
+        let frame_batch = Batch { output: output, checkpoint: Some(checkpoint_path.clone()), sequence: None, ..batch.clone() };
+        render_headless(pool, camera, scene, integrator, film, state, &frame_batch);
+        std::fs::remove_file(&checkpoint_path).expect("remove checkpoint");
+    }
 }
+
 fn main() {
     const WIDTH: usize = 800;
     const HEIGHT: usize = 600;
+
+    // --headless <output> renders without a window and writes <output>.ppm and <output>.<aov>.pfm
+    let args: Vec<String> = std::env::args().collect();
+    let argument = |name: &str| args.iter().position(|arg| arg == name).map(|i| args.get(i + 1).expect("missing argument value").clone());
+    // --filter <box|tent|gaussian|mitchell|lanczos> selects the pixel reconstruction filter
+    let filter = argument("--filter").map_or(Filter::Box, |name| Filter::from_name(&name).expect("unknown filter"));
+    // --projection <perspective|orthographic|equidistant|equisolid|equirectangular|cubemap> selects the camera projection
+    let projection = argument("--projection").map_or(Projection::Perspective, |name| Projection::from_name(&name).expect("unknown projection"));
+    // --exposure <stops>, --white-balance <kelvin> and --tonemap <linear|reinhard|aces|agx> set up the colour pipeline
+    let mut pipeline = ColorPipeline::new();
+    if let Some(exposure) = argument("--exposure") {
+        pipeline.exposure = exposure.parse().expect("--exposure takes a number");
+    }
+    if let Some(temperature) = argument("--white-balance") {
+        pipeline.set_white_balance(temperature.parse().expect("--white-balance takes a number"));
+    }
+    if let Some(name) = argument("--tonemap") {
+        pipeline.tone_mapper = ToneMapper::from_name(&name).expect("unknown tone mapper");
+    }
+    let batch = argument("--headless").map(|output| Batch {
+        output: output,
+        samples: argument("--spp").map_or(64, |n| n.parse().expect("--spp takes a number")),
+        // --denoise also writes <output>.denoised.ppm and <output>.denoised.pfm
+        denoiser: if args.iter().any(|arg| arg == "--denoise") { Some(Denoiser::new()) } else { None },
+        // --adaptive <threshold> stops sampling pixels whose relative error is below the threshold
+        sampler: argument("--adaptive").map(|threshold| AdaptiveSampler::new(threshold.parse().expect("--adaptive takes a number"))),
+        pipeline: pipeline,
+        // --checkpoint <path> saves the accumulation state every few passes
+        checkpoint: argument("--checkpoint"),
+        // --animation <keyframes> renders the camera path into the --headless directory as an
+        // image sequence, --frames <first>:<last> limits it to a range of frames
+        sequence: argument("--animation").map(|path| {
+            let path = CameraPath::load(std::path::Path::new(&path)).expect("camera path");
+            let (first, last) = match argument("--frames") {
+                Some(range) => {
+                    let frames = range.split(':').map(|n| n.parse::<u32>().expect("--frames takes <first>:<last>")).collect::<Vec<_>>();
+                    (frames[0], *frames.get(1).expect("--frames takes <first>:<last>"))
+                },
+                None => (path.first_frame().max(0.).ceil() as u32, path.last_frame().max(0.).floor() as u32),
+            };
+            Sequence { path: path, first: first, last: last }
+        }),
+    });
+    // --resume <path> continues accumulating from a checkpoint of the same scene and camera,
+    // in the viewer F5 saves to and F9 resumes from the --checkpoint path
+    let resume = argument("--resume");
+    let checkpoint_path = argument("--checkpoint").unwrap_or("render.ptck".to_string());
+    // --clamp <max> clamps the indirect light of every sample, --regularize <degrees> roughens
+    // specular bounces after a diffuse one and --median-of-means rejects outliers in the film
+    let clamp = argument("--clamp").map(|max| max.parse().expect("--clamp takes a number"));
+    let regularize = argument("--regularize").map(|degrees| degrees.parse::<f32>().expect("--regularize takes a number").to_radians());
+
+    //let scene = Scene::<Sphere>::default_scene().expect("scene");
+    //let scene = Scene::<primitive::moving::Moving<Sphere>>::motion_scene().expect("scene");
+    //let scene = Scene::<primitive::shape::Shape>::analytic_scene().expect("scene");
+    //let scene = Scene::<primitive::shape::Shape>::sdf_scene().expect("scene");
+    //let scene = Scene::<primitive::shape::Shape>::csg_scene().expect("scene");
+    //let scene = Scene::<primitive::shape::Shape>::hair_scene(&std::path::Path::new("./models/hair.txt")).expect("scene");
+    //let scene = Scene::<Triangle>::instanced_scene(&std::path::Path::new("./models/buddha.obj")).expect("scene");
+    //let scene = Scene::<Triangle>::from_graph(&Scene::<Triangle>::model_graph(&std::path::Path::new("./models/buddha.obj"))).expect("scene");
+    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/buddha.obj")).expect("scene").combine(Scene::<Sphere>::default_scene().expect("scene"));
+    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/cube.obj")).expect("scene");
+    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/dragon.obj")).expect("scene");
+    // the files the scene is made from, a checkpoint only resumes with the same ones
+    let model = std::path::Path::new("./models/buddha.obj");
+    let mut scene_files = vec![model.to_path_buf()];
+    let scene = Scene::<MeshTriangle>::cached_scene(model).expect("scene");
+    // the BVH comes from the cache, unless it could not be written
+    if cache::cache_path(model).is_file() {
+        scene_files.push(cache::cache_path(model));
+    }
+    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/rungholt.obj")).expect("scene");
+    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/powerplant.obj")).expect("scene");
+    //let scene = Scene::<Triangle>::scene(model).expect("scene");
+    //let scene = Scene::<MeshTriangle>::indexed_scene(model).expect("scene");
+    //let scene = scene.with_fog(medium::Medium::fog(0.05, 0.9, 0.3));
+    //let scene = scene.with_volume(volume::GridVolume::new(volume::DensityGrid::cloud(64), primitive::aabb::AABB::new().extent(&cgmath::Point3::new(0.3, -0.3, -0.6)).extent(&cgmath::Point3::new(1.1, 0.5, 0.2)), 40.0, cgmath::Vector3::new(0.9, 0.9, 0.9), 0.2));
+    // --volume <grid> places a dense or sparse density grid file in the scene, where the cloud would go
+    let scene = match argument("--volume") {
+        Some(path) => {
+            let grid = volume::DensityGrid::load(std::path::Path::new(&path)).expect("density grid");
+            scene_files.push(std::path::PathBuf::from(path));
+            let bounds = primitive::aabb::AABB::new().extent(&cgmath::Point3::new(0.3, -0.3, -0.6)).extent(&cgmath::Point3::new(1.1, 0.5, 0.2));
+            scene.with_volume(volume::GridVolume::new(grid, bounds, 40.0, cgmath::Vector3::new(0.9, 0.9, 0.9), 0.2))
+        },
+        None => scene,
+    };
+    let mut camera = Camera::new(WIDTH, HEIGHT, &scene);
+    camera.filter = FilterSampler::new(filter);
+    camera.projection = projection;
+    // --sensor <mm>, --focal-length <mm>, --f-number <n> and --focus <distance> set up the lens,
+    // --aperture <circle|blades:<n>|<image.pgm>> shapes the bokeh and --cat-eye <offset> cuts it off towards the corners
+    if let Some(width) = argument("--sensor") {
+        camera.sensor_width = width.parse().expect("--sensor takes a number");
+    }
+    if let Some(length) = argument("--focal-length") {
+        camera.focal_length = length.parse().expect("--focal-length takes a number");
+    }
+    if let Some(f_number) = argument("--f-number") {
+        camera.f_number = f_number.parse().expect("--f-number takes a number");
+    }
+    if let Some(distance) = argument("--focus") {
+        camera.focus_distance = Some(distance.parse().expect("--focus takes a number"));
+    }
+    if let Some(aperture) = argument("--aperture") {
+        camera.aperture = if aperture == "circle" {
+            Aperture::Circular
+        } else if aperture.starts_with("blades:") {
+            Aperture::Polygonal { blades: aperture["blades:".len()..].parse::<u32>().expect("blades:<n> takes a number").max(3), rotation: 0. }
+        } else {
+            Aperture::Image(BokehImage::load(std::path::Path::new(&aperture)).expect("bokeh image"))
+        };
+    }
+    if let Some(offset) = argument("--cat-eye") {
+        camera.cat_eye = offset.parse().expect("--cat-eye takes a number");
+    }
+    // --shutter <open>,<close> sets the times in the frame the shutter is open for motion blur,
+    // --camera-motion <x>,<y>,<z> moves the camera by that much over the frame
+    let numbers = |value: &str, name: &str| value.split(',').map(|n| n.parse::<f32>().expect(name)).collect::<Vec<_>>();
+    if let Some(shutter) = argument("--shutter") {
+        let times = numbers(&shutter, "--shutter takes <open>,<close>");
+        camera.shutter_open = times[0];
+        camera.shutter_close = *times.get(1).expect("--shutter takes <open>,<close>");
+    }
+    if let Some(motion) = argument("--camera-motion") {
+        let motion = numbers(&motion, "--camera-motion takes <x>,<y>,<z>");
+        if motion.len() != 3 {
+            panic!("--camera-motion takes <x>,<y>,<z>");
+        }
+        camera.motion = cgmath::Vector3::new(motion[0], motion[1], motion[2]);
+    }
+    camera.update(&scene);
+    let mut path_tracer = PathTracer::new(32);
+    path_tracer.clamp = clamp;
+    path_tracer.regularize = regularize;
+    let mut integrator: Box<Integrator<MeshTriangle>> = Box::new(path_tracer);
+    // the keys for the settings of the path tracer keep a debug view, and apply once 0 goes back
+    let mut path_tracing = true;
+    let mut film = Film::new(WIDTH, HEIGHT);
+    film.median_of_means = args.iter().any(|arg| arg == "--median-of-means");
+    let denoiser = Denoiser::new();
+    // only the displayed image gets denoised, the film keeps accumulating
+    let mut show_denoised = false;
+    let sampler = AdaptiveSampler::new(0.02);
+    let mut adaptive = false;
+
+
+    let num_cpus = num_cpus::get();
+    let mut key_presses = HashSet::new();
+    let mut pool = scoped_threadpool::Pool::new(num_cpus as u32);
+
+    // hashing reads every scene file, so it only happens for checkpoints
+    let hash_scene = || checkpoint::hash_files(&scene_files).expect("hash scene files");
+    let mut scene_hash = None;
+    // the random numbers of every pass follow from the seed
+    let mut seed = rand::random::<u64>();
+    let mut passes = 0;
+    if let Some(ref path) = resume {
+        let state = Checkpoint { passes: 0, seed: seed, camera_hash: checkpoint::hash(&camera), scene_hash: *scene_hash.get_or_insert_with(&hash_scene), integrator_hash: checkpoint::hash(&*integrator) };
+        let resumed = state.resume(path, &mut film).expect("resume checkpoint");
+        passes = resumed.passes;
+        seed = resumed.seed;
+        println!("resumed after {} passes", passes);
+    }
+
+    if let Some(batch) = batch {
+        if batch.sequence.is_some() {
+            let scene_hash = *scene_hash.get_or_insert_with(&hash_scene);
+            render_sequence(&mut pool, &mut camera, &scene, &*integrator, &mut film, scene_hash, &batch);
+            return;
+        }
+        let state = if resume.is_some() || batch.checkpoint.is_some() {
+            Checkpoint { passes: passes, seed: seed, camera_hash: checkpoint::hash(&camera), scene_hash: *scene_hash.get_or_insert_with(&hash_scene), integrator_hash: checkpoint::hash(&*integrator) }
+        } else {
+            // nothing gets saved, the hashes are never compared
+            Checkpoint { passes: passes, seed: seed, camera_hash: 0, scene_hash: 0, integrator_hash: 0 }
+        };
+        render_headless(&mut pool, &camera, &scene, &*integrator, &mut film, state, &batch);
+        return;
+    }
+
     let sdl_context = sdl2::init().expect("SDL Context");
     let video_subsystem = sdl_context.video().expect("Video subsystem");
 
@@ -97,33 +432,106 @@ fn main() {
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut accum = vec![Vector3::new(0.,0.,0.); WIDTH*HEIGHT];
-    let mut spp: f32 = 0.;
-
-
-    //let scene = Scene::<Sphere>::default_scene().expect("scene");
-    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/cube.obj")).expect("scene");
-    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/dragon.obj")).expect("scene");
-    let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/buddha.obj")).expect("scene");
-    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/rungholt.obj")).expect("scene");
-    //let scene = Scene::<Triangle>::scene(&std::path::Path::new("./models/powerplant.obj")).expect("scene");
-    let mut camera = Camera::new(WIDTH, HEIGHT, scene);
-
-
-    let num_cpus = num_cpus::get();
-    let mut key_presses = HashSet::new();
-    let mut pool = scoped_threadpool::Pool::new(num_cpus as u32);
-
     'running: loop {
+        let mut reset = false;
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..}  => {
                     break 'running
                 },
-                Event::KeyDown { keycode: Some(keycode) , ..} => {
+                Event::KeyDown { keycode: Some(keycode), repeat, ..} => {
                     if keycode == Keycode::Escape {
                         break 'running
                     }
+                    if !repeat {
+                        if keycode == Keycode::L {
+                            path_tracer.spectral = !path_tracer.spectral;
+                            println!("spectral: {:?}", path_tracer.spectral);
+                            if path_tracing {
+                                integrator = Box::new(path_tracer);
+                                reset = true;
+                            }
+                        } else if keycode == Keycode::C {
+                            path_tracer.clamp = if path_tracer.clamp.is_some() { None } else { Some(clamp.unwrap_or(10.)) };
+                            println!("clamp: {:?}", path_tracer.clamp);
+                            if path_tracing {
+                                integrator = Box::new(path_tracer);
+                                reset = true;
+                            }
+                        } else if keycode == Keycode::K {
+                            path_tracer.regularize = if path_tracer.regularize.is_some() { None } else { Some(regularize.unwrap_or(5f32.to_radians())) };
+                            println!("regularize: {:?}", path_tracer.regularize.map(|angle| angle.to_degrees()));
+                            if path_tracing {
+                                integrator = Box::new(path_tracer);
+                                reset = true;
+                            }
+                        } else if keycode == Keycode::M {
+                            // the buckets are always filled, only the estimate changes
+                            film.median_of_means = !film.median_of_means;
+                            println!("median of means: {:?}", film.median_of_means);
+                        } else if keycode == Keycode::N {
+                            show_denoised = !show_denoised;
+                            println!("denoised: {:?}", show_denoised);
+                        } else if keycode == Keycode::T {
+                            let next = TONE_MAPPERS[(TONE_MAPPERS.iter().position(|&tone_mapper| tone_mapper == pipeline.tone_mapper).unwrap() + 1) % TONE_MAPPERS.len()];
+                            pipeline.tone_mapper = next;
+                            println!("tone mapper: {}", next.name());
+                        } else if keycode == Keycode::Equals || keycode == Keycode::Minus {
+                            pipeline.exposure += if keycode == Keycode::Equals { 0.5 } else { -0.5 };
+                            println!("exposure: {} EV", pipeline.exposure);
+                        } else if keycode == Keycode::LeftBracket || keycode == Keycode::RightBracket {
+                            let temperature = pipeline.white_balance() + if keycode == Keycode::RightBracket { 500. } else { -500. };
+                            pipeline.set_white_balance(temperature);
+                            println!("white balance: {} K", pipeline.white_balance());
+                        } else if keycode == Keycode::G {
+                            // next reconstruction filter
+                            let next = FILTERS[(FILTERS.iter().position(|&filter| filter == camera.filter.filter).unwrap() + 1) % FILTERS.len()];
+                            camera.filter = FilterSampler::new(next);
+                            println!("filter: {}", next.name());
+                            reset = true;
+                        } else if keycode == Keycode::F5 || keycode == Keycode::F9 {
+                            let state = Checkpoint { passes: passes, seed: seed, camera_hash: checkpoint::hash(&camera), scene_hash: *scene_hash.get_or_insert_with(&hash_scene), integrator_hash: checkpoint::hash(&*integrator) };
+                            if keycode == Keycode::F5 {
+                                match state.save(&checkpoint_path, &film) {
+                                    Ok(()) => println!("saved {} passes to {}", passes, checkpoint_path),
+                                    Err(error) => println!("could not save checkpoint: {}", error),
+                                }
+                            } else {
+                                // the film of a refused checkpoint keeps accumulating
+                                match state.resume(&checkpoint_path, &mut film) {
+                                    Ok(resumed) => {
+                                        passes = resumed.passes;
+                                        seed = resumed.seed;
+                                        println!("resumed after {} passes", passes);
+                                    },
+                                    Err(error) => println!("could not resume: {}", error),
+                                }
+                            }
+                        } else if keycode == Keycode::Comma || keycode == Keycode::Period {
+                            // a stop smaller or larger
+                            camera.f_number *= if keycode == Keycode::Period { f32::consts::SQRT_2 } else { f32::consts::FRAC_1_SQRT_2 };
+                            println!("f/{:.1}", camera.f_number);
+                            reset = true;
+                        } else if keycode == Keycode::Z || keycode == Keycode::X {
+                            camera.focal_length *= if keycode == Keycode::Z { 1.25 } else { 0.8 };
+                            camera.update(&scene);
+                            println!("focal length: {:.0} mm", camera.focal_length);
+                            reset = true;
+                        } else if keycode == Keycode::O {
+                            // next camera projection
+                            let next = PROJECTIONS[(PROJECTIONS.iter().position(|&projection| projection == camera.projection).unwrap() + 1) % PROJECTIONS.len()];
+                            camera.projection = next;
+                            println!("projection: {}", next.name());
+                            reset = true;
+                        } else if keycode == Keycode::V {
+                            adaptive = !adaptive;
+                            println!("adaptive sampling: {:?}", adaptive);
+                        } else if let Some(selected) = select_integrator(keycode, path_tracer) {
+                            integrator = selected;
+                            path_tracing = keycode == Keycode::Num0;
+                            reset = true;
+                        }
+                    }
                     key_presses.insert(keycode);
                 },
                 Event::KeyUp { keycode: Some(keycode) , ..} => {
@@ -134,31 +542,36 @@ fn main() {
 
         }
 
-        if camera.handle_input(&key_presses) {
-            for mut x in &mut accum[..] {
-                *x = Vector3::new(0.,0.,0.);
-            }
-            spp = 0.;
+        if camera.handle_input(&key_presses, &scene) || reset {
+            film.clear();
+            passes = 0;
         }
-        spp += 1.0;
+        passes += 1;
+        let mask = if adaptive { sampler.mask(&film) } else { vec![true; WIDTH*HEIGHT] };
 
-        let scale: f32 = 1.0 / spp;
         texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            let mask = &mask;
             let camera = &camera;
-            let accum = &mut accum;
+            let scene = &scene;
+            let integrator = &*integrator;
+            let median_of_means = film.median_of_means;
+            let pixels = &mut film.pixels;
             pool.scoped(|scope| {
-                let accum_iter = accum.chunks_mut((WIDTH*HEIGHT)/num_cpus);
+                let accum_iter = pixels.chunks_mut((WIDTH*HEIGHT)/num_cpus);
                 let fb_iter = buffer.chunks_mut((WIDTH*HEIGHT*3)/num_cpus);
                 for (core_id, (mut chunk, mut chunk2)) in &mut accum_iter.zip(fb_iter).enumerate() {
                     scope.execute(move||{
+                        random::seed(seed, passes, core_id as u32);
                         let start_y = core_id * (HEIGHT / num_cpus);
                         for y in 0.. HEIGHT / num_cpus {
                             for x in 0..WIDTH {
-                                let mut ray = camera.generate(x,y+start_y);
                                 let idx = x + y * WIDTH;
-                                chunk[idx] += camera.sample(&mut ray, 32);
+                                if mask[x + (y + start_y) * WIDTH] {
+                                    let (mut ray, weight) = camera.generate(x,y+start_y);
+                                    chunk[idx].add(&Sample::trace(integrator, scene, &mut ray, weight));
+                                }
                                 let offset = y*pitch + x*3;
-                                let rgb = vec_to_rgb(scale*chunk[idx]);
+                                let rgb = pipeline.apply(chunk[idx].estimate(Aov::Beauty, median_of_means));
                                 chunk2[offset + 0] = rgb.x;
                                 chunk2[offset + 1] = rgb.y;
                                 chunk2[offset + 2] = rgb.z;
@@ -168,6 +581,16 @@ fn main() {
                     });
                 }
             });
+            if show_denoised {
+                let denoised = denoiser.denoise(&film, &mut pool);
+                for (idx, color) in denoised.iter().enumerate() {
+                    let offset = (idx / WIDTH)*pitch + (idx % WIDTH)*3;
+                    let rgb = pipeline.apply(*color);
+                    buffer[offset + 0] = rgb.x;
+                    buffer[offset + 1] = rgb.y;
+                    buffer[offset + 2] = rgb.z;
+                }
+            }
         }).unwrap();
         //game.tick(&key_presses, &mut accum, &mut samples_per_pixel);
         //game.render(&mut texture, &accum, samples_per_pixel);
@@ -1,5 +1,8 @@
 extern crate cgmath;
 use self::cgmath::Vector3;
+use medium::Medium;
+use hair::Hair;
+use spectrum::Dispersion;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Material {
@@ -11,9 +14,90 @@ pub enum Material {
         refraction_index_n1: f32,
         refraction_index_n2: f32,
         color: Vector3<f32>,
+        // wavelength dependence of refraction index n2, only used in spectral mode
+        dispersion: Dispersion,
     },
     Emissive {
         color: Vector3<f32>,
+    },
+    // index matched boundary, the inside of the mesh is filled with the medium
+    Volume {
+        medium: Medium,
+    },
+    // dielectric boundary around a scattering medium, for wax, jade, marble and skin
+    Subsurface {
+        refraction_index_n1: f32,
+        refraction_index_n2: f32,
+        albedo: Vector3<f32>,
+        mean_free_path: Vector3<f32>,
+        anisotropy: f32,
+    },
+    // fibres, only on curves
+    Hair {
+        hair: Hair,
+    },
+}
+
+impl Material {
+    /// colour of the material after a single interaction
+    pub fn albedo(&self) -> Vector3<f32> {
+        match *self {
+            Material::Diffuse { color, .. } => color,
+            Material::Dielectric { color, .. } => color,
+            Material::Emissive { color } => color,
+            Material::Volume { medium } => {
+                // a channel without extinction neither scatters nor absorbs
+                let extinction = medium.extinction();
+                let ratio = |scattering: f32, extinction: f32| if extinction > 0. { scattering / extinction } else { 0. };
+                Vector3::new(ratio(medium.scattering.x, extinction.x),
+                             ratio(medium.scattering.y, extinction.y),
+                             ratio(medium.scattering.z, extinction.z))
+            },
+            Material::Subsurface { albedo, .. } => albedo,
+            // what is left after passing through the fibre once
+            Material::Hair { hair } => Vector3::new((-hair.absorption.x).exp(), (-hair.absorption.y).exp(), (-hair.absorption.z).exp()),
+        }
+    }
+    pub fn hair(eumelanin: f32, pheomelanin: f32) -> Material {
+        Material::Hair {
+            hair: Hair::from_melanin(eumelanin, pheomelanin),
+        }
+    }
+    pub fn wax() -> Material {
+        Material::Subsurface {
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.45,
+            albedo: Vector3::new(0.99, 0.95, 0.85),
+            mean_free_path: Vector3::new(0.04, 0.03, 0.02),
+            anisotropy: 0.0,
+        }
+    }
+    pub fn jade() -> Material {
+        Material::Subsurface {
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.66,
+            albedo: Vector3::new(0.75, 0.98, 0.8),
+            mean_free_path: Vector3::new(0.08, 0.12, 0.08),
+            anisotropy: 0.3,
+        }
+    }
+    pub fn marble() -> Material {
+        Material::Subsurface {
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.5,
+            albedo: Vector3::new(0.999, 0.998, 0.995),
+            mean_free_path: Vector3::new(0.02, 0.02, 0.025),
+            anisotropy: 0.0,
+        }
+    }
+    pub fn skin() -> Material {
+        Material::Subsurface {
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.4,
+            albedo: Vector3::new(0.97, 0.85, 0.75),
+            mean_free_path: Vector3::new(0.036, 0.014, 0.008),
+            anisotropy: 0.0,
+        }
     }
 }
 
@@ -25,3 +109,10 @@ pub const LIGHT_COLOR: Vector3<f32> =
         y: 8.5 * LIGHT_SCALE,
         z: 7.0 * LIGHT_SCALE,
     };
+
+#[test]
+fn albedo_of_clear_channels() {
+    // only red is taken out of the light, green and blue pass through untouched
+    let medium = Medium { absorption: Vector3::new(0.5, 0., 0.), scattering: Vector3::new(1.5, 0., 0.), anisotropy: 0. };
+    assert_eq!(Material::Volume { medium: medium }.albedo(), Vector3::new(0.75, 0., 0.));
+}
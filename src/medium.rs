@@ -0,0 +1,143 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, Array, ElementWise};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+/// A homogeneous participating medium.
+/// Absorption and scattering are given per colour channel as coefficients
+/// per unit of distance, the anisotropy is the `g` of the Henyey-Greenstein
+/// phase function (-1 back scattering, 0 isotropic, 1 forward scattering).
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    pub absorption: Vector3<f32>,
+    pub scattering: Vector3<f32>,
+    pub anisotropy: f32,
+}
+
+/// The outcome of sampling a free-flight distance through a medium.
+/// The weight is the transmittance (times scattering for a scatter event)
+/// divided by the probability of the sampled event.
+pub struct FreeFlight {
+    pub distance: f32,
+    pub weight: Vector3<f32>,
+    pub scattered: bool,
+}
+
+impl Medium {
+    /// grey fog with the given density, albedo and anisotropy
+    pub fn fog(density: f32, albedo: f32, anisotropy: f32) -> Medium {
+        Medium {
+            absorption: Vector3::from_value(density * (1. - albedo)),
+            scattering: Vector3::from_value(density * albedo),
+            anisotropy: anisotropy,
+        }
+    }
+
+    /// medium inside a subsurface scattering material, the mean free path
+    /// is the average distance between two interactions per colour channel
+    pub fn subsurface(albedo: Vector3<f32>, mean_free_path: Vector3<f32>, anisotropy: f32) -> Medium {
+        let extinction = Vector3::new(1. / mean_free_path.x, 1. / mean_free_path.y, 1. / mean_free_path.z);
+        Medium {
+            absorption: (Vector3::from_value(1.) - albedo).mul_element_wise(extinction),
+            scattering: albedo.mul_element_wise(extinction),
+            anisotropy: anisotropy,
+        }
+    }
+
+    pub fn extinction(&self) -> Vector3<f32> {
+        self.absorption + self.scattering
+    }
+
+    /// Beer's law over the given distance
+    pub fn transmittance(&self, distance: f32) -> Vector3<f32> {
+        let extinction = self.extinction();
+        Vector3::new(transmittance(extinction.x, distance),
+                     transmittance(extinction.y, distance),
+                     transmittance(extinction.z, distance))
+    }
+
+    /// Samples a free-flight distance up to the next surface at `max_distance`.
+    /// One colour channel is picked to sample the distance with, the pdf is
+    /// averaged over all channels so coloured media do not produce fireflies.
+    pub fn sample_distance(&self, max_distance: f32) -> FreeFlight {
+        let extinction = self.extinction();
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let channel = ((r0 * 3.) as usize).min(2);
+        let distance = if extinction[channel] > 0. {
+            -(1. - r1).max(f32::MIN_POSITIVE).ln() / extinction[channel]
+        } else {
+            f32::INFINITY
+        };
+        if distance < max_distance {
+            let transmittance = self.transmittance(distance);
+            let pdf = extinction.mul_element_wise(transmittance).sum() / 3.;
+            FreeFlight {
+                distance: distance,
+                weight: transmittance.mul_element_wise(self.scattering) / pdf,
+                scattered: true,
+            }
+        } else {
+            let transmittance = self.transmittance(max_distance);
+            let pdf = transmittance.sum() / 3.;
+            FreeFlight {
+                distance: max_distance,
+                weight: if pdf > 0. { transmittance / pdf } else { Vector3::from_value(0.) },
+                scattered: false,
+            }
+        }
+    }
+
+    /// Henyey-Greenstein phase function, `cos_theta` is the cosine between
+    /// the incoming and outgoing propagation direction
+    pub fn phase(&self, cos_theta: f32) -> f32 {
+        henyey_greenstein(cos_theta, self.anisotropy)
+    }
+
+    /// Samples an outgoing direction in tangent space around the incoming
+    /// propagation direction, which is the z axis.
+    pub fn sample_phase(&self) -> Vector3<f32> {
+        sample_henyey_greenstein(self.anisotropy)
+    }
+}
+
+fn transmittance(extinction: f32, distance: f32) -> f32 {
+    if extinction == 0. { 1. } else { (-extinction * distance).exp() }
+}
+
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denominator = 1. + g * g - 2. * g * cos_theta;
+    (1. - g * g) / (4. * f32::consts::PI * denominator * denominator.sqrt())
+}
+
+/// Samples the Henyey-Greenstein phase function in tangent space,
+/// the incoming propagation direction is the z axis.
+pub fn sample_henyey_greenstein(g: f32) -> Vector3<f32> {
+    let Closed01(r0) = random::<Closed01<f32>>();
+    let Closed01(r1) = random::<Closed01<f32>>();
+    let cos_theta = if g.abs() < 1e-3 {
+        1. - 2. * r0
+    } else {
+        let div = (1. - g * g) / (1. - g + 2. * g * r0);
+        ((1. + g * g - div * div) / (2. * g)).max(-1.).min(1.)
+    };
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * f32::consts::PI * r1;
+    Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+}
+
+#[test]
+fn henyey_greenstein_normalized() {
+    // integrate over the sphere, the phase function only depends on theta
+    for &g in [-0.7, 0.0, 0.3, 0.9].iter() {
+        let steps = 100000;
+        let mut integral = 0.;
+        for i in 0..steps {
+            let cos_theta = -1. + 2. * (i as f32 + 0.5) / steps as f32;
+            integral += henyey_greenstein(cos_theta, g) * 2. / steps as f32;
+        }
+        integral *= 2. * f32::consts::PI;
+        assert!((integral - 1.).abs() < 1e-2);
+    }
+}
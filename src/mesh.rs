@@ -1,11 +1,17 @@
 use std::path::Path;
 use tobj;
+use std::mem;
 use cgmath::Point2;
 use cgmath::Point3;
 use cgmath::Vector3;
 use primitive::triangle::Triangle;
+use primitive::triangle_mesh::{Geometry, TriangleMesh};
 use material::Material;
+use spectrum::Dispersion;
 
+/// the models as separate triangles, each with its own copy of its vertices and material.
+/// `load_geometry` stores big models far smaller, this one stays for the scenes that place,
+/// transform or mix the triangles themselves: instances, the scene graph and shapes
 pub fn load_mesh(path: &Path, backup_material: Material) -> Vec<Triangle> {
     let obj_data = tobj::load_obj(path);
     let (models, materials) = obj_data.expect("error with file");
@@ -15,7 +21,7 @@ pub fn load_mesh(path: &Path, backup_material: Material) -> Vec<Triangle> {
     println!("# of materials: {}", materials.len());
     println!("# of triangles: {}", triangles.capacity());
 
-    for model in models.iter() {
+    for (object_id, model) in models.iter().enumerate() {
         println!("model.name: {}", model.name);
         let mesh = &model.mesh;
         let positions: Vec<_> = mesh.positions.chunks(3).map(|i| Point3::<f32>::new(i[0], i[1], i[2])).collect();
@@ -37,34 +43,20 @@ pub fn load_mesh(path: &Path, backup_material: Material) -> Vec<Triangle> {
                 //texture0: texcoords[indexes[0] as usize],
                 //texture1: texcoords[indexes[1] as usize],
                 //texture2: texcoords[indexes[2] as usize],
-                material: {
-                    if let Some(material_id) = mesh.material_id {
-                        let m = &materials[material_id];
-                        if m.dissolve < 1.0 {
-                            Material::Dielectric{
-                                refraction_index_n1: 1.0,
-                                refraction_index_n2: 1.3,
-                                color: Vector3::new(m.ambient[0], m.ambient[1], m.ambient[2]),
-                            }
-                        } else {
-                            Material::Diffuse{
-                                speculaty: m.shininess,
-                                color: Vector3::new(m.diffuse[0], m.diffuse[1], m.diffuse[2]),
-                            }
-                        }
-                    } else {
-                        backup_material
-                        //Material::Dielectric{
-                        //    refraction_index_n1: 1.0,
-                        //    refraction_index_n2: 1.3,
-                        //    color: Vector3::new(0.9,0.8,0.7),
-                        //}
-                        //Material::Diffuse {
-                        //    speculaty: 0.,
-                        //    color: Vector3::new(0.9,0.9,0.9),
-                        //}
-                    }
+                material: match mesh.material_id {
+                    Some(material_id) => convert_material(&materials[material_id]),
+                    None => backup_material
+                    //Material::Dielectric{
+                    //    refraction_index_n1: 1.0,
+                    //    refraction_index_n2: 1.3,
+                    //    color: Vector3::new(0.9,0.8,0.7),
+                    //}
+                    //Material::Diffuse {
+                    //    speculaty: 0.,
+                    //    color: Vector3::new(0.9,0.9,0.9),
+                    //}
                 },
+                object_id: object_id as u32,
         }));
     }
 
@@ -88,3 +80,56 @@ pub fn load_mesh(path: &Path, backup_material: Material) -> Vec<Triangle> {
     //    }
     //}
 }
+
+// the closest we have to a material of the file
+fn convert_material(m: &tobj::Material) -> Material {
+    if m.dissolve < 1.0 {
+        Material::Dielectric{
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.3,
+            color: Vector3::new(m.ambient[0], m.ambient[1], m.ambient[2]),
+            dispersion: Dispersion::None,
+        }
+    } else {
+        Material::Diffuse{
+            speculaty: m.shininess,
+            color: Vector3::new(m.diffuse[0], m.diffuse[1], m.diffuse[2]),
+        }
+    }
+}
+
+/// the models as meshes, which keep the vertices and indices of the file, with their
+/// materials in one table and the backup material last
+pub fn load_geometry(path: &Path, backup_material: Material) -> Geometry {
+    let obj_data = tobj::load_obj(path);
+    let (models, materials) = obj_data.expect("error with file");
+    let mut geometry = Geometry::new();
+    for material in &materials {
+        geometry.add_material(convert_material(material));
+    }
+    let backup = geometry.add_material(backup_material);
+
+    for (object_id, model) in models.into_iter().enumerate() {
+        let mesh = model.mesh;
+        geometry.add_mesh(TriangleMesh {
+            positions: mesh.positions.chunks(3).map(|i| Point3::<f32>::new(i[0], i[1], i[2])).collect(),
+            normals: mesh.normals.chunks(3).map(|i| Vector3::<f32>::new(i[0], i[1], i[2])).collect(),
+            uvs: mesh.texcoords.chunks(2).map(|i| Point2::<f32>::new(i[0], i[1])).collect(),
+            indices: mesh.indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect(),
+            material: mesh.material_id.map(|material_id| material_id as u32).unwrap_or(backup),
+            object_id: object_id as u32,
+        });
+    }
+
+    let vertices: usize = geometry.meshes.iter().map(|mesh| mesh.positions.len()).sum();
+    let triangles: usize = geometry.meshes.iter().map(|mesh| mesh.indices.len()).sum();
+    let bytes: usize = geometry.meshes.iter().map(|mesh|
+        mesh.positions.len() * mem::size_of::<Point3<f32>>() + mesh.normals.len() * mem::size_of::<Vector3<f32>>()
+        + mesh.uvs.len() * mem::size_of::<Point2<f32>>() + mesh.indices.len() * mem::size_of::<[u32; 3]>()).sum();
+    println!("# of meshes: {}", geometry.meshes.len());
+    println!("# of materials: {}", geometry.materials.len());
+    println!("# of vertices: {}", vertices);
+    println!("# of triangles: {}", triangles);
+    println!("mesh memory: {} MB, {} MB as separate triangles", bytes >> 20, (triangles * mem::size_of::<Triangle>()) >> 20);
+    geometry
+}
@@ -36,6 +36,16 @@ impl AABB {
                             z : self.max.z.max(aabb.max.z) }
         }
     }
+    // the box both boxes have in common, empty when they don't touch
+    pub fn overlap(&self, aabb: &AABB) -> AABB {
+        AABB {min : Point3 {x : self.min.x.max(aabb.min.x),
+                            y : self.min.y.max(aabb.min.y),
+                            z : self.min.z.max(aabb.min.z) },
+              max : Point3 {x : self.max.x.min(aabb.max.x),
+                            y : self.max.y.min(aabb.max.y),
+                            z : self.max.z.min(aabb.max.z) }
+        }
+    }
     pub fn size(&self) -> Vector3<f32> {
         self.max - self.min
     }
@@ -0,0 +1,153 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+use super::{Primitive, Solid, solve_quadratic};
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+/// A closed cone standing upright on the centre of its base with the apex on top,
+/// turn it with an instance
+#[derive(Debug, Clone)]
+pub struct Cone {
+    pub position: Point3<f32>,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Cone {
+    // every hit of the line of the ray with the side and the base, with the normal and the uv
+    fn hits<F: FnMut(f32, Vector3<f32>, Point2<f32>)>(&self, ray: &Ray, mut hit: F) {
+        let origin = ray.origin - self.position;
+        let direction = ray.direction;
+        // x^2 + z^2 = (k (height - y))^2 with the slope k of the side
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let rest = self.height - origin.y;
+        let a = direction.x * direction.x + direction.z * direction.z - k2 * direction.y * direction.y;
+        let b = 2. * (origin.x * direction.x + origin.z * direction.z + k2 * rest * direction.y);
+        let c = origin.x * origin.x + origin.z * origin.z - k2 * rest * rest;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            for &t in &[t0, t1] {
+                let point = origin + t * direction;
+                // the equation also holds for the mirrored cone above the apex
+                if point.y >= 0. && point.y <= self.height {
+                    let distance = (point.x * point.x + point.z * point.z).sqrt();
+                    let u = 0.5 + point.z.atan2(point.x) * 0.5 * f32::consts::FRAC_1_PI;
+                    let normal = Vector3::new(point.x, k * distance, point.z).normalize();
+                    hit(t, normal, Point2::new(u, point.y / self.height));
+                }
+            }
+        }
+        let t = -origin.y / direction.y;
+        let point = origin + t * direction;
+        if point.x * point.x + point.z * point.z <= self.radius * self.radius {
+            let u = 0.5 + point.z.atan2(point.x) * 0.5 * f32::consts::FRAC_1_PI;
+            hit(t, Vector3::new(0., -1., 0.), Point2::new(u, 0.));
+        }
+    }
+    fn surface(&self, normal: Vector3<f32>, uv: Point2<f32>, direction: Vector3<f32>) -> Intersection {
+        Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            uv: uv,
+            tangent: None,
+            inside: normal.dot(direction) > 0.,
+            area: self.area(),
+            object_id: self.object_id,
+            material: &self.material,
+        }
+    }
+}
+
+impl Primitive for Cone {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        // the closest of the side and the base, with the normal and the uv
+        let mut closest: Option<(f32, Vector3<f32>, Point2<f32>)> = None;
+        self.hits(ray, |t, normal, uv| {
+            if t >= 0. && t < ray.distance && closest.map_or(true, |(closest, _, _)| t < closest) {
+                closest = Some((t, normal, uv));
+            }
+        });
+        closest.map(move |(t, normal, uv)| {
+            ray.distance = t;
+            self.surface(normal, uv, ray.direction)
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.position + Vector3::new(0., 0.25 * self.height, 0.)
+    }
+    fn bounds(&self) -> AABB {
+        AABB {
+            min: self.position + Vector3::new(-self.radius, 0., -self.radius),
+            max: self.position + Vector3::new(self.radius, self.height, self.radius),
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match self.material {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let Closed01(r2) = random::<Closed01<f32>>();
+        let phi = 2. * f32::consts::PI * r1;
+        let base = f32::consts::PI * self.radius * self.radius;
+        // the side unrolls into a part of a disk around the apex, so both are sampled like a disk
+        let fraction = r2.sqrt();
+        let y = if r0 * self.area() < base { 0. } else { self.height * (1. - fraction) };
+        self.position + Vector3::new(self.radius * fraction * phi.cos(), y, self.radius * fraction * phi.sin())
+    }
+    fn area(&self) -> f32 {
+        let slant = (self.height * self.height + self.radius * self.radius).sqrt();
+        f32::consts::PI * self.radius * (self.radius + slant)
+    }
+}
+
+impl Solid for Cone {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        // it is convex, so the line goes in at the first hit and out at the last, a hit on the
+        // rim of the base is on both the side and the base and only counts once
+        let mut first: Option<(f32, Vector3<f32>, Point2<f32>)> = None;
+        let mut last = first;
+        self.hits(ray, |t, normal, uv| {
+            if first.map_or(true, |(first, _, _)| t < first) {
+                first = Some((t, normal, uv));
+            }
+            if last.map_or(true, |(last, _, _)| t > last) {
+                last = Some((t, normal, uv));
+            }
+        });
+        match (first, last) {
+            (Some(first), Some(last)) if first.0 < last.0 => {
+                [first, last].iter().map(|&(t, normal, uv)| (t, self.surface(normal, uv, ray.direction))).collect()
+            }
+            // only touching the rim or the apex
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn intersections_cone() {
+    let cone = Cone { position: Point3::new(0., -1., 3.), radius: 1., height: 2., material: Material::jade(), object_id: 0 };
+    // halfway up the radius is a half
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = cone.intersect(&mut ray).expect("hit the side");
+    assert!((ray.distance - 2.5).abs() < 1e-5);
+    assert!(intersection.normal.z < 0. && intersection.normal.y > 0.);
+    let mut ray = Ray::new(Point3::new(0.5, -3., 3.), Vector3::new(0., 1., 0.), f32::INFINITY);
+    let intersection = cone.intersect(&mut ray).expect("hit the base");
+    assert!((ray.distance - 2.).abs() < 1e-5 && intersection.normal == Vector3::new(0., -1., 0.));
+    // above the apex is the mirrored cone of the equation, which isn't there
+    let mut ray = Ray::new(Point3::new(0., 2., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(cone.intersect(&mut ray).is_none());
+}
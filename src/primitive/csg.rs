@@ -0,0 +1,143 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Vector3};
+use std::f32;
+
+use super::{Primitive, Solid, nearest};
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    // the left without the right
+    Difference,
+}
+
+/// Constructive solid geometry, two solids combined into one by walking along the ray through
+/// where it goes in and out of either. It is a solid itself, so they can be nested.
+/// It is never sampled as a light.
+#[derive(Debug)]
+pub struct Csg<T> {
+    pub operation: Operation,
+    pub left: T,
+    pub right: T,
+    bounds: AABB,
+}
+
+impl<T: Solid> Csg<T> {
+    pub fn new(operation: Operation, left: T, right: T) -> Csg<T> {
+        let bounds = match operation {
+            Operation::Union => left.bounds().combine(&right.bounds()),
+            Operation::Intersection => left.bounds().overlap(&right.bounds()),
+            Operation::Difference => left.bounds(),
+        };
+        Csg {
+            operation: operation,
+            left: left,
+            right: right,
+            bounds: bounds,
+        }
+    }
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self.operation {
+            Operation::Union => in_left || in_right,
+            Operation::Intersection => in_left && in_right,
+            Operation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+impl<T: Solid> Primitive for Csg<T> {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        nearest(self.crossings(ray), ray)
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.bounds.min + 0.5 * (self.bounds.max - self.bounds.min)
+    }
+    fn bounds(&self) -> AABB {
+        self.bounds
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        None
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        self.centre()
+    }
+    fn area(&self) -> f32 {
+        f32::INFINITY
+    }
+}
+
+impl<T: Solid> Solid for Csg<T> {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        let mut left = self.left.crossings(ray).into_iter().peekable();
+        let mut right = self.right.crossings(ray).into_iter().peekable();
+        let (mut in_left, mut in_right, mut inside) = (false, false, false);
+        let mut crossings = Vec::new();
+        loop {
+            // the nearest crossing of either changes whether we are in it
+            let from_left = match (left.peek(), right.peek()) {
+                (Some(&(a, _)), Some(&(b, _))) => a <= b,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let (t, mut intersection) = if from_left {
+                in_left = !in_left;
+                left.next().unwrap()
+            } else {
+                in_right = !in_right;
+                right.next().unwrap()
+            };
+            // only where the combination changes is its surface
+            if self.contains(in_left, in_right) == inside {
+                continue;
+            }
+            inside = !inside;
+            // the inside of what is cut away is the outside of what remains
+            if !from_left && self.operation == Operation::Difference {
+                intersection.normal = -intersection.normal;
+                intersection.geometric_normal = -intersection.geometric_normal;
+            }
+            intersection.inside = !inside;
+            // a light that is never sampled gets all of the weight when it is hit
+            intersection.area = f32::INFINITY;
+            crossings.push((t, intersection));
+        }
+        crossings
+    }
+}
+
+#[test]
+fn csg_lens_and_cut() {
+    use super::sphere::Sphere;
+    use material::Material;
+    let sphere = |z: f32| Sphere { position: Point3::new(0., 0., z), radius: 1., material: Material::jade(), object_id: 0 };
+    // two overlapping spheres, the lens in between is from z 4.5 to 5.5
+    let lens = Csg::new(Operation::Intersection, sphere(4.5), sphere(5.5));
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = lens.intersect(&mut ray).expect("hit the lens");
+    assert!((ray.distance - 4.5).abs() < 1e-5 && !intersection.inside);
+    assert_eq!(intersection.normal, Vector3::new(0., 0., -1.));
+    assert_eq!(lens.crossings(&ray).len(), 2);
+    // the ray leaves through the back of the lens, the surface of the first sphere
+    let mut ray = Ray::new(Point3::new(0., 0., 5.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = lens.intersect(&mut ray).expect("leave the lens");
+    assert!((ray.distance - 0.5).abs() < 1e-5 && intersection.inside);
+    // without the second sphere the first is hollowed out, its inside faces the ray
+    let cut = Csg::new(Operation::Difference, sphere(4.5), sphere(5.5));
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(cut.intersect(&mut ray).is_some() && (ray.distance - 3.5).abs() < 1e-5);
+    let mut ray = Ray::new(Point3::new(0., 0., 4.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = cut.intersect(&mut ray).expect("hit the cut");
+    assert!((ray.distance - 0.5).abs() < 1e-5 && intersection.inside);
+    assert_eq!(intersection.normal, Vector3::new(0., 0., 1.));
+    // and together they are a capsule with the lens inside it
+    let union = Csg::new(Operation::Union, Csg::new(Operation::Union, sphere(4.5), sphere(5.5)), lens);
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let crossings = union.crossings(&ray);
+    assert_eq!(crossings.len(), 2);
+    assert!((crossings[0].0 - 3.5).abs() < 1e-5 && (crossings[1].0 - 6.5).abs() < 1e-5);
+}
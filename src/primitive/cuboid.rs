@@ -0,0 +1,150 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+use super::{Primitive, Solid};
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+/// An axis aligned box, turn it with an instance
+#[derive(Debug, Clone)]
+pub struct Cuboid {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+// the axis of a face, and whether it is on the max side or the min side
+type Face = (usize, bool);
+
+impl Cuboid {
+    // outward normal of the face on the axis, on the max side or the min side
+    fn normal(axis: usize, max: bool) -> Vector3<f32> {
+        let mut normal = Vector3::new(0., 0., 0.);
+        normal[axis] = if max { 1. } else { -1. };
+        normal
+    }
+    // where the line of the ray enters and leaves the slabs of the box, and through which face
+    fn slabs(&self, ray: &Ray) -> Option<((f32, Face), (f32, Face))> {
+        let (mut t_enter, mut t_exit) = (-f32::INFINITY, f32::INFINITY);
+        let (mut enter, mut exit) = ((0, false), (0, false));
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - ray.origin[axis]) / ray.direction[axis];
+            let t1 = (self.max[axis] - ray.origin[axis]) / ray.direction[axis];
+            // the ray enters through the min side when it goes up the axis
+            let (near, far, near_max) = if t0 <= t1 { (t0, t1, false) } else { (t1, t0, true) };
+            if near > t_enter {
+                t_enter = near;
+                enter = (axis, near_max);
+            }
+            if far < t_exit {
+                t_exit = far;
+                exit = (axis, !near_max);
+            }
+        }
+        if t_enter > t_exit { None } else { Some(((t_enter, enter), (t_exit, exit))) }
+    }
+    fn face(&self, ray: &Ray, t: f32, (axis, max): Face, inside: bool) -> Intersection {
+        // the position on the face along the other two axes
+        let point = ray.origin + t * ray.direction;
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let uv = Point2::new((point[a] - self.min[a]) / (self.max[a] - self.min[a]),
+                             (point[b] - self.min[b]) / (self.max[b] - self.min[b]));
+        let normal = Cuboid::normal(axis, max);
+        Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            uv: uv,
+            tangent: None,
+            inside: inside,
+            area: self.area(),
+            object_id: self.object_id,
+            material: &self.material,
+        }
+    }
+}
+
+impl Primitive for Cuboid {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let ((t_enter, enter), (t_exit, exit)) = match self.slabs(ray) {
+            Some(slabs) => slabs,
+            None => return None,
+        };
+        if t_exit < 0. {
+            return None;
+        }
+        let (t, face, inside) = if t_enter >= 0. { (t_enter, enter, false) } else { (t_exit, exit, true) };
+        if t >= ray.distance {
+            return None;
+        }
+        ray.distance = t;
+        Some(self.face(ray, t, face, inside))
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.min + 0.5 * (self.max - self.min)
+    }
+    fn bounds(&self) -> AABB {
+        AABB {
+            min: self.min,
+            max: self.max,
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match self.material {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        // a face with a chance of its area, then a point on it
+        let size = self.max - self.min;
+        let faces = [size.y * size.z, size.x * size.z, size.x * size.y];
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let mut pick = r0 * (faces[0] + faces[1] + faces[2]);
+        let mut axis = 0;
+        while axis < 2 && pick > faces[axis] {
+            pick -= faces[axis];
+            axis += 1;
+        }
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let Closed01(r2) = random::<Closed01<f32>>();
+        let Closed01(r3) = random::<Closed01<f32>>();
+        let mut point = self.min + Vector3::new(r1 * size.x, r2 * size.y, r3 * size.z);
+        point[axis] = if random::<bool>() { self.min[axis] } else { self.max[axis] };
+        point
+    }
+    fn area(&self) -> f32 {
+        let size = self.max - self.min;
+        2. * (size.x * size.y + size.x * size.z + size.y * size.z)
+    }
+}
+
+impl Solid for Cuboid {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        match self.slabs(ray) {
+            Some(((t_enter, enter), (t_exit, exit))) => vec![(t_enter, self.face(ray, t_enter, enter, false)), (t_exit, self.face(ray, t_exit, exit, true))],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn intersections_cuboid() {
+    use self::cgmath::InnerSpace;
+    let cuboid = Cuboid { min: Point3::new(-1., -1., 1.), max: Point3::new(1., 1., 3.), material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = cuboid.intersect(&mut ray).expect("hit the front");
+    assert!(!intersection.inside && (ray.distance - 1.).abs() < 1e-5);
+    assert_eq!(intersection.normal, Vector3::new(0., 0., -1.));
+    // from the inside the ray leaves through the back
+    let mut ray = Ray::new(Point3::new(0., 0., 2.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = cuboid.intersect(&mut ray).expect("hit the back");
+    assert!(intersection.inside && intersection.normal.dot(ray.direction) > 0.);
+    let mut ray = Ray::new(Point3::new(2., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(cuboid.intersect(&mut ray).is_none());
+}
@@ -0,0 +1,328 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace, EuclideanSpace};
+use std::f32;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+// at most this many times the subdivision of a segment is halved to find the hit
+const MAX_DEPTH: i32 = 10;
+// the longest segment in widths, and the most segments a curve is split into
+const SEGMENT_LENGTH: f32 = 8.;
+const MAX_SEGMENTS: u32 = 16;
+
+/// How the width of a curve faces the rays
+#[derive(Debug, Copy, Clone)]
+pub enum CurveKind {
+    // a flat strip that always faces the ray
+    Flat,
+    // a flat strip with a normal at the start and at the end, like a blade of grass
+    Ribbon([Vector3<f32>; 2]),
+    // a tube, it faces the ray like a flat strip but the normal bends around it across the width
+    Round,
+}
+
+/// A cubic Bézier curve with its width changing from the start to the end, shared by its segments
+#[derive(Debug, Clone)]
+pub struct Bezier {
+    pub points: [Point3<f32>; 4],
+    pub width: [f32; 2],
+    pub kind: CurveKind,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+/// A part of a Bézier curve between two parameters, long thin curves are split into segments
+/// so their bounds in the BVH stay tight. It is never sampled as a light.
+#[derive(Debug, Clone)]
+pub struct Curve {
+    pub bezier: Arc<Bezier>,
+    pub u_min: f32,
+    pub u_max: f32,
+}
+
+impl Bezier {
+    pub fn width(&self, u: f32) -> f32 {
+        (1. - u) * self.width[0] + u * self.width[1]
+    }
+    fn max_width(&self, u_min: f32, u_max: f32) -> f32 {
+        self.width(u_min).max(self.width(u_max))
+    }
+}
+
+impl Curve {
+    /// the curve in segments no longer than a few times its width
+    pub fn split(bezier: Bezier) -> Vec<Curve> {
+        let p = &bezier.points;
+        let length = (p[1] - p[0]).magnitude() + (p[2] - p[1]).magnitude() + (p[3] - p[2]).magnitude();
+        let width = bezier.max_width(0., 1.).max(f32::EPSILON);
+        let segments = ((length / (SEGMENT_LENGTH * width)).ceil() as u32).max(1).min(MAX_SEGMENTS);
+        let bezier = Arc::new(bezier);
+        (0..segments).map(|i| Curve {
+            bezier: bezier.clone(),
+            u_min: i as f32 / segments as f32,
+            u_max: (i + 1) as f32 / segments as f32,
+        }).collect()
+    }
+
+    /// a smooth strand through the points, a Catmull-Rom spline turned into Bézier curves,
+    /// getting thinner or wider from the width at the root to the width at the tip
+    pub fn strand(points: &[Point3<f32>], width: [f32; 2], kind: CurveKind, material: Material, object_id: u32) -> Vec<Curve> {
+        let mut curves = Vec::new();
+        if points.len() < 2 {
+            return curves;
+        }
+        let last = points.len() - 1;
+        let width_at = |i: usize| width[0] + (width[1] - width[0]) * i as f32 / last as f32;
+        for i in 0..last {
+            let previous = points[if i > 0 { i - 1 } else { 0 }];
+            let next = points[(i + 2).min(last)];
+            curves.extend(Curve::split(Bezier {
+                points: [points[i], points[i] + (points[i + 1] - previous) / 6., points[i + 1] + (points[i] - next) / 6., points[i + 1]],
+                width: [width_at(i), width_at(i + 1)],
+                kind: kind,
+                material: material,
+                object_id: object_id,
+            }));
+        }
+        curves
+    }
+
+    pub fn load(path: &Path, kind: CurveKind, material: Material) -> Result<Vec<Curve>, io::Error> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        Curve::parse(&text, kind, material)
+    }
+
+    /// One strand per line, `root_width tip_width x y z x y z ...` with at least two points
+    /// from the root to the tip. Everything after a `#` is a comment. Every strand gets its own id.
+    pub fn parse(text: &str, kind: CurveKind, material: Material) -> Result<Vec<Curve>, io::Error> {
+        let mut curves = Vec::new();
+        let mut object_id = 0;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid strand on line {}", number + 1));
+            if fields.len() < 8 || (fields.len() - 2) % 3 != 0 {
+                return Err(invalid());
+            }
+            let mut values = Vec::with_capacity(fields.len());
+            for field in &fields {
+                values.push(try!(field.parse::<f32>().map_err(|_| invalid())));
+            }
+            let points: Vec<_> = values[2..].chunks(3).map(|p| Point3::new(p[0], p[1], p[2])).collect();
+            curves.extend(Curve::strand(&points, [values[0], values[1]], kind, material, object_id));
+            object_id += 1;
+        }
+        Ok(curves)
+    }
+
+    fn control_points(&self) -> [Vector3<f32>; 4] {
+        let p = &self.bezier.points;
+        sub_curve(&[p[0].to_vec(), p[1].to_vec(), p[2].to_vec(), p[3].to_vec()], self.u_min, self.u_max)
+    }
+
+    // the width at the parameter as seen along the direction
+    fn hit_width(&self, u: f32, direction: Vector3<f32>) -> f32 {
+        let width = self.bezier.width(u);
+        match self.bezier.kind {
+            CurveKind::Ribbon(normals) => width * ribbon_normal(normals, u).dot(direction).abs(),
+            _ => width,
+        }
+    }
+
+    /// Closest hit of the ray with the control points in ray space, where the ray starts at
+    /// the origin along z. The curve is halved until it is nearly straight, then the hit is
+    /// the closest point on the segment between its ends. The hit is the nearest distance a
+    /// hit can be at, the distance to beat and the parameter along the curve once there is one.
+    fn intersect_segment(&self, cp: &[Vector3<f32>; 4], u0: f32, u1: f32, depth: i32, direction: Vector3<f32>, hit: &mut (f32, f32, Option<f32>)) {
+        let half_width = 0.5 * self.bezier.max_width(u0, u1);
+        let (mut min, mut max) = (cp[0], cp[0]);
+        for p in &cp[1..] {
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        if min.x - half_width > 0. || max.x + half_width < 0. || min.y - half_width > 0. || max.y + half_width < 0. ||
+           min.z - half_width > hit.1 || max.z + half_width < hit.0 {
+            return;
+        }
+        if depth > 0 {
+            let middle = 0.5 * (u0 + u1);
+            self.intersect_segment(&sub_curve(cp, 0., 0.5), u0, middle, depth - 1, direction, hit);
+            self.intersect_segment(&sub_curve(cp, 0.5, 1.), middle, u1, depth - 1, direction, hit);
+            return;
+        }
+        // the origin has to be between the perpendiculars at both ends
+        if (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x) < 0. ||
+           (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x) < 0. {
+            return;
+        }
+        let segment = Vector3::new(cp[3].x - cp[0].x, cp[3].y - cp[0].y, 0.);
+        let denominator = segment.magnitude2();
+        if denominator == 0. {
+            return;
+        }
+        let w = -(cp[0].x * segment.x + cp[0].y * segment.y) / denominator;
+        let u = (u0 + w * (u1 - u0)).max(u0).min(u1);
+        let hit_width = self.hit_width(u, direction);
+        let (point, _) = evaluate(cp, w.max(0.).min(1.));
+        if point.x * point.x + point.y * point.y > 0.25 * hit_width * hit_width {
+            return;
+        }
+        if point.z < hit.0 || point.z > hit.1 {
+            return;
+        }
+        hit.1 = point.z;
+        hit.2 = Some(u);
+    }
+}
+
+impl Primitive for Curve {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        // ray space, the ray starts at the origin and goes along z
+        let d = ray.direction;
+        let x = d.cross(if d.x.abs() > 0.9 { Vector3::new(0., 1., 0.) } else { Vector3::new(1., 0., 0.) }).normalize();
+        let y = d.cross(x);
+        let world = self.control_points();
+        let mut cp = world;
+        for p in cp.iter_mut() {
+            let offset = *p - ray.origin.to_vec();
+            *p = Vector3::new(offset.dot(x), offset.dot(y), offset.dot(d));
+        }
+        // halve until the curve is within a twentieth of its width from straight
+        let mut l0: f32 = 0.;
+        for i in 0..2 {
+            let second = cp[i] - 2. * cp[i + 1] + cp[i + 2];
+            l0 = l0.max(second.x.abs()).max(second.y.abs()).max(second.z.abs());
+        }
+        let epsilon = 0.05 * self.bezier.max_width(self.u_min, self.u_max);
+        let depth = if l0 > 0. && epsilon > 0. {
+            ((2f32.sqrt() * 6. * l0 / (8. * epsilon)).log2() / 2.).ceil().max(0.).min(MAX_DEPTH as f32) as i32
+        } else {
+            0
+        };
+        // a ray that leaves the curve starts off by the rounding of its origin and by how far the
+        // straight pieces are from the curve, hits closer than that are the curve it starts from
+        let scale = ray.origin.x.abs().max(ray.origin.y.abs()).max(ray.origin.z.abs()).max(1.);
+        let near = epsilon.max(20. * f32::EPSILON * scale);
+        let mut hit = (near, ray.distance, None);
+        self.intersect_segment(&cp, self.u_min, self.u_max, depth, d, &mut hit);
+        let (t, u) = match hit {
+            (_, t, Some(u)) => (t, u),
+            (_, _, None) => return None,
+        };
+        ray.distance = t;
+        let p = &self.bezier.points;
+        let (centre, derivative) = evaluate(&[p[0].to_vec(), p[1].to_vec(), p[2].to_vec(), p[3].to_vec()], u);
+        let tangent = derivative.normalize();
+        // towards the ray across the curve, and the offset of the hit from the middle across the width
+        let towards = -d - (-d).dot(tangent) * tangent;
+        let facing = if towards.magnitude2() > 0. { towards.normalize() } else { x };
+        let across = facing.cross(tangent);
+        let h = ((ray.intersection().to_vec() - centre).dot(across) / (0.5 * self.hit_width(u, d))).max(-1.).min(1.);
+        let normal = match self.bezier.kind {
+            CurveKind::Flat => facing,
+            CurveKind::Ribbon(normals) => {
+                let normal = ribbon_normal(normals, u);
+                if normal.dot(d) > 0. { -normal } else { normal }
+            },
+            CurveKind::Round => (1. - h * h).max(0.).sqrt() * facing + h * across,
+        };
+        Some(Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            // along the curve and across the width
+            uv: Point2::new(u, 0.5 * (h + 1.)),
+            tangent: Some(tangent),
+            inside: false,
+            // a light that is never sampled gets all of the weight when it is hit
+            area: f32::INFINITY,
+            object_id: self.bezier.object_id,
+            material: &self.bezier.material,
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        let p = &self.bezier.points;
+        let (centre, _) = evaluate(&[p[0].to_vec(), p[1].to_vec(), p[2].to_vec(), p[3].to_vec()], 0.5 * (self.u_min + self.u_max));
+        Point3::from_vec(centre)
+    }
+    fn bounds(&self) -> AABB {
+        let half_width = 0.5 * self.bezier.max_width(self.u_min, self.u_max);
+        let bounds = self.control_points().iter().fold(AABB::new(), |bounds, &p| bounds.extent(&Point3::from_vec(p)));
+        AABB {
+            min: bounds.min + Vector3::new(-half_width, -half_width, -half_width),
+            max: bounds.max + Vector3::new(half_width, half_width, half_width),
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        None
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        self.centre()
+    }
+    fn area(&self) -> f32 {
+        f32::INFINITY
+    }
+}
+
+fn ribbon_normal(normals: [Vector3<f32>; 2], u: f32) -> Vector3<f32> {
+    ((1. - u) * normals[0] + u * normals[1]).normalize()
+}
+
+// the point and the derivative of a cubic Bézier curve
+fn evaluate(p: &[Vector3<f32>; 4], u: f32) -> (Vector3<f32>, Vector3<f32>) {
+    let lerp = |a: Vector3<f32>, b: Vector3<f32>| (1. - u) * a + u * b;
+    let (a, b, c) = (lerp(p[0], p[1]), lerp(p[1], p[2]), lerp(p[2], p[3]));
+    let (d, e) = (lerp(a, b), lerp(b, c));
+    (lerp(d, e), 3. * (e - d))
+}
+
+// the control points of the part of the curve between the parameters, by blossoming
+fn sub_curve(p: &[Vector3<f32>; 4], u0: f32, u1: f32) -> [Vector3<f32>; 4] {
+    let blossom = |u: [f32; 3]| {
+        let lerp = |a: Vector3<f32>, b: Vector3<f32>, u: f32| (1. - u) * a + u * b;
+        let (a, b, c) = (lerp(p[0], p[1], u[0]), lerp(p[1], p[2], u[0]), lerp(p[2], p[3], u[0]));
+        let (d, e) = (lerp(a, b, u[1]), lerp(b, c, u[1]));
+        lerp(d, e, u[2])
+    };
+    [blossom([u0, u0, u0]), blossom([u0, u0, u1]), blossom([u0, u1, u1]), blossom([u1, u1, u1])]
+}
+
+#[test]
+fn intersections_curve() {
+    // a strand bending up along x in front of the origin, thinning out
+    let curves = Curve::parse("# root tip points\n0.2 0.1  -2 0 5  -1 0.2 5  1 0.2 5  2 0 5\n", CurveKind::Round, Material::hair(1.3, 0.)).expect("parse strand");
+    assert!(curves.len() > 3);
+    let bounds = curves.iter().fold(AABB::new(), |bounds, curve| bounds.combine(&curve.bounds()));
+    assert!(bounds.min.x < -2. && bounds.max.x > 2. && bounds.max.z - bounds.min.z < 0.3);
+    let closest = |ray: &mut Ray| curves.iter().fold(None, |hit, curve| curve.intersect(ray).map(|i| (i.normal, i.uv, i.tangent)).or(hit));
+    // through the middle the round curve faces the ray and runs along x
+    let mut ray = Ray::new(Point3::new(0., 0.225, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let (normal, uv, tangent) = closest(&mut ray).expect("hit the strand");
+    assert!((ray.distance - 5.).abs() < 1e-3 && (uv.x - 0.5).abs() < 0.05);
+    assert!(normal.z < -0.9 && tangent.expect("tangent along the curve").x.abs() > 0.99);
+    // off its middle the normal of the tube leans across
+    let mut ray = Ray::new(Point3::new(0., 0.28, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let (normal, uv, _) = closest(&mut ray).expect("hit the side");
+    assert!(normal.y.abs() > 0.3 && (uv.y - 0.5).abs() > 0.15);
+    // beside the width
+    let mut ray = Ray::new(Point3::new(0., 0.35, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(closest(&mut ray).is_none());
+    // a ray starting closer than the width of the strand still hits it, one leaving it doesn't
+    let mut ray = Ray::new(Point3::new(0., 0.225, 4.9), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(closest(&mut ray).is_some() && (ray.distance - 0.1).abs() < 1e-3);
+    let mut ray = Ray::new(Point3::new(0., 0.225, 5.), Vector3::new(0., 0.6, -0.8), f32::INFINITY);
+    assert!(closest(&mut ray).is_none());
+    assert!(Curve::parse("0.1 0.1 0 0 0", CurveKind::Flat, Material::hair(1.3, 0.)).is_err());
+}
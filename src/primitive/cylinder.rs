@@ -0,0 +1,157 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+use super::{Primitive, Solid, solve_quadratic};
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+/// A closed cylinder standing upright on the centre of its bottom cap, turn it with an instance
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    pub position: Point3<f32>,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Cylinder {
+    // every hit of the line of the ray with the side and the caps, with the normal and the uv
+    fn hits<F: FnMut(f32, Vector3<f32>, Point2<f32>)>(&self, ray: &Ray, mut hit: F) {
+        let origin = ray.origin - self.position;
+        let direction = ray.direction;
+        let a = direction.x * direction.x + direction.z * direction.z;
+        let b = 2. * (origin.x * direction.x + origin.z * direction.z);
+        let c = origin.x * origin.x + origin.z * origin.z - self.radius * self.radius;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            for &t in &[t0, t1] {
+                let point = origin + t * direction;
+                if point.y >= 0. && point.y <= self.height {
+                    let u = 0.5 + point.z.atan2(point.x) * 0.5 * f32::consts::FRAC_1_PI;
+                    hit(t, Vector3::new(point.x, 0., point.z) / self.radius, Point2::new(u, point.y / self.height));
+                }
+            }
+        }
+        for &(y, normal_y) in &[(0., -1.), (self.height, 1.)] {
+            let t = (y - origin.y) / direction.y;
+            let point = origin + t * direction;
+            if point.x * point.x + point.z * point.z <= self.radius * self.radius {
+                let u = 0.5 + point.z.atan2(point.x) * 0.5 * f32::consts::FRAC_1_PI;
+                hit(t, Vector3::new(0., normal_y, 0.), Point2::new(u, y / self.height));
+            }
+        }
+    }
+    fn surface(&self, normal: Vector3<f32>, uv: Point2<f32>, direction: Vector3<f32>) -> Intersection {
+        Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            uv: uv,
+            tangent: None,
+            inside: normal.dot(direction) > 0.,
+            area: self.area(),
+            object_id: self.object_id,
+            material: &self.material,
+        }
+    }
+}
+
+impl Primitive for Cylinder {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        // the closest of the side and the caps, with the normal and the uv
+        let mut closest: Option<(f32, Vector3<f32>, Point2<f32>)> = None;
+        self.hits(ray, |t, normal, uv| {
+            if t >= 0. && t < ray.distance && closest.map_or(true, |(closest, _, _)| t < closest) {
+                closest = Some((t, normal, uv));
+            }
+        });
+        closest.map(move |(t, normal, uv)| {
+            ray.distance = t;
+            self.surface(normal, uv, ray.direction)
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.position + Vector3::new(0., 0.5 * self.height, 0.)
+    }
+    fn bounds(&self) -> AABB {
+        AABB {
+            min: self.position + Vector3::new(-self.radius, 0., -self.radius),
+            max: self.position + Vector3::new(self.radius, self.height, self.radius),
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match self.material {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let Closed01(r2) = random::<Closed01<f32>>();
+        let phi = 2. * f32::consts::PI * r1;
+        let side = 2. * f32::consts::PI * self.radius * self.height;
+        let offset = if r0 * self.area() < side {
+            Vector3::new(self.radius * phi.cos(), r2 * self.height, self.radius * phi.sin())
+        } else {
+            // one of the caps
+            let r = self.radius * r2.sqrt();
+            Vector3::new(r * phi.cos(), if random::<bool>() { 0. } else { self.height }, r * phi.sin())
+        };
+        self.position + offset
+    }
+    fn area(&self) -> f32 {
+        2. * f32::consts::PI * self.radius * (self.height + self.radius)
+    }
+}
+
+impl Solid for Cylinder {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        // it is convex, so the line goes in at the first hit and out at the last, a hit on the
+        // rim of a cap is on both the side and the cap and only counts once
+        let mut first: Option<(f32, Vector3<f32>, Point2<f32>)> = None;
+        let mut last = first;
+        self.hits(ray, |t, normal, uv| {
+            if first.map_or(true, |(first, _, _)| t < first) {
+                first = Some((t, normal, uv));
+            }
+            if last.map_or(true, |(last, _, _)| t > last) {
+                last = Some((t, normal, uv));
+            }
+        });
+        match (first, last) {
+            (Some(first), Some(last)) if first.0 < last.0 => {
+                [first, last].iter().map(|&(t, normal, uv)| (t, self.surface(normal, uv, ray.direction))).collect()
+            }
+            // only touching the rim
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn intersections_cylinder() {
+    let cylinder = Cylinder { position: Point3::new(0., -1., 3.), radius: 1., height: 2., material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = cylinder.intersect(&mut ray).expect("hit the side");
+    assert!((ray.distance - 2.).abs() < 1e-5 && (intersection.normal - Vector3::new(0., 0., -1.)).magnitude() < 1e-5);
+    let mut ray = Ray::new(Point3::new(0.5, 5., 3.), Vector3::new(0., -1., 0.), f32::INFINITY);
+    let intersection = cylinder.intersect(&mut ray).expect("hit the top cap");
+    assert!((ray.distance - 4.).abs() < 1e-5 && intersection.normal == Vector3::new(0., 1., 0.));
+    // from inside the ray leaves through the bottom cap
+    let mut ray = Ray::new(Point3::new(0.5, 0., 3.), Vector3::new(0., -1., 0.), f32::INFINITY);
+    assert!(cylinder.intersect(&mut ray).expect("hit the bottom cap").inside);
+    let mut ray = Ray::new(Point3::new(0., 1.5, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(cylinder.intersect(&mut ray).is_none());
+    // in through the rim of the top cap, on both the side and the cap, and out through the side
+    let cylinder = Cylinder { position: Point3::new(0., 0., 0.), radius: 1., height: 2., material: Material::jade(), object_id: 0 };
+    let ray = Ray::new(Point3::new(-3., 3., 0.), Vector3::new(2., -1., 0.).normalize(), f32::INFINITY);
+    let crossings = cylinder.crossings(&ray);
+    assert_eq!(crossings.len(), 2);
+    assert!(!crossings[0].1.inside && crossings[1].1.inside);
+    assert!((crossings[1].1.normal - Vector3::new(1., 0., 0.)).magnitude() < 1e-5);
+}
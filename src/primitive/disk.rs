@@ -0,0 +1,95 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+use integrator::from_tangent_to_local;
+
+/// A flat disk around `position` facing along its normal
+#[derive(Debug, Clone)]
+pub struct Disk {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub radius: f32,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Primitive for Disk {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let cos = ray.direction.dot(self.normal);
+        if cos.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = (self.position - ray.origin).dot(self.normal) / cos;
+        if t < 0. || t >= ray.distance {
+            return None;
+        }
+        let offset = ray.origin + t * ray.direction - self.position;
+        if offset.magnitude2() > self.radius * self.radius {
+            return None;
+        }
+        ray.distance = t;
+        // distance from the centre and angle around it
+        let x = offset.dot(from_tangent_to_local(&self.normal, &Vector3::new(1., 0., 0.)));
+        let y = offset.dot(from_tangent_to_local(&self.normal, &Vector3::new(0., 1., 0.)));
+        let uv = Point2::new(offset.magnitude() / self.radius, 0.5 + y.atan2(x) * 0.5 * f32::consts::FRAC_1_PI);
+        Some(Intersection {
+            normal: self.normal,
+            geometric_normal: self.normal,
+            uv: uv,
+            tangent: None,
+            inside: cos > 0.,
+            area: self.area(),
+            object_id: self.object_id,
+            material: &self.material,
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.position
+    }
+    fn bounds(&self) -> AABB {
+        // the disk reaches less far along the axes it faces
+        let extent = |n: f32| self.radius * (1. - n * n).max(0.).sqrt() + 1e-4;
+        let extent = Vector3::new(extent(self.normal.x), extent(self.normal.y), extent(self.normal.z));
+        AABB {
+            min: self.position + -extent,
+            max: self.position + extent,
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match self.material {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        let r = self.radius * r0.sqrt();
+        let phi = 2. * f32::consts::PI * r1;
+        self.position + from_tangent_to_local(&self.normal, &Vector3::new(r * phi.cos(), r * phi.sin(), 0.))
+    }
+    fn area(&self) -> f32 {
+        f32::consts::PI * self.radius * self.radius
+    }
+}
+
+#[test]
+fn intersections_disk() {
+    let disk = Disk { position: Point3::new(0., 0., 2.), normal: Vector3::new(0., 0., -1.), radius: 1., material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(0.5, 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = disk.intersect(&mut ray).expect("hit inside the radius");
+    assert!(!intersection.inside && (intersection.uv.x - 0.5).abs() < 1e-5);
+    let mut ray = Ray::new(Point3::new(0.8, 0.8, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(disk.intersect(&mut ray).is_none());
+    for _ in 0..100 {
+        assert!((disk.random_point(0.) - disk.position).magnitude() <= 1. + 1e-5);
+    }
+}
@@ -0,0 +1,147 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Vector3, Matrix, Matrix4, SquareMatrix, InnerSpace};
+use self::cgmath::Transform as TransformTrait;
+use std::f32;
+use std::sync::Arc;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use bvh::BVH;
+use material::Material;
+use ray::{Ray, Intersection};
+use transform::transform_bounds;
+
+/// A placement of a mesh with its own bottom level BVH, many instances share one mesh.
+/// A BVH over instances is the top level, rays go into the space of the mesh to traverse it.
+/// An instance of a single light is a light itself, other lights in instances are only hit.
+#[derive(Debug)]
+pub struct Instance<T: Primitive> {
+    pub mesh: Arc<BVH<T>>,
+    transform: Matrix4<f32>,
+    inverse: Matrix4<f32>,
+    bounds: AABB,
+    // replaces the materials of the mesh
+    material: Option<Material>,
+    // growth of areas, exact for uniform scales
+    area_scale: f32,
+    light: bool,
+    // added to the object ids of the mesh, keeps them apart from the ids of other scenes
+    pub object_id_offset: u32,
+}
+
+impl<T: Primitive> Instance<T> {
+    /// places the mesh with an affine transform, None when it can't be inverted
+    pub fn new(mesh: Arc<BVH<T>>, transform: Matrix4<f32>) -> Option<Instance<T>> {
+        let inverse = match transform.invert() {
+            Some(inverse) => inverse,
+            None => return None,
+        };
+        let bounds = transform_bounds(&transform, &mesh.bounds());
+        let light = mesh.objects().len() == 1 && mesh.objects()[0].is_light().is_some();
+        Some(Instance {
+            mesh: mesh,
+            transform: transform,
+            inverse: inverse,
+            bounds: bounds,
+            material: None,
+            area_scale: transform.determinant().abs().powf(2. / 3.),
+            light: light,
+            object_id_offset: 0,
+        })
+    }
+
+    /// the instance with one material for the whole mesh
+    pub fn with_material(self, material: Material) -> Instance<T> {
+        let light = self.mesh.objects().len() == 1 && match material {
+            Material::Emissive { .. } => true,
+            _ => false,
+        };
+        Instance {
+            material: Some(material),
+            light: light,
+            ..self
+        }
+    }
+
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+}
+
+impl<T: Primitive> Primitive for Instance<T> {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        // the primitives expect a normalized direction, distances change with the length of the direction
+        let direction = self.inverse.transform_vector(ray.direction);
+        let scale = direction.magnitude();
+        let mut local = Ray::new(self.inverse.transform_point(ray.origin), direction / scale, ray.distance * scale).with_time(ray.time);
+        let intersection = match self.mesh.intersect_closest(&mut local) {
+            Some(intersection) => intersection,
+            None => return None,
+        };
+        ray.distance = local.distance / scale;
+        // normals go through the inverse transpose to stay perpendicular under non-uniform scale
+        let normal_matrix = self.inverse.transpose();
+        let material = match self.material {
+            Some(ref material) => material,
+            None => intersection.material,
+        };
+        let area = match *material {
+            // lights that are never sampled get all of the weight when they are hit
+            Material::Emissive { .. } if !self.light => f32::INFINITY,
+            _ => intersection.area * self.area_scale,
+        };
+        Some(Intersection {
+            normal: normal_matrix.transform_vector(intersection.normal).normalize(),
+            geometric_normal: normal_matrix.transform_vector(intersection.geometric_normal).normalize(),
+            tangent: intersection.tangent.map(|tangent| self.transform.transform_vector(tangent).normalize()),
+            area: area,
+            object_id: intersection.object_id + self.object_id_offset,
+            material: material,
+            ..intersection
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        let bounds = self.mesh.bounds();
+        self.transform.transform_point(bounds.min + 0.5 * (bounds.max - bounds.min))
+    }
+    fn bounds(&self) -> AABB {
+        self.bounds
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match (self.light, self.material) {
+            (true, Some(Material::Emissive { color })) => Some(color),
+            (true, _) => self.mesh.objects()[0].is_light(),
+            _ => None,
+        }
+    }
+    fn random_point(&self, time: f32) -> Point3<f32> {
+        match self.mesh.random_light() {
+            Some((_, light)) => self.transform.transform_point(light.random_point(time)),
+            None => self.centre(),
+        }
+    }
+    fn area(&self) -> f32 {
+        self.mesh.objects().iter().map(|object| object.area()).sum::<f32>() * self.area_scale
+    }
+}
+
+#[test]
+fn instance_hit_in_object_space() {
+    use self::cgmath::Vector3;
+    use primitive::sphere::Sphere;
+    let mesh = Arc::new(BVH::new(vec![Sphere::light(Point3::new(0., 0., 0.), 1.)]));
+    // stretched to twice the size along x and moved to x = 5
+    let transform = Matrix4::from_translation(Vector3::new(5., 0., 0.)) * Matrix4::from_nonuniform_scale(2., 1., 1.);
+    let instance = Instance::new(mesh, transform).unwrap();
+    assert!(instance.bounds().min.x <= 3. && instance.bounds().max.x >= 7.);
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(1., 0., 0.), f32::INFINITY);
+    let intersection = instance.intersect(&mut ray).expect("hit the stretched sphere");
+    assert!(instance.is_light().is_some() && intersection.area.is_finite());
+    assert!((ray.distance - 3.).abs() < 1e-4);
+    assert!((intersection.normal - Vector3::new(-1., 0., 0.)).magnitude() < 1e-4);
+    // a ray that only hits the sphere where it was before the transform misses
+    let mut ray = Ray::new(Point3::new(0., 0., -5.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(instance.intersect(&mut ray).is_none());
+    assert!(Instance::new(instance.mesh.clone(), Matrix4::from_scale(0.)).is_none());
+}
@@ -4,6 +4,20 @@ use self::cgmath::{Point3,Vector3};
 pub mod aabb;
 pub mod sphere;
 pub mod triangle;
+pub mod triangle_mesh;
+pub mod moving;
+pub mod instance;
+pub mod shape;
+pub mod plane;
+pub mod disk;
+pub mod quad;
+pub mod cuboid;
+pub mod cylinder;
+pub mod cone;
+pub mod torus;
+pub mod sdf;
+pub mod csg;
+pub mod curve;
 
 use self::aabb::AABB;
 
@@ -14,6 +28,97 @@ pub trait Primitive {
     fn centre(&self) -> Point3<f32>;
     fn bounds(&self) -> AABB;
     fn is_light(&self) -> Option<Vector3<f32>>;
-    fn random_point(&self) -> Point3<f32>;
+    // a point on the primitive where it is at the given time
+    fn random_point(&self, time: f32) -> Point3<f32>;
     fn area(&self) -> f32;
 }
+
+/// A closed primitive, which has an inside, so it can be part of constructive solid geometry
+pub trait Solid: Primitive {
+    /// everywhere the line of the ray crosses the surface, also behind the origin, nearest
+    /// first and going in and out in turn, with the outward normals
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)>;
+}
+
+/// the first of the crossings in front of the ray and before its distance
+pub fn nearest<'m>(crossings: Vec<(f32, Intersection<'m>)>, ray: &mut Ray) -> Option<Intersection<'m>> {
+    crossings.into_iter().find(|&(t, _)| t >= 0. && t < ray.distance).map(|(t, intersection)| {
+        ray.distance = t;
+        intersection
+    })
+}
+
+/// both solutions of a x^2 + b x + c = 0, the smallest first, without cancellation
+pub fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    if a == 0. {
+        return if b == 0. { None } else { Some((-c / b, -c / b)) };
+    }
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0. { (0., 0.) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// real solutions of x^4 + a x^3 + b x^2 + c x + d = 0 with Ferrari's method, in double
+/// precision and polished with Newton's method, in no particular order
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    use std::f64;
+    // x = y - a / 4 leaves y^4 + p y^2 + q y + r = 0
+    let a2 = a * a;
+    let p = b - 3. / 8. * a2;
+    let q = c - 0.5 * a * b + a2 * a / 8.;
+    let r = d - 0.25 * a * c + a2 * b / 16. - 3. / 256. * a2 * a2;
+    let mut roots = Vec::with_capacity(4);
+    {
+        let mut quadratic = |b: f64, c: f64| {
+            let discriminant = b * b - 4. * c;
+            if discriminant >= 0. {
+                roots.push(0.5 * (-b + discriminant.sqrt()));
+                roots.push(0.5 * (-b - discriminant.sqrt()));
+            }
+        };
+        if q.abs() < 1e-12 {
+            // biquadratic, y^2 solves a quadratic
+            let discriminant = p * p - 4. * r;
+            if discriminant >= 0. {
+                for &z in &[0.5 * (-p + discriminant.sqrt()), 0.5 * (-p - discriminant.sqrt())] {
+                    if z >= 0. {
+                        quadratic(0., -z);
+                    }
+                }
+            }
+        } else {
+            // the largest root of the resolvent cubic m^3 + p m^2 + (p^2 / 4 - r) m - q^2 / 8 = 0 is positive
+            let (ca, cb, cc) = (p, 0.25 * p * p - r, -q * q / 8.);
+            let cq = (ca * ca - 3. * cb) / 9.;
+            let cr = (2. * ca * ca * ca - 9. * ca * cb + 27. * cc) / 54.;
+            let m = if cr * cr < cq * cq * cq {
+                let theta = (cr / (cq * cq * cq).sqrt()).max(-1.).min(1.).acos();
+                -2. * cq.sqrt() * ((theta + 2. * f64::consts::PI) / 3.).cos() - ca / 3.
+            } else {
+                let s = -cr.signum() * (cr.abs() + (cr * cr - cq * cq * cq).sqrt()).cbrt();
+                let t = if s == 0. { 0. } else { cq / s };
+                s + t - ca / 3.
+            };
+            let m = m.max(f64::EPSILON);
+            // (y^2 + p / 2 + m)^2 = (sqrt(2 m) y - q / (2 sqrt(2 m)))^2
+            let s = (2. * m).sqrt();
+            quadratic(-s, 0.5 * p + m + q / (2. * s));
+            quadratic(s, 0.5 * p + m - q / (2. * s));
+        }
+    }
+    roots.iter().map(|&y| {
+        let mut x = y - 0.25 * a;
+        for _ in 0..2 {
+            let f = (((x + a) * x + b) * x + c) * x + d;
+            let df = ((4. * x + 3. * a) * x + 2. * b) * x + c;
+            if df != 0. {
+                x -= f / df;
+            }
+        }
+        x
+    }).collect()
+}
@@ -0,0 +1,92 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Vector3, InnerSpace, Rotation};
+use self::cgmath::Transform as TransformTrait;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use transform::{Transform, interpolate, motion_bounds};
+
+/// A primitive that moves from its start transform to its end transform during a frame.
+/// Rays are intersected with the primitive where it is at their time.
+#[derive(Debug)]
+pub struct Moving<T: Primitive> {
+    pub primitive: T,
+    pub start: Transform,
+    pub end: Transform,
+}
+
+impl<T: Primitive> Moving<T> {
+    pub fn new(primitive: T, start: Transform, end: Transform) -> Moving<T> {
+        Moving {
+            primitive: primitive,
+            start: start,
+            end: end,
+        }
+    }
+
+    fn at(&self, time: f32) -> Transform {
+        interpolate(&self.start, &self.end, time.max(0.).min(1.))
+    }
+}
+
+impl<T: Primitive> Primitive for Moving<T> {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let transform = self.at(ray.time);
+        if transform.scale <= 0. {
+            return None;
+        }
+        let inverse = match transform.inverse_transform() {
+            Some(inverse) => inverse,
+            None => return None,
+        };
+        // the primitive expects a normalized direction, distances shrink with the scale
+        let mut local = Ray::new(inverse.transform_point(ray.origin), inverse.transform_vector(ray.direction).normalize(), ray.distance / transform.scale).with_time(ray.time);
+        let intersection = match self.primitive.intersect(&mut local) {
+            Some(intersection) => intersection,
+            None => return None,
+        };
+        ray.distance = local.distance * transform.scale;
+        Some(Intersection {
+            normal: transform.rot.rotate_vector(intersection.normal),
+            geometric_normal: transform.rot.rotate_vector(intersection.geometric_normal),
+            tangent: intersection.tangent.map(|tangent| transform.rot.rotate_vector(tangent)),
+            area: intersection.area * transform.scale * transform.scale,
+            ..intersection
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.at(0.5).transform_point(self.primitive.centre())
+    }
+    fn bounds(&self) -> AABB {
+        motion_bounds(&self.start, &self.end, &self.primitive.bounds())
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        self.primitive.is_light()
+    }
+    fn random_point(&self, time: f32) -> Point3<f32> {
+        self.at(time).transform_point(self.primitive.random_point(time))
+    }
+    fn area(&self) -> f32 {
+        // halfway through the motion, intersections know the area at their time
+        let scale = self.at(0.5).scale;
+        self.primitive.area() * scale * scale
+    }
+}
+
+#[test]
+fn moving_sphere_hit_at_time() {
+    use primitive::sphere::Sphere;
+    use transform::translation;
+    let sphere = Sphere::light(Point3::new(0., 0., 0.), 0.5);
+    let moving = Moving::new(sphere, translation(Vector3::new(0., 0., 0.)), translation(Vector3::new(2., 0., 0.)));
+    // a ray at x = 2 only hits the sphere at the end of the motion
+    let mut ray = Ray::new(Point3::new(2., 0., -5.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(moving.intersect(&mut ray).is_none());
+    let mut ray = ray.with_time(1.);
+    let intersection = moving.intersect(&mut ray).expect("hit at the end");
+    assert!((ray.distance - 4.5).abs() < 1e-4);
+    assert!((intersection.normal - Vector3::new(0., 0., -1.)).magnitude() < 1e-4);
+    assert!(moving.bounds().max.x >= 2.5 && moving.bounds().min.x <= -0.5);
+}
@@ -0,0 +1,84 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace};
+use std::f32;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+use integrator::from_tangent_to_local;
+
+// half the size of the bounds of a plane, the BVH can't hold an infinite box
+const EXTENT: f32 = 1e4;
+
+/// An infinite plane through `position` facing along its normal.
+/// It is never sampled as a light, an emissive plane only lights what sees it.
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Primitive for Plane {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let cos = ray.direction.dot(self.normal);
+        if cos.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = (self.position - ray.origin).dot(self.normal) / cos;
+        if t < 0. || t >= ray.distance {
+            return None;
+        }
+        ray.distance = t;
+        // scene units along two tangents
+        let offset = ray.intersection() - self.position;
+        let uv = Point2::new(offset.dot(from_tangent_to_local(&self.normal, &Vector3::new(1., 0., 0.))),
+                             offset.dot(from_tangent_to_local(&self.normal, &Vector3::new(0., 1., 0.))));
+        Some(Intersection {
+            normal: self.normal,
+            geometric_normal: self.normal,
+            uv: uv,
+            tangent: None,
+            inside: cos > 0.,
+            // a light that is never sampled gets all of the weight when it is hit
+            area: f32::INFINITY,
+            object_id: self.object_id,
+            material: &self.material,
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.position
+    }
+    fn bounds(&self) -> AABB {
+        // flat along the axis the plane faces, if it faces along one
+        let extent = |n: f32| if n.abs() > 0.999 { 1e-3 } else { EXTENT };
+        let extent = Vector3::new(extent(self.normal.x), extent(self.normal.y), extent(self.normal.z));
+        AABB {
+            min: self.position + -extent,
+            max: self.position + extent,
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        None
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        self.position
+    }
+    fn area(&self) -> f32 {
+        f32::INFINITY
+    }
+}
+
+#[test]
+fn intersections_plane() {
+    let plane = Plane { position: Point3::new(0., -1., 0.), normal: Vector3::new(0., 1., 0.), material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(100., 0., 50.), Vector3::new(0., -1., 0.), f32::INFINITY);
+    let intersection = plane.intersect(&mut ray).expect("hit from above");
+    assert!(!intersection.inside && (ray.distance - 1.).abs() < 1e-5);
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(1., 0., 0.), f32::INFINITY);
+    assert!(plane.intersect(&mut ray).is_none());
+    assert!(plane.bounds().min.x < -100. && plane.bounds().max.y < -0.99);
+}
@@ -0,0 +1,105 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace};
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::{Material, LIGHT_COLOR};
+
+/// A parallelogram from the corner `position` along two edges, a rectangle when they are
+/// perpendicular. It faces along the cross product of the edges.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    pub position: Point3<f32>,
+    pub edge0: Vector3<f32>,
+    pub edge1: Vector3<f32>,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Quad {
+    /// a rectangular area light
+    pub fn light(position: Point3<f32>, edge0: Vector3<f32>, edge1: Vector3<f32>) -> Quad {
+        Quad {
+            position: position,
+            edge0: edge0,
+            edge1: edge1,
+            material: Material::Emissive {
+                color: LIGHT_COLOR,
+            },
+            object_id: 0,
+        }
+    }
+}
+
+impl Primitive for Quad {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let cross = self.edge0.cross(self.edge1);
+        let normal = cross.normalize();
+        let cos = ray.direction.dot(normal);
+        if cos.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = (self.position - ray.origin).dot(normal) / cos;
+        if t < 0. || t >= ray.distance {
+            return None;
+        }
+        // coordinates of the hit along both edges
+        let offset = ray.origin + t * ray.direction - self.position;
+        let u = offset.cross(self.edge1).dot(cross) / cross.magnitude2();
+        let v = self.edge0.cross(offset).dot(cross) / cross.magnitude2();
+        if u < 0. || u > 1. || v < 0. || v > 1. {
+            return None;
+        }
+        ray.distance = t;
+        Some(Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            uv: Point2::new(u, v),
+            tangent: None,
+            inside: cos > 0.,
+            area: self.area(),
+            object_id: self.object_id,
+            material: &self.material,
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.position + 0.5 * (self.edge0 + self.edge1)
+    }
+    fn bounds(&self) -> AABB {
+        AABB::new().extent(&self.position)
+                   .extent(&(self.position + self.edge0))
+                   .extent(&(self.position + self.edge1))
+                   .extent(&(self.position + self.edge0 + self.edge1))
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match self.material {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let Closed01(r1) = random::<Closed01<f32>>();
+        self.position + r0 * self.edge0 + r1 * self.edge1
+    }
+    fn area(&self) -> f32 {
+        self.edge0.cross(self.edge1).magnitude()
+    }
+}
+
+#[test]
+fn intersections_quad() {
+    let quad = Quad::light(Point3::new(0., 2., 0.), Vector3::new(2., 0., 0.), Vector3::new(0., 0., 1.));
+    assert_eq!(quad.area(), 2.);
+    // faces down, towards a ray from below
+    let mut ray = Ray::new(Point3::new(1.5, 0., 0.5), Vector3::new(0., 1., 0.), f32::INFINITY);
+    let intersection = quad.intersect(&mut ray).expect("hit from below");
+    assert!(!intersection.inside && (intersection.uv.x - 0.75).abs() < 1e-5 && (intersection.uv.y - 0.5).abs() < 1e-5);
+    let mut ray = Ray::new(Point3::new(2.5, 0., 0.5), Vector3::new(0., 1., 0.), f32::INFINITY);
+    assert!(quad.intersect(&mut ray).is_none());
+}
@@ -0,0 +1,254 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace, EuclideanSpace};
+use std::f32;
+use std::fmt;
+use std::sync::Arc;
+
+use super::Primitive;
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+// the surface is this close when sphere tracing stops
+const HIT: f32 = 1e-4;
+const MAX_STEPS: u32 = 256;
+// rays that graze a surface creep along it, running out of steps this close still hits it
+const NEAR: f32 = 1e-2;
+
+/// A signed distance function, negative inside, around the origin and upright
+#[derive(Debug, Clone)]
+pub enum Distance {
+    Sphere { radius: f32 },
+    Box { half_size: Vector3<f32> },
+    // a box of the size with its edges rounded by the radius
+    RoundedBox { half_size: Vector3<f32>, radius: f32 },
+    Torus { major_radius: f32, minor_radius: f32 },
+    // blends the surfaces over the smoothness distance, sharp at 0
+    SmoothUnion(Box<Distance>, Box<Distance>, f32),
+    // cuts the second out of the first
+    SmoothSubtraction(Box<Distance>, Box<Distance>, f32),
+    Translate(Box<Distance>, Vector3<f32>),
+    // an endless grid of copies with the period along each axis, 0 doesn't repeat
+    Repeat(Box<Distance>, Vector3<f32>),
+    Custom(DistanceFn),
+}
+
+/// A distance function of our own, it may underestimate the distance but never overestimate it.
+/// Closures can't be printed, so the name stands in for it in the debug output.
+#[derive(Clone)]
+pub struct DistanceFn(Arc<NamedFn>);
+
+// behind one pointer, so a custom distance is no larger than the others
+struct NamedFn {
+    name: String,
+    function: Box<Fn(Point3<f32>) -> f32 + Send + Sync>,
+}
+
+impl DistanceFn {
+    pub fn new<F: Fn(Point3<f32>) -> f32 + Send + Sync + 'static>(name: &str, function: F) -> DistanceFn {
+        DistanceFn(Arc::new(NamedFn {
+            name: name.to_string(),
+            function: Box::new(function),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+}
+
+impl fmt::Debug for DistanceFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DistanceFn({:?})", self.0.name)
+    }
+}
+
+impl Distance {
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        match *self {
+            Distance::Sphere { radius } => p.to_vec().magnitude() - radius,
+            Distance::Box { half_size } => Distance::RoundedBox { half_size: half_size, radius: 0. }.distance(p),
+            Distance::RoundedBox { half_size, radius } => {
+                let q = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs()) - half_size + Vector3::new(radius, radius, radius);
+                let outside = Vector3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).magnitude();
+                outside + q.x.max(q.y).max(q.z).min(0.) - radius
+            },
+            Distance::Torus { major_radius, minor_radius } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            },
+            Distance::SmoothUnion(ref a, ref b, smoothness) => {
+                let (a, b) = (a.distance(p), b.distance(p));
+                if smoothness <= 0. {
+                    return a.min(b);
+                }
+                let h = (0.5 + 0.5 * (b - a) / smoothness).max(0.).min(1.);
+                b + h * (a - b) - smoothness * h * (1. - h)
+            },
+            Distance::SmoothSubtraction(ref a, ref b, smoothness) => {
+                let (a, b) = (a.distance(p), b.distance(p));
+                if smoothness <= 0. {
+                    return a.max(-b);
+                }
+                let h = (0.5 - 0.5 * (a + b) / smoothness).max(0.).min(1.);
+                a + h * (-b - a) + smoothness * h * (1. - h)
+            },
+            Distance::Translate(ref distance, offset) => distance.distance(p + -offset),
+            Distance::Repeat(ref distance, period) => distance.distance(Distance::fold(p, period)),
+            Distance::Custom(ref distance) => (distance.0.function)(p),
+        }
+    }
+
+    /// the exact gradient where it is known
+    pub fn gradient(&self, p: Point3<f32>) -> Option<Vector3<f32>> {
+        match *self {
+            Distance::Sphere { .. } => Some(p.to_vec()),
+            Distance::Torus { major_radius, .. } => {
+                let distance = (p.x * p.x + p.z * p.z).sqrt();
+                if distance > 0. {
+                    Some(p.to_vec() - major_radius / distance * Vector3::new(p.x, 0., p.z))
+                } else {
+                    None
+                }
+            },
+            Distance::Translate(ref distance, offset) => distance.gradient(p + -offset),
+            Distance::Repeat(ref distance, period) => distance.gradient(Distance::fold(p, period)),
+            _ => None,
+        }
+    }
+
+    // the point in the copy around the origin
+    fn fold(p: Point3<f32>, period: Vector3<f32>) -> Point3<f32> {
+        let fold = |x: f32, period: f32| if period > 0. { x - period * (x / period).round() } else { x };
+        Point3::new(fold(p.x, period.x), fold(p.y, period.y), fold(p.z, period.z))
+    }
+}
+
+/// A surface given by a distance function, rendered by sphere tracing inside its bounds.
+/// It is never sampled as a light.
+#[derive(Debug, Clone)]
+pub struct Sdf {
+    pub distance: Distance,
+    // where the origin of the distance function is
+    pub position: Point3<f32>,
+    // have to hold the whole surface, which can be endless when repeating
+    pub bounds: AABB,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Sdf {
+    /// outward normal, from the gradient or from the differences between four close points
+    pub fn normal(&self, p: Point3<f32>) -> Vector3<f32> {
+        let local = Point3::from_vec(p - self.position);
+        let gradient = self.distance.gradient(local).unwrap_or_else(|| {
+            [Vector3::new(1., -1., -1.), Vector3::new(-1., -1., 1.), Vector3::new(-1., 1., -1.), Vector3::new(1., 1., 1.)].iter()
+                .fold(Vector3::new(0., 0., 0.), |sum, &k| sum + k * self.distance.distance(local + HIT * k))
+        });
+        gradient.normalize()
+    }
+}
+
+impl Primitive for Sdf {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let (enter, exit) = match self.bounds.intersect(ray) {
+            Some(interval) => interval,
+            None => return None,
+        };
+        let end = exit.min(ray.distance);
+        let distance = |t: f32| self.distance.distance(Point3::from_vec(ray.origin + t * ray.direction - self.position));
+        let mut t = enter.max(0.);
+        // a ray that starts on the surface, after a bounce, goes in or out depending on its direction
+        let start = distance(t);
+        let (sign, skip) = if enter <= 0. && start.abs() < 2. * HIT {
+            (if self.normal(ray.origin + t * ray.direction).dot(ray.direction) < 0. { -1. } else { 1. }, 4. * HIT)
+        } else {
+            (start.signum(), 0.)
+        };
+        let skip = t + skip;
+        let mut steps = 0;
+        while t <= end {
+            let step = sign * distance(t);
+            if step < HIT && t >= skip {
+                break;
+            }
+            steps += 1;
+            if steps == MAX_STEPS {
+                if step < NEAR {
+                    break;
+                }
+                return None;
+            }
+            t += step.max(HIT);
+        }
+        if t > end {
+            return None;
+        }
+        ray.distance = t;
+        let normal = self.normal(ray.intersection());
+        Some(Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            // the direction of the normal, as on a sphere
+            uv: Point2::new(0.5 + normal.z.atan2(normal.x) * 0.5 * f32::consts::FRAC_1_PI,
+                            normal.y.max(-1.).min(1.).acos() * f32::consts::FRAC_1_PI),
+            tangent: None,
+            inside: sign < 0.,
+            // a light that is never sampled gets all of the weight when it is hit
+            area: f32::INFINITY,
+            object_id: self.object_id,
+            material: &self.material,
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.bounds.min + 0.5 * (self.bounds.max - self.bounds.min)
+    }
+    fn bounds(&self) -> AABB {
+        self.bounds
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        None
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        self.position
+    }
+    fn area(&self) -> f32 {
+        f32::INFINITY
+    }
+}
+
+#[test]
+fn sphere_tracing_sdf() {
+    let around = |size: f32| AABB { min: Point3::new(-size, -size, 5. - size), max: Point3::new(size, size, 5. + size) };
+    let sphere = Sdf { distance: Distance::Sphere { radius: 1. }, position: Point3::new(0., 0., 5.), bounds: around(1.), material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = sphere.intersect(&mut ray).expect("hit the sphere");
+    assert!((ray.distance - 4.).abs() < 1e-3 && !intersection.inside);
+    assert!((intersection.normal - Vector3::new(0., 0., -1.)).magnitude() < 1e-3);
+    // after refracting into it the ray leaves through the back
+    let point = ray.intersection();
+    ray.reset(point, Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = sphere.intersect(&mut ray).expect("leave the sphere");
+    assert!((ray.distance - 2.).abs() < 1e-3 && intersection.inside);
+    // the rounded box is cut in half and the finite difference normal faces away from it
+    let half = Distance::SmoothSubtraction(Box::new(Distance::RoundedBox { half_size: Vector3::new(1., 1., 1.), radius: 0.2 }),
+                                           Box::new(Distance::Translate(Box::new(Distance::Box { half_size: Vector3::new(2., 2., 1.) }), Vector3::new(0., 0., -1.))), 0.);
+    let cut = Sdf { distance: half, position: Point3::new(0., 0., 5.), bounds: around(1.), material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(0.5, 0.5, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = cut.intersect(&mut ray).expect("hit the cut");
+    assert!((ray.distance - 5.).abs() < 1e-3 && (intersection.normal - Vector3::new(0., 0., -1.)).magnitude() < 1e-2);
+    // a closure works as well
+    let custom = Sdf { distance: Distance::Custom(DistanceFn::new("plane", |p: Point3<f32>| p.z + 0.5)), ..cut };
+    let mut ray = Ray::new(Point3::new(0.5, 0.5, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(custom.intersect(&mut ray).is_some() && (ray.distance - 4.5).abs() < 1e-3);
+    // closures are told apart by their names
+    let other = Distance::Custom(DistanceFn::new("other plane", |p: Point3<f32>| p.z - 0.5));
+    assert!(format!("{:?}", custom.distance) != format!("{:?}", other));
+
+    // a ray grazing a plane only gets a little closer every step, it runs out of steps just above it
+    let floor = Sdf { distance: Distance::Custom(DistanceFn::new("floor", |p: Point3<f32>| p.y)), position: Point3::new(0., 0., 0.),
+                      bounds: AABB { min: Point3::new(-100., -1., -100.), max: Point3::new(100., 1., 100.) }, material: Material::jade(), object_id: 0 };
+    let mut ray = Ray::new(Point3::new(0., 0.5, 0.), Vector3::new(0., -0.02, 1.).normalize(), f32::INFINITY);
+    assert!(floor.intersect(&mut ray).is_some() && ray.intersection().y.abs() < NEAR);
+}
@@ -0,0 +1,394 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Vector3};
+use std::collections::HashMap;
+use std::slice;
+use std::sync::Arc;
+
+use super::{Primitive, Solid};
+use super::aabb::AABB;
+use super::sphere::Sphere;
+use super::triangle::Triangle;
+use super::moving::Moving;
+use super::instance::Instance;
+use super::plane::Plane;
+use super::disk::Disk;
+use super::quad::Quad;
+use super::cuboid::Cuboid;
+use super::cylinder::Cylinder;
+use super::cone::Cone;
+use super::torus::Torus;
+use super::sdf::Sdf;
+use super::csg::Csg;
+use super::curve::{Curve, Bezier};
+use super::triangle_mesh::{MeshTriangle, Geometry};
+
+use ray::{Ray, Intersection};
+
+/// Any kind of primitive, so one scene and one BVH can mix them.
+/// Triangles come first, they are by far the most common.
+#[derive(Debug)]
+pub enum Shape {
+    Triangle(Triangle),
+    Sphere(Sphere),
+    Moving(Box<Moving<Shape>>),
+    Instance(Box<Instance<Shape>>),
+    Plane(Plane),
+    Disk(Disk),
+    Quad(Quad),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Sdf(Sdf),
+    Csg(Box<Csg<Shape>>),
+    Curve(Curve),
+    MeshTriangle(MeshTriangle),
+}
+
+impl Primitive for Shape {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        match *self {
+            Shape::Triangle(ref triangle) => triangle.intersect(ray),
+            Shape::Sphere(ref sphere) => sphere.intersect(ray),
+            Shape::Moving(ref moving) => moving.intersect(ray),
+            Shape::Instance(ref instance) => instance.intersect(ray),
+            Shape::Plane(ref plane) => plane.intersect(ray),
+            Shape::Disk(ref disk) => disk.intersect(ray),
+            Shape::Quad(ref quad) => quad.intersect(ray),
+            Shape::Cuboid(ref cuboid) => cuboid.intersect(ray),
+            Shape::Cylinder(ref cylinder) => cylinder.intersect(ray),
+            Shape::Cone(ref cone) => cone.intersect(ray),
+            Shape::Torus(ref torus) => torus.intersect(ray),
+            Shape::Sdf(ref sdf) => sdf.intersect(ray),
+            Shape::Csg(ref csg) => csg.intersect(ray),
+            Shape::Curve(ref curve) => curve.intersect(ray),
+            Shape::MeshTriangle(ref triangle) => triangle.intersect(ray),
+        }
+    }
+    fn centre(&self) -> Point3<f32> {
+        match *self {
+            Shape::Triangle(ref triangle) => triangle.centre(),
+            Shape::Sphere(ref sphere) => sphere.centre(),
+            Shape::Moving(ref moving) => moving.centre(),
+            Shape::Instance(ref instance) => instance.centre(),
+            Shape::Plane(ref plane) => plane.centre(),
+            Shape::Disk(ref disk) => disk.centre(),
+            Shape::Quad(ref quad) => quad.centre(),
+            Shape::Cuboid(ref cuboid) => cuboid.centre(),
+            Shape::Cylinder(ref cylinder) => cylinder.centre(),
+            Shape::Cone(ref cone) => cone.centre(),
+            Shape::Torus(ref torus) => torus.centre(),
+            Shape::Sdf(ref sdf) => sdf.centre(),
+            Shape::Csg(ref csg) => csg.centre(),
+            Shape::Curve(ref curve) => curve.centre(),
+            Shape::MeshTriangle(ref triangle) => triangle.centre(),
+        }
+    }
+    fn bounds(&self) -> AABB {
+        match *self {
+            Shape::Triangle(ref triangle) => triangle.bounds(),
+            Shape::Sphere(ref sphere) => sphere.bounds(),
+            Shape::Moving(ref moving) => moving.bounds(),
+            Shape::Instance(ref instance) => instance.bounds(),
+            Shape::Plane(ref plane) => plane.bounds(),
+            Shape::Disk(ref disk) => disk.bounds(),
+            Shape::Quad(ref quad) => quad.bounds(),
+            Shape::Cuboid(ref cuboid) => cuboid.bounds(),
+            Shape::Cylinder(ref cylinder) => cylinder.bounds(),
+            Shape::Cone(ref cone) => cone.bounds(),
+            Shape::Torus(ref torus) => torus.bounds(),
+            Shape::Sdf(ref sdf) => sdf.bounds(),
+            Shape::Csg(ref csg) => csg.bounds(),
+            Shape::Curve(ref curve) => curve.bounds(),
+            Shape::MeshTriangle(ref triangle) => triangle.bounds(),
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match *self {
+            Shape::Triangle(ref triangle) => triangle.is_light(),
+            Shape::Sphere(ref sphere) => sphere.is_light(),
+            Shape::Moving(ref moving) => moving.is_light(),
+            Shape::Instance(ref instance) => instance.is_light(),
+            Shape::Plane(ref plane) => plane.is_light(),
+            Shape::Disk(ref disk) => disk.is_light(),
+            Shape::Quad(ref quad) => quad.is_light(),
+            Shape::Cuboid(ref cuboid) => cuboid.is_light(),
+            Shape::Cylinder(ref cylinder) => cylinder.is_light(),
+            Shape::Cone(ref cone) => cone.is_light(),
+            Shape::Torus(ref torus) => torus.is_light(),
+            Shape::Sdf(ref sdf) => sdf.is_light(),
+            Shape::Csg(ref csg) => csg.is_light(),
+            Shape::Curve(ref curve) => curve.is_light(),
+            Shape::MeshTriangle(ref triangle) => triangle.is_light(),
+        }
+    }
+    fn random_point(&self, time: f32) -> Point3<f32> {
+        match *self {
+            Shape::Triangle(ref triangle) => triangle.random_point(time),
+            Shape::Sphere(ref sphere) => sphere.random_point(time),
+            Shape::Moving(ref moving) => moving.random_point(time),
+            Shape::Instance(ref instance) => instance.random_point(time),
+            Shape::Plane(ref plane) => plane.random_point(time),
+            Shape::Disk(ref disk) => disk.random_point(time),
+            Shape::Quad(ref quad) => quad.random_point(time),
+            Shape::Cuboid(ref cuboid) => cuboid.random_point(time),
+            Shape::Cylinder(ref cylinder) => cylinder.random_point(time),
+            Shape::Cone(ref cone) => cone.random_point(time),
+            Shape::Torus(ref torus) => torus.random_point(time),
+            Shape::Sdf(ref sdf) => sdf.random_point(time),
+            Shape::Csg(ref csg) => csg.random_point(time),
+            Shape::Curve(ref curve) => curve.random_point(time),
+            Shape::MeshTriangle(ref triangle) => triangle.random_point(time),
+        }
+    }
+    fn area(&self) -> f32 {
+        match *self {
+            Shape::Triangle(ref triangle) => triangle.area(),
+            Shape::Sphere(ref sphere) => sphere.area(),
+            Shape::Moving(ref moving) => moving.area(),
+            Shape::Instance(ref instance) => instance.area(),
+            Shape::Plane(ref plane) => plane.area(),
+            Shape::Disk(ref disk) => disk.area(),
+            Shape::Quad(ref quad) => quad.area(),
+            Shape::Cuboid(ref cuboid) => cuboid.area(),
+            Shape::Cylinder(ref cylinder) => cylinder.area(),
+            Shape::Cone(ref cone) => cone.area(),
+            Shape::Torus(ref torus) => torus.area(),
+            Shape::Sdf(ref sdf) => sdf.area(),
+            Shape::Csg(ref csg) => csg.area(),
+            Shape::Curve(ref curve) => curve.area(),
+            Shape::MeshTriangle(ref triangle) => triangle.area(),
+        }
+    }
+}
+
+/// one more than the largest object id of the shapes, the first id another scene can take
+pub fn object_id_end(shapes: &[Shape]) -> u32 {
+    shapes.iter().map(|shape| match *shape {
+        Shape::Triangle(ref triangle) => triangle.object_id + 1,
+        Shape::Sphere(ref sphere) => sphere.object_id + 1,
+        Shape::Moving(ref moving) => object_id_end(slice::from_ref(&moving.primitive)),
+        Shape::Instance(ref instance) => instance.object_id_offset + object_id_end(instance.mesh.objects()),
+        Shape::Plane(ref plane) => plane.object_id + 1,
+        Shape::Disk(ref disk) => disk.object_id + 1,
+        Shape::Quad(ref quad) => quad.object_id + 1,
+        Shape::Cuboid(ref cuboid) => cuboid.object_id + 1,
+        Shape::Cylinder(ref cylinder) => cylinder.object_id + 1,
+        Shape::Cone(ref cone) => cone.object_id + 1,
+        Shape::Torus(ref torus) => torus.object_id + 1,
+        Shape::Sdf(ref sdf) => sdf.object_id + 1,
+        Shape::Csg(ref csg) => object_id_end(slice::from_ref(&csg.left)).max(object_id_end(slice::from_ref(&csg.right))),
+        Shape::Curve(ref curve) => curve.bezier.object_id + 1,
+        Shape::MeshTriangle(ref triangle) => triangle.geometry.meshes[triangle.mesh as usize].object_id + 1,
+    }).max().unwrap_or(0)
+}
+
+/// Moves the object ids of the shapes up by `offset`, past the ids of the scene they get combined
+/// with. Meshes and curves shared by many shapes are copied once, instances offset their whole mesh.
+pub fn offset_object_ids(shapes: &mut [Shape], offset: u32) {
+    let mut shared = Shared { geometries: HashMap::new(), beziers: HashMap::new() };
+    for shape in shapes {
+        offset_shape(shape, offset, &mut shared);
+    }
+}
+
+// the offset copies of shared meshes and curves, by the address of the original
+struct Shared {
+    geometries: HashMap<*const Geometry, Arc<Geometry>>,
+    beziers: HashMap<*const Bezier, Arc<Bezier>>,
+}
+
+fn offset_shape(shape: &mut Shape, offset: u32, shared: &mut Shared) {
+    match *shape {
+        Shape::Triangle(ref mut triangle) => triangle.object_id += offset,
+        Shape::Sphere(ref mut sphere) => sphere.object_id += offset,
+        Shape::Moving(ref mut moving) => offset_shape(&mut moving.primitive, offset, shared),
+        Shape::Instance(ref mut instance) => instance.object_id_offset += offset,
+        Shape::Plane(ref mut plane) => plane.object_id += offset,
+        Shape::Disk(ref mut disk) => disk.object_id += offset,
+        Shape::Quad(ref mut quad) => quad.object_id += offset,
+        Shape::Cuboid(ref mut cuboid) => cuboid.object_id += offset,
+        Shape::Cylinder(ref mut cylinder) => cylinder.object_id += offset,
+        Shape::Cone(ref mut cone) => cone.object_id += offset,
+        Shape::Torus(ref mut torus) => torus.object_id += offset,
+        Shape::Sdf(ref mut sdf) => sdf.object_id += offset,
+        Shape::Csg(ref mut csg) => {
+            offset_shape(&mut csg.left, offset, shared);
+            offset_shape(&mut csg.right, offset, shared);
+        },
+        Shape::Curve(ref mut curve) => {
+            let original = &*curve.bezier as *const Bezier;
+            let bezier = shared.beziers.entry(original).or_insert_with(|| {
+                Arc::new(Bezier { object_id: curve.bezier.object_id + offset, ..(*curve.bezier).clone() })
+            }).clone();
+            curve.bezier = bezier;
+        },
+        Shape::MeshTriangle(ref mut triangle) => {
+            let original = &*triangle.geometry as *const Geometry;
+            let geometry = shared.geometries.entry(original).or_insert_with(|| {
+                let mut geometry = (*triangle.geometry).clone();
+                for mesh in &mut geometry.meshes {
+                    mesh.object_id += offset;
+                }
+                Arc::new(geometry)
+            }).clone();
+            triangle.geometry = geometry;
+        },
+    }
+}
+
+impl Solid for Shape {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        match *self {
+            Shape::Sphere(ref sphere) => sphere.crossings(ray),
+            Shape::Cuboid(ref cuboid) => cuboid.crossings(ray),
+            Shape::Cylinder(ref cylinder) => cylinder.crossings(ray),
+            Shape::Cone(ref cone) => cone.crossings(ray),
+            Shape::Torus(ref torus) => torus.crossings(ray),
+            Shape::Csg(ref csg) => csg.crossings(ray),
+            // open surfaces have no inside, they take no part in constructive solid geometry
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl From<Triangle> for Shape {
+    fn from(triangle: Triangle) -> Shape {
+        Shape::Triangle(triangle)
+    }
+}
+
+impl From<Sphere> for Shape {
+    fn from(sphere: Sphere) -> Shape {
+        Shape::Sphere(sphere)
+    }
+}
+
+impl<T: Primitive + Into<Shape>> From<Moving<T>> for Shape {
+    fn from(moving: Moving<T>) -> Shape {
+        Shape::Moving(Box::new(Moving::new(moving.primitive.into(), moving.start, moving.end)))
+    }
+}
+
+impl From<Instance<Shape>> for Shape {
+    fn from(instance: Instance<Shape>) -> Shape {
+        Shape::Instance(Box::new(instance))
+    }
+}
+
+impl From<Plane> for Shape {
+    fn from(plane: Plane) -> Shape {
+        Shape::Plane(plane)
+    }
+}
+
+impl From<Disk> for Shape {
+    fn from(disk: Disk) -> Shape {
+        Shape::Disk(disk)
+    }
+}
+
+impl From<Quad> for Shape {
+    fn from(quad: Quad) -> Shape {
+        Shape::Quad(quad)
+    }
+}
+
+impl From<Cuboid> for Shape {
+    fn from(cuboid: Cuboid) -> Shape {
+        Shape::Cuboid(cuboid)
+    }
+}
+
+impl From<Cylinder> for Shape {
+    fn from(cylinder: Cylinder) -> Shape {
+        Shape::Cylinder(cylinder)
+    }
+}
+
+impl From<Cone> for Shape {
+    fn from(cone: Cone) -> Shape {
+        Shape::Cone(cone)
+    }
+}
+
+impl From<Torus> for Shape {
+    fn from(torus: Torus) -> Shape {
+        Shape::Torus(torus)
+    }
+}
+
+impl From<Sdf> for Shape {
+    fn from(sdf: Sdf) -> Shape {
+        Shape::Sdf(sdf)
+    }
+}
+
+impl From<Curve> for Shape {
+    fn from(curve: Curve) -> Shape {
+        Shape::Curve(curve)
+    }
+}
+
+impl From<MeshTriangle> for Shape {
+    fn from(triangle: MeshTriangle) -> Shape {
+        Shape::MeshTriangle(triangle)
+    }
+}
+
+impl<T: Solid + Into<Shape>> From<Csg<T>> for Shape {
+    fn from(csg: Csg<T>) -> Shape {
+        Shape::Csg(Box::new(Csg::new(csg.operation, csg.left.into(), csg.right.into())))
+    }
+}
+
+#[test]
+fn mixed_shapes_in_one_bvh() {
+    use std::f32;
+    use bvh::BVH;
+    use material::Material;
+    let triangle = Triangle::light(Point3::new(-1., -1., 5.), Point3::new(1., -1., 5.), Point3::new(0., 1., 5.),
+                                   Vector3::new(0., 0., -1.), Vector3::new(0., 0., -1.), Vector3::new(0., 0., -1.));
+    let sphere = Sphere { position: Point3::new(0., 0., 2.), radius: 0.5, material: Material::jade(), object_id: 1 };
+    let bvh = BVH::new(vec![Shape::from(triangle), Shape::from(sphere)]);
+    // the sphere is in front of the triangle, beside it only the triangle is hit
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert_eq!(bvh.intersect_closest(&mut ray).map(|intersection| intersection.object_id), Some(1));
+    let mut ray = Ray::new(Point3::new(0., -0.8, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert_eq!(bvh.intersect_closest(&mut ray).map(|intersection| intersection.object_id), Some(0));
+    assert!(bvh.random_light().is_some());
+    // instances are boxed like the other large variants, so they do not grow every shape
+    assert!(::std::mem::size_of::<Shape>() <= ::std::mem::size_of::<Triangle>() + 8);
+}
+
+#[test]
+fn offset_object_ids_of_combined_shapes() {
+    use material::Material;
+    use primitive::triangle_mesh::TriangleMesh;
+    let sphere = Sphere { position: Point3::new(0., 0., 2.), radius: 0.5, material: Material::jade(), object_id: 2 };
+    let mut geometry = Geometry::new();
+    let material = geometry.add_material(Material::jade());
+    geometry.add_mesh(TriangleMesh {
+        positions: vec![Point3::new(-1., -1., 2.), Point3::new(1., -1., 2.), Point3::new(1., 1., 2.), Point3::new(-1., 1., 2.)],
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        indices: vec![[0, 1, 2], [0, 2, 3]],
+        material: material,
+        object_id: 4,
+    });
+    let geometry = Arc::new(geometry);
+    let mut shapes: Vec<Shape> = vec![Shape::from(sphere)];
+    shapes.extend(Geometry::triangles(&geometry).into_iter().map(Shape::from));
+    assert_eq!(object_id_end(&shapes), 5);
+    offset_object_ids(&mut shapes, 10);
+    assert_eq!(object_id_end(&shapes), 15);
+    match (&shapes[0], &shapes[1], &shapes[2]) {
+        (&Shape::Sphere(ref sphere), &Shape::MeshTriangle(ref a), &Shape::MeshTriangle(ref b)) => {
+            assert_eq!(sphere.object_id, 12);
+            // the triangles of the mesh still share one copy of it, the original is untouched
+            assert!(Arc::ptr_eq(&a.geometry, &b.geometry) && !Arc::ptr_eq(&a.geometry, &geometry));
+            assert_eq!(geometry.meshes[0].object_id, 4);
+        },
+        _ => panic!("shapes changed kind"),
+    }
+}
@@ -1,10 +1,10 @@
 extern crate cgmath;
-use self::cgmath::{Point3, InnerSpace, Vector3};
+use self::cgmath::{Point3, Point2, InnerSpace, Vector3};
 
 use std::f32;
-use rand;
+use random::with_generator;
 
-use super::Primitive;
+use super::{Primitive, Solid};
 use super::aabb::AABB;
 
 use ray::{Ray, Intersection};
@@ -15,16 +15,23 @@ pub struct Sphere {
     pub position: Point3<f32>,
     pub radius: f32,
     pub material: Material,
+    pub object_id: u32,
 }
 
 impl Sphere {
+    /// spherical coordinates of a point on the sphere given its normal
+    pub fn uv(&self, normal: Vector3<f32>) -> Point2<f32> {
+        Point2::new(0.5 + normal.z.atan2(normal.x) * 0.5 * f32::consts::FRAC_1_PI,
+                    normal.y.max(-1.).min(1.).acos() * f32::consts::FRAC_1_PI)
+    }
     pub fn light(position: Point3<f32>, radius: f32) -> Sphere {
         Sphere {
             position: position,
             radius: radius,
             material: Material::Emissive {
                 color: LIGHT_COLOR,
-            }
+            },
+            object_id: 0,
         }
     }
 }
@@ -55,10 +62,15 @@ impl Primitive for Sphere {
                 None
             } else {
                 ray.distance = t0;
+                let normal = (ray.origin + ray.direction * t0 - self.position).normalize();
                 Some(Intersection{
-                    normal: (ray.origin + ray.direction * t0 - self.position).normalize(),
-                    inside:  false,
+                    normal: normal,
+                    geometric_normal: normal,
+                    uv: self.uv(normal),
+                    tangent: None,
+                    inside: false,
                     area: self.area(),
+                    object_id: self.object_id,
                     material: &self.material,
                 })
             }
@@ -67,10 +79,15 @@ impl Primitive for Sphere {
                 None
             } else {
                 ray.distance = t1;
+                let normal = (ray.origin + ray.direction * t1 - self.position).normalize();
                 Some(Intersection{
-                    normal: (ray.origin + ray.direction * t1 - self.position).normalize(),
+                    normal: normal,
+                    geometric_normal: normal,
+                    uv: self.uv(normal),
+                    tangent: None,
                     inside: true,
                     area: self.area(),
+                    object_id: self.object_id,
                     material: &self.material,
                 })
             }
@@ -95,18 +112,15 @@ impl Primitive for Sphere {
             _ => None,
         }
     }
-    fn random_point(&self) -> Point3<f32> {
+    fn random_point(&self, _time: f32) -> Point3<f32> {
         use std::f32;
         use rand::distributions::*;
-        let mut rng = rand::thread_rng();
 
         let phi_range: Range<f32> = Range::new(0.0, 2.0*f32::consts::PI);
         let cos_theta_range: Range<f32> = Range::new(-1.0, 1.0);
         let u_range: Range<f32> = Range::new(0.0,1.0);
 
-        let phi =  phi_range.ind_sample(&mut rng);
-        let cos_theta = cos_theta_range.ind_sample(&mut rng);
-        let u = u_range.ind_sample(&mut rng);
+        let (phi, cos_theta, u) = with_generator(|rng| (phi_range.ind_sample(rng), cos_theta_range.ind_sample(rng), u_range.ind_sample(rng)));
 
         let theta = cos_theta.acos();
 
@@ -121,6 +135,31 @@ impl Primitive for Sphere {
     }
 }
 
+impl Solid for Sphere {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        let distance = self.position - ray.origin;
+        let tca = distance.dot(ray.direction);
+        let d2 = distance.dot(distance) - tca*tca;
+        if d2 > self.radius * self.radius {
+            return Vec::new();
+        }
+        let thc = (self.radius * self.radius - d2).sqrt();
+        [tca - thc, tca + thc].iter().enumerate().map(|(i, &t)| {
+            let normal = (ray.origin + ray.direction * t - self.position).normalize();
+            (t, Intersection {
+                normal: normal,
+                geometric_normal: normal,
+                uv: self.uv(normal),
+                tangent: None,
+                inside: i == 1,
+                area: self.area(),
+                object_id: self.object_id,
+                material: &self.material,
+            })
+        }).collect()
+    }
+}
+
 #[test]
 fn intersections_sphere() {
     use self::cgmath::Vector3;
@@ -0,0 +1,142 @@
+extern crate cgmath;
+use self::cgmath::{Point3, Point2, Vector3, InnerSpace};
+use std::f32;
+use std::cmp::Ordering;
+use random::random;
+use rand::Closed01;
+
+use super::{Primitive, Solid, solve_quartic};
+use super::aabb::AABB;
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+/// A ring around `position` lying flat, a tube with the minor radius around a circle with
+/// the major radius. Turn it with an instance.
+#[derive(Debug, Clone)]
+pub struct Torus {
+    pub position: Point3<f32>,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub material: Material,
+    pub object_id: u32,
+}
+
+impl Torus {
+    // where the line of the ray crosses the tube from `start` on, in no particular order
+    fn roots(&self, ray: &Ray, start: f32) -> Vec<f32> {
+        // the coefficients of the quartic lose precision far away
+        let o = (ray.origin + start * ray.direction - self.position).cast::<f64>();
+        let d = ray.direction.cast::<f64>();
+        let (major, minor) = (self.major_radius as f64, self.minor_radius as f64);
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) along the ray
+        let f = o.dot(d);
+        let k = o.dot(o) + major * major - minor * minor;
+        let r2 = 4. * major * major;
+        solve_quartic(4. * f,
+                      4. * f * f + 2. * k - r2 * (d.x * d.x + d.z * d.z),
+                      4. * f * k - 2. * r2 * (o.x * d.x + o.z * d.z),
+                      k * k - r2 * (o.x * o.x + o.z * o.z)).iter().map(|&t| start + t as f32).collect()
+    }
+    fn surface(&self, ray: &Ray, t: f32) -> Intersection {
+        // away from the closest point on the circle in the middle of the tube
+        let point = ray.origin + t * ray.direction - self.position;
+        let distance = (point.x * point.x + point.z * point.z).sqrt();
+        let ring = if distance > 0. { self.major_radius / distance * Vector3::new(point.x, 0., point.z) } else { Vector3::new(0., 0., 0.) };
+        let normal = (point - ring).normalize();
+        let uv = Point2::new(0.5 + point.z.atan2(point.x) * 0.5 * f32::consts::FRAC_1_PI,
+                             0.5 + point.y.atan2(distance - self.major_radius) * 0.5 * f32::consts::FRAC_1_PI);
+        Intersection {
+            normal: normal,
+            geometric_normal: normal,
+            uv: uv,
+            tangent: None,
+            inside: normal.dot(ray.direction) > 0.,
+            area: self.area(),
+            object_id: self.object_id,
+            material: &self.material,
+        }
+    }
+}
+
+impl Primitive for Torus {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        // start at the bounds
+        let start = match self.bounds().intersect(ray) {
+            Some((t_min, _)) => t_min.max(0.),
+            None => return None,
+        };
+        let t = self.roots(ray, start).into_iter().filter(|&t| t >= 0. && t < ray.distance).fold(None, |closest: Option<f32>, t| Some(closest.map_or(t, |closest| closest.min(t))));
+        let t = match t {
+            Some(t) => t,
+            None => return None,
+        };
+        ray.distance = t;
+        Some(self.surface(ray, t))
+    }
+    fn centre(&self) -> Point3<f32> {
+        self.position
+    }
+    fn bounds(&self) -> AABB {
+        let outer = self.major_radius + self.minor_radius;
+        AABB {
+            min: self.position + Vector3::new(-outer, -self.minor_radius, -outer),
+            max: self.position + Vector3::new(outer, self.minor_radius, outer),
+        }
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match self.material {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let phi = 2. * f32::consts::PI * r0;
+        // the outside of the tube has more area than the inside, reject angles in proportion
+        let theta = loop {
+            let Closed01(r1) = random::<Closed01<f32>>();
+            let Closed01(r2) = random::<Closed01<f32>>();
+            let theta = 2. * f32::consts::PI * r1;
+            if r2 * (self.major_radius + self.minor_radius) <= self.major_radius + self.minor_radius * theta.cos() {
+                break theta;
+            }
+        };
+        let distance = self.major_radius + self.minor_radius * theta.cos();
+        self.position + Vector3::new(distance * phi.cos(), self.minor_radius * theta.sin(), distance * phi.sin())
+    }
+    fn area(&self) -> f32 {
+        4. * f32::consts::PI * f32::consts::PI * self.major_radius * self.minor_radius
+    }
+}
+
+impl Solid for Torus {
+    fn crossings(&self, ray: &Ray) -> Vec<(f32, Intersection)> {
+        let start = match self.bounds().intersect(ray) {
+            Some((t_min, _)) => t_min,
+            None => return Vec::new(),
+        };
+        let mut roots = self.roots(ray, start);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        roots.into_iter().map(|t| (t, self.surface(ray, t))).collect()
+    }
+}
+
+#[test]
+fn intersections_torus() {
+    let torus = Torus { position: Point3::new(0., 0., 10.), major_radius: 2., minor_radius: 0.5, material: Material::jade(), object_id: 0 };
+    // beside the ring and through its hole
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 1., 0.), f32::INFINITY);
+    assert!(torus.intersect(&mut ray).is_none());
+    let mut ray = Ray::new(Point3::new(0., 5., 10.), Vector3::new(0., -1., 0.), f32::INFINITY);
+    assert!(torus.intersect(&mut ray).is_none());
+    // in the plane of the ring the tube is hit four times, the first from the outside
+    let mut ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    let intersection = torus.intersect(&mut ray).expect("hit the tube");
+    assert!((ray.distance - 7.5).abs() < 1e-3 && !intersection.inside);
+    assert!((intersection.normal - Vector3::new(0., 0., -1.)).magnitude() < 1e-3);
+    // from the top of the tube
+    let mut ray = Ray::new(Point3::new(2., 5., 10.), Vector3::new(0., -1., 0.), f32::INFINITY);
+    let intersection = torus.intersect(&mut ray).expect("hit the top");
+    assert!((ray.distance - 4.5).abs() < 1e-3 && (intersection.normal - Vector3::new(0., 1., 0.)).magnitude() < 1e-3);
+}
@@ -1,7 +1,7 @@
 extern crate cgmath;
-use self::cgmath::{Vector3, Point3, InnerSpace, EuclideanSpace};
+use self::cgmath::{Vector3, Point3, Point2, InnerSpace, EuclideanSpace};
 use std::f32;
-use rand;
+use random::random;
 use rand::Open01;
 
 use super::Primitive;
@@ -10,7 +10,46 @@ use super::aabb::AABB;
 use ray::{Ray,Intersection};
 use material::{Material, LIGHT_COLOR};
 
-#[derive(Debug)]
+/// Möller-Trumbore, the distance, the barycentric coördinates of the second and third
+/// corner and the determinant, which is negative from the back, of a hit before the ray's distance
+pub fn intersect_triangle(position0: Point3<f32>, position1: Point3<f32>, position2: Point3<f32>, ray: &Ray) -> Option<(f32, f32, f32, f32)> {
+    let edge1 = position1 - position0;
+    let edge2 = position2 - position0;
+    let h = ray.direction.cross(edge2);
+    let a = edge1.dot(h);
+
+    if a > -f32::EPSILON && a < f32::EPSILON {
+        return None
+    }
+
+    let f = 1.0 / a;
+    let s = ray.origin - position0;
+    let u = f * s.dot(h);
+
+    if u < 0.0 || u > 1.0 {
+        return None
+    }
+
+    let q = s.cross(edge1);
+    let v = f * ray.direction.dot(q);
+
+    if v < 0.0 || u + v > 1.0 {
+        return None
+    }
+
+    // at this stage we can compute t to find out where
+    // the intersection point is on the ray
+    let t = f * edge2.dot(q);
+    if t < 0.0 {
+        return None // the intersection is behind the ray's origin
+    }
+    if t >= ray.distance {
+        return None // the ray doesn't reach the triangle
+    }
+    Some((t, u, v, a))
+}
+
+#[derive(Debug, Clone)]
 pub struct Triangle {
     pub position0: Point3<f32>,
     pub position1: Point3<f32>,
@@ -19,6 +58,7 @@ pub struct Triangle {
     pub normal1: Vector3<f32>,
     pub normal2: Vector3<f32>,
     pub material: Material,
+    pub object_id: u32,
 }
 
 impl Triangle {
@@ -32,48 +72,29 @@ impl Triangle {
             normal2: n2,
             material: Material::Emissive {
                 color: LIGHT_COLOR,
-            }
+            },
+            object_id: 0,
         }
     }
 }
 
 impl Primitive for Triangle {
     fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let (t, u, v, a) = match intersect_triangle(self.position0, self.position1, self.position2, ray) {
+            Some(hit) => hit,
+            None => return None,
+        };
         let edge1 = self.position1 - self.position0;
         let edge2 = self.position2 - self.position0;
-        let h = ray.direction.cross(edge2);
-        let a = edge1.dot(h);
-
-        if a > -f32::EPSILON && a < f32::EPSILON {
-            return None
-        }
-
-        let f = 1.0 / a;
-        let s = ray.origin - self.position0;
-        let u = f * s.dot(h);
-
-        if u < 0.0 || u > 1.0 {
-            return None
-        }
-
-        let q = s.cross(edge1);
-        let v = f * ray.direction.dot(q);
-
-        if v < 0.0 || u + v > 1.0 {
-            return None
-        }
-
-        // at this stage we can compute t to find out where
-        // the intersection point is on the ray
-        let t = f * edge2.dot(q);
-        if t < 0.0 {
-            return None // the intersection is behind the ray's origin
-        }
         ray.distance = t;
         Some(Intersection{
             normal: ((1. - u - v) * self.normal0 + u * self.normal1 + v * self.normal2).normalize(),
+            geometric_normal: edge1.cross(edge2).normalize(),
+            uv: Point2::new(u, v),
+            tangent: None,
             inside: a < 0.,
             area: self.area(),
+            object_id: self.object_id,
             material: &self.material,
         })
     }
@@ -94,9 +115,9 @@ impl Primitive for Triangle {
             _ => None,
         }
     }
-    fn random_point(&self) -> Point3<f32> {
-        let Open01(u) = rand::random::<Open01<f32>>(); // 0 < u < 1
-        let Open01(v) = rand::random::<Open01<f32>>(); // 0 < v < 1
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Open01(u) = random::<Open01<f32>>(); // 0 < u < 1
+        let Open01(v) = random::<Open01<f32>>(); // 0 < v < 1
         let v = (1. - u) * v; //  0 < u + v < 1
         let edge1 = self.position1 - self.position0;
         let edge2 = self.position2 - self.position0;
@@ -0,0 +1,210 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, Point3, Point2, InnerSpace, EuclideanSpace};
+use std::f32;
+use std::fmt;
+use std::sync::Arc;
+use random::random;
+use rand::Open01;
+
+use super::Primitive;
+use super::aabb::AABB;
+use super::triangle::{Triangle, intersect_triangle};
+
+use ray::{Ray, Intersection};
+use material::Material;
+
+/// A model with its vertices stored once and triangles of three indices into them.
+/// Without normals it is flat shaded, without texture coördinates the uv are barycentric.
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    pub positions: Vec<Point3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub uvs: Vec<Point2<f32>>,
+    pub indices: Vec<[u32; 3]>,
+    // into the material table of the geometry
+    pub material: u32,
+    pub object_id: u32,
+}
+
+/// All meshes of a scene and the table of their materials, shared by every triangle
+#[derive(Debug, Clone)]
+pub struct Geometry {
+    pub meshes: Vec<TriangleMesh>,
+    pub materials: Vec<Material>,
+}
+
+/// One triangle of a mesh, which is all that the BVH holds
+#[derive(Clone)]
+pub struct MeshTriangle {
+    pub geometry: Arc<Geometry>,
+    pub mesh: u32,
+    pub triangle: u32,
+}
+
+impl Geometry {
+    pub fn new() -> Geometry {
+        Geometry {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    // the index of the material in the table
+    pub fn add_material(&mut self, material: Material) -> u32 {
+        self.materials.push(material);
+        (self.materials.len() - 1) as u32
+    }
+
+    pub fn add_mesh(&mut self, mesh: TriangleMesh) -> u32 {
+        self.meshes.push(mesh);
+        (self.meshes.len() - 1) as u32
+    }
+
+    /// separate triangles as a mesh each, for the few that are made by hand
+    pub fn add_triangles(&mut self, triangles: &[Triangle]) {
+        for triangle in triangles {
+            let material = self.add_material(triangle.material);
+            self.add_mesh(TriangleMesh {
+                positions: vec![triangle.position0, triangle.position1, triangle.position2],
+                normals: vec![triangle.normal0, triangle.normal1, triangle.normal2],
+                uvs: Vec::new(),
+                indices: vec![[0, 1, 2]],
+                material: material,
+                object_id: triangle.object_id,
+            });
+        }
+    }
+
+    /// every triangle of every mesh, to put in a BVH
+    pub fn triangles(geometry: &Arc<Geometry>) -> Vec<MeshTriangle> {
+        let mut triangles = Vec::with_capacity(geometry.meshes.iter().map(|mesh| mesh.indices.len()).sum());
+        for (index, mesh) in geometry.meshes.iter().enumerate() {
+            triangles.extend((0..mesh.indices.len()).map(|triangle| MeshTriangle {
+                geometry: geometry.clone(),
+                mesh: index as u32,
+                triangle: triangle as u32,
+            }));
+        }
+        triangles
+    }
+}
+
+impl MeshTriangle {
+    fn mesh(&self) -> &TriangleMesh {
+        &self.geometry.meshes[self.mesh as usize]
+    }
+
+    fn indices(&self) -> [usize; 3] {
+        let indices = self.mesh().indices[self.triangle as usize];
+        [indices[0] as usize, indices[1] as usize, indices[2] as usize]
+    }
+
+    fn positions(&self) -> [Point3<f32>; 3] {
+        let positions = &self.mesh().positions;
+        let [i0, i1, i2] = self.indices();
+        [positions[i0], positions[i1], positions[i2]]
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.geometry.materials[self.mesh().material as usize]
+    }
+}
+
+// only the indices, printing the shared geometry with every triangle would take the square of
+// its size, it gets hashed once with the files it is loaded from
+impl fmt::Debug for MeshTriangle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MeshTriangle").field("mesh", &self.mesh).field("triangle", &self.triangle).finish()
+    }
+}
+
+impl Primitive for MeshTriangle {
+    fn intersect(&self, ray: &mut Ray) -> Option<Intersection> {
+        let [p0, p1, p2] = self.positions();
+        let (t, u, v, a) = match intersect_triangle(p0, p1, p2, ray) {
+            Some(hit) => hit,
+            None => return None,
+        };
+        ray.distance = t;
+        let mesh = self.mesh();
+        let [i0, i1, i2] = self.indices();
+        let geometric_normal = (p1 - p0).cross(p2 - p0).normalize();
+        let normal = if mesh.normals.is_empty() {
+            geometric_normal
+        } else {
+            ((1. - u - v) * mesh.normals[i0] + u * mesh.normals[i1] + v * mesh.normals[i2]).normalize()
+        };
+        let uv = if mesh.uvs.is_empty() {
+            Point2::new(u, v)
+        } else {
+            Point2::from_vec((1. - u - v) * mesh.uvs[i0].to_vec() + u * mesh.uvs[i1].to_vec() + v * mesh.uvs[i2].to_vec())
+        };
+        Some(Intersection{
+            normal: normal,
+            geometric_normal: geometric_normal,
+            uv: uv,
+            tangent: None,
+            inside: a < 0.,
+            area: self.area(),
+            object_id: mesh.object_id,
+            material: self.material(),
+        })
+    }
+    fn centre(&self) -> Point3<f32> {
+        let [p0, p1, p2] = self.positions();
+        (p0 + p1.to_vec() + p2.to_vec()) * (1.0 / 3.0)
+    }
+    fn bounds(&self) -> AABB {
+        let [p0, p1, p2] = self.positions();
+        AABB::new().extent(&p0).extent(&p1).extent(&p2)
+    }
+    fn is_light(&self) -> Option<Vector3<f32>> {
+        match *self.material() {
+            Material::Emissive { color } => Some(color),
+            _ => None,
+        }
+    }
+    fn random_point(&self, _time: f32) -> Point3<f32> {
+        let Open01(u) = random::<Open01<f32>>();
+        let Open01(v) = random::<Open01<f32>>();
+        // uniform over the triangle
+        let u = u.sqrt();
+        let [p0, p1, p2] = self.positions();
+        p0 + (1. - v) * u * (p1 - p0) + v * u * (p2 - p0)
+    }
+    fn area(&self) -> f32 {
+        let [p0, p1, p2] = self.positions();
+        0.5 * (p1 - p0).cross(p2 - p0).magnitude()
+    }
+}
+
+#[test]
+fn intersections_mesh_triangle() {
+    use std::mem;
+    // a square of two triangles sharing the diagonal, textured from corner to corner
+    let mut geometry = Geometry::new();
+    let material = geometry.add_material(Material::jade());
+    geometry.add_mesh(TriangleMesh {
+        positions: vec![Point3::new(-1., -1., 2.), Point3::new(1., -1., 2.), Point3::new(1., 1., 2.), Point3::new(-1., 1., 2.)],
+        normals: Vec::new(),
+        uvs: vec![Point2::new(0., 0.), Point2::new(1., 0.), Point2::new(1., 1.), Point2::new(0., 1.)],
+        indices: vec![[0, 1, 2], [0, 2, 3]],
+        material: material,
+        object_id: 7,
+    });
+    let triangles = Geometry::triangles(&Arc::new(geometry));
+    assert_eq!(triangles.len(), 2);
+    assert!(mem::size_of::<MeshTriangle>() * 4 < mem::size_of::<Triangle>());
+    assert!((triangles[0].area() - 2.).abs() < 1e-5);
+    assert_eq!(format!("{:?}", triangles[1]), "MeshTriangle { mesh: 0, triangle: 1 }");
+
+    // through the second triangle, it is hit from its back
+    let mut ray = Ray::new(Point3::new(-0.5, 0.5, 0.), Vector3::new(0., 0., 1.), f32::INFINITY);
+    assert!(triangles[0].intersect(&mut ray).is_none());
+    let intersection = triangles[1].intersect(&mut ray).expect("hit the square");
+    assert!((ray.distance - 2.).abs() < 1e-5);
+    assert!((intersection.uv - Point2::new(0.25, 0.75)).magnitude() < 1e-5);
+    assert!((intersection.normal - Vector3::new(0., 0., 1.)).magnitude() < 1e-5 && intersection.inside);
+    assert_eq!(intersection.object_id, 7);
+    assert!(triangles[1].is_light().is_none());
+}
@@ -0,0 +1,112 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, InnerSpace};
+use std::f32;
+
+/// How the camera maps the film to rays
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    // thin lens through the screen plane
+    Perspective,
+    // parallel rays through the screen plane at the focal distance
+    Orthographic,
+    // 180 degrees in a circle that fills the height, the angle grows linearly with the radius
+    FisheyeEquidistant,
+    // 180 degrees in a circle that fills the height, preserving areas
+    FisheyeEquisolid,
+    // latitude and longitude around the camera, 360 by 180 degrees
+    Equirectangular,
+    // six 90 degree faces in a 3x2 grid: right, left, up in the top row and down, front, back below
+    CubeMap,
+}
+
+pub const PROJECTIONS: [Projection; 6] = [Projection::Perspective, Projection::Orthographic, Projection::FisheyeEquidistant,
+                                          Projection::FisheyeEquisolid, Projection::Equirectangular, Projection::CubeMap];
+
+impl Projection {
+    pub fn from_name(name: &str) -> Option<Projection> {
+        PROJECTIONS.iter().find(|projection| projection.name() == name).cloned()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+            Projection::FisheyeEquidistant => "equidistant",
+            Projection::FisheyeEquisolid => "equisolid",
+            Projection::Equirectangular => "equirectangular",
+            Projection::CubeMap => "cubemap",
+        }
+    }
+
+    /// Maps a position on the film, `u` to the right and `v` down in `[0, 1]`, to the origin
+    /// of the ray relative to the eye in units of the focal distance, and its direction.
+    /// Both are in camera space, x to the right, y up and z forward.
+    /// None outside of the image circle of a fisheye.
+    pub fn map(&self, u: f32, v: f32, aspect_ratio: f32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let (a, b) = (2. * u - 1., 1. - 2. * v);
+        let origin = Vector3::new(0., 0., 0.);
+        match *self {
+            // the screen plane at a distance of 1 is 1 high
+            Projection::Perspective => Some((origin, Vector3::new(0.5 * a * aspect_ratio, 0.5 * b, 1.).normalize())),
+            Projection::Orthographic => Some((Vector3::new(0.5 * a * aspect_ratio, 0.5 * b, 0.), Vector3::new(0., 0., 1.))),
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                let a = a * aspect_ratio;
+                let r = (a * a + b * b).sqrt();
+                if r > 1. {
+                    return None;
+                }
+                let theta = if *self == Projection::FisheyeEquidistant {
+                    r * f32::consts::FRAC_PI_2
+                } else {
+                    2. * (r * f32::consts::FRAC_1_SQRT_2).asin()
+                };
+                if r == 0. {
+                    return Some((origin, Vector3::new(0., 0., 1.)));
+                }
+                Some((origin, Vector3::new(theta.sin() * a / r, theta.sin() * b / r, theta.cos())))
+            },
+            Projection::Equirectangular => {
+                let phi = a * f32::consts::PI;
+                let elevation = b * f32::consts::FRAC_PI_2;
+                Some((origin, Vector3::new(elevation.cos() * phi.sin(), elevation.sin(), elevation.cos() * phi.cos())))
+            },
+            Projection::CubeMap => {
+                let column = ((3. * u) as usize).min(2);
+                let row = ((2. * v) as usize).min(1);
+                // position on the face in [-1, 1]
+                let s = 2. * (3. * u - column as f32) - 1.;
+                let t = 1. - 2. * (2. * v - row as f32);
+                let (x, y, z) = (Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.), Vector3::new(0., 0., 1.));
+                // forward, right and up of every face
+                let (forward, right, up) = match (row, column) {
+                    (0, 0) => (x, -z, y),
+                    (0, 1) => (-x, z, y),
+                    (0, _) => (y, x, -z),
+                    (_, 0) => (-y, x, z),
+                    (_, 1) => (z, x, y),
+                    (_, _) => (-z, -x, y),
+                };
+                Some((origin, (forward + s * right + t * up).normalize()))
+            },
+        }
+    }
+}
+
+#[test]
+fn projection_centres_and_borders() {
+    let forward = Vector3::new(0., 0., 1.);
+    for projection in PROJECTIONS.iter().filter(|&&projection| projection != Projection::CubeMap) {
+        let (_, direction) = projection.map(0.5, 0.5, 4. / 3.).unwrap();
+        assert!((direction - forward).magnitude() < 1e-5);
+    }
+    // the edge of a fisheye circle is perpendicular to the view, outside of it there is nothing
+    let (_, direction) = Projection::FisheyeEquisolid.map(0.5, 0., 1.).unwrap();
+    assert!(direction.z.abs() < 1e-5 && direction.y > 0.99);
+    assert_eq!(Projection::FisheyeEquidistant.map(0., 0., 1.), None);
+    // the panorama wraps around behind the camera
+    let (_, direction) = Projection::Equirectangular.map(0., 0.5, 2.).unwrap();
+    assert!((direction + forward).magnitude() < 1e-5);
+    // the centre of the fifth face looks forward
+    let (_, direction) = Projection::CubeMap.map(0.5, 0.75, 1.5).unwrap();
+    assert!((direction - forward).magnitude() < 1e-5);
+}
@@ -0,0 +1,49 @@
+extern crate rand;
+use self::rand::{Rng, Rand, SeedableRng, XorShiftRng};
+use std::cell::RefCell;
+
+thread_local! {
+    // seeded by the operating system until a render job restarts it
+    static GENERATOR: RefCell<XorShiftRng> = RefCell::new(rand::thread_rng().gen());
+}
+
+/// A random value from the generator of this thread, like `rand::random`
+pub fn random<T: Rand>() -> T {
+    GENERATOR.with(|generator| generator.borrow_mut().gen())
+}
+
+/// runs a function that needs a generator, such as the samplers of `rand::distributions`
+pub fn with_generator<F: FnOnce(&mut XorShiftRng) -> R, R>(f: F) -> R {
+    GENERATOR.with(|generator| f(&mut generator.borrow_mut()))
+}
+
+/// Restarts the generator of this thread at a stream that only depends on the seed of the
+/// render, the pass and the chunk of pixels. Every job seeds itself, so a pass comes out the
+/// same whichever thread renders it, and a resumed render continues where it stopped.
+pub fn seed(seed: u64, pass: u32, chunk: u32) {
+    let a = mix(seed ^ mix((pass as u64) << 32 | chunk as u64));
+    let b = mix(a);
+    // xorshift gets stuck on a seed of only zeros
+    let state = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32 | 1];
+    GENERATOR.with(|generator| *generator.borrow_mut() = XorShiftRng::from_seed(state));
+}
+
+// splitmix64, nearby passes and chunks get unrelated streams
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[test]
+fn seeded_streams_repeat() {
+    seed(42, 3, 5);
+    let first: Vec<f32> = (0..8).map(|_| random()).collect();
+    seed(42, 3, 6);
+    let other: Vec<f32> = (0..8).map(|_| random()).collect();
+    seed(42, 3, 5);
+    let again: Vec<f32> = (0..8).map(|_| random()).collect();
+    assert_eq!(first, again);
+    assert!(first != other);
+}
@@ -1,12 +1,20 @@
 extern crate cgmath;
-use self::cgmath::{Vector3, Point3};
+use self::cgmath::{Vector3, Point3, Point2};
 use std::f32;
 use material::Material;
 
 pub struct Intersection<'m> {
     pub normal: Vector3<f32>,
+    // normal of the actual surface, without interpolation
+    pub geometric_normal: Vector3<f32>,
+    // barycentric coordinates on triangles, surface coordinates on other primitives
+    pub uv: Point2<f32>,
+    // direction along a curve, which hair scatters around
+    pub tangent: Option<Vector3<f32>>,
     pub inside: bool,
     pub area: f32,
+    // the mesh or object the primitive belongs to
+    pub object_id: u32,
     pub material: &'m Material,
 }
 
@@ -15,6 +23,8 @@ pub struct Ray {
     pub origin: Point3<f32>,
     pub direction: Vector3<f32>,
     pub distance: f32,
+    // moment in the frame the ray exists at, 0 at the start and 1 at the end of the motion
+    pub time: f32,
 }
 
 impl Ray {
@@ -23,12 +33,17 @@ impl Ray {
             origin: origin,
             direction: direction,
             distance: distance,
+            time: 0.,
         }
     }
+    pub fn with_time(mut self, time: f32) -> Ray {
+        self.time = time;
+        self
+    }
     pub fn reset(&mut self, origin: Point3<f32>, direction: Vector3<f32>, distance: f32) {
         self.origin = origin + 20. * f32::EPSILON * direction; // advance ray
         self.direction = direction;
-        self.distance = distance; // set length
+        self.distance = distance; // set length, the time stays the same
     }
     pub fn intersection(&self) -> Point3<f32> {
         self.origin + self.distance * self.direction
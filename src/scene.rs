@@ -1,8 +1,9 @@
 extern crate cgmath;
-use self::cgmath::{Vector3, Point3};
+use self::cgmath::{Vector3, Point3, Matrix4, Rad, SquareMatrix};
 use std::io;
 use std::path::Path;
 use std::mem;
+use std::sync::Arc;
 use bvh::BVH;
 use std::f32::consts::FRAC_1_PI;
 extern crate memmap;
@@ -11,34 +12,221 @@ use self::memmap::*;
 use primitive::Primitive;
 use primitive::sphere::Sphere;
 use primitive::triangle::Triangle;
+use primitive::triangle_mesh::{Geometry, MeshTriangle};
+use primitive::moving::Moving;
+use primitive::instance::Instance;
+use primitive::shape;
+use primitive::shape::Shape;
+use primitive::plane::Plane;
+use primitive::disk::Disk;
+use primitive::quad::Quad;
+use primitive::cuboid::Cuboid;
+use primitive::cylinder::Cylinder;
+use primitive::cone::Cone;
+use primitive::torus::Torus;
+use primitive::sdf::{Sdf, Distance, DistanceFn};
+use primitive::aabb::AABB;
+use primitive::csg::{Csg, Operation};
+use primitive::curve::{Curve, CurveKind};
+use transform;
 use material;
 use material::Material;
+use spectrum;
+use spectrum::Dispersion;
+use medium::Medium;
+use volume::GridVolume;
 use mesh;
+use cache;
+use graph::{SceneGraph, Node};
 
 #[derive(Debug)]
 pub struct Scene<T: Primitive> {
     pub bvh: BVH<T>,
+    // medium filling the whole scene outside of any volume
+    pub fog: Option<Medium>,
+    // heterogeneous media, placed independently of the primitives
+    pub volumes: Vec<GridVolume>,
     skybox: Vec<f32>,
 }
 
 impl<T: Primitive> Scene<T> {
     // creates a new default scene
     fn new(objects: Vec<T>) -> Result<Scene<T>, io::Error> {
+        Scene::from_bvh(BVH::new(objects))
+    }
+
+    // a default scene around a BVH that is already built
+    fn from_bvh(bvh: BVH<T>) -> Result<Scene<T>, io::Error> {
         let skybox = try!(Scene::<T>::read_skybox());
         let scene = Scene {
-            bvh: BVH::new(objects),
+            bvh: bvh,
+            fog: None,
+            volumes: Vec::new(),
             skybox: skybox,
         };
         Ok(scene)
     }
 
+    /// a scene that is black where nothing gets hit, it needs no skybox file
+    pub fn without_skybox(objects: Vec<T>) -> Scene<T> {
+        Scene {
+            bvh: BVH::new(objects),
+            fog: None,
+            volumes: Vec::new(),
+            skybox: Vec::new(),
+        }
+    }
+
+    // fills the space outside of all volumes with a medium
+    pub fn with_fog(self, fog: Medium) -> Scene<T> {
+        Scene {
+            fog: Some(fog),
+            ..self
+        }
+    }
+
+    // places a heterogeneous medium in the scene
+    pub fn with_volume(mut self, volume: GridVolume) -> Scene<T> {
+        self.volumes.push(volume);
+        self
+    }
+
+    /// one scene with the primitives of both, which can be of different kinds.
+    /// The fog is the one of this scene, the volumes of both are kept.
+    pub fn combine<U: Primitive + Into<Shape>>(self, other: Scene<U>) -> Scene<Shape> where T: Into<Shape> {
+        let mut shapes: Vec<Shape> = self.bvh.into_objects().into_iter().map(|object| object.into()).collect();
+        // the objects of the other scene come after the ones of this scene
+        let mut others: Vec<Shape> = other.bvh.into_objects().into_iter().map(|object| object.into()).collect();
+        shape::offset_object_ids(&mut others, shape::object_id_end(&shapes));
+        shapes.extend(others);
+        let mut volumes = self.volumes;
+        volumes.extend(other.volumes);
+        Scene {
+            bvh: BVH::new(shapes),
+            fog: self.fog,
+            volumes: volumes,
+            skybox: self.skybox,
+        }
+    }
+
     pub fn scene(path: &Path) -> Result<Scene<Triangle>, io::Error> {
         let mut triangles = mesh::load_mesh(path, Material::Dielectric{
             refraction_index_n1: 1.0,
             refraction_index_n2: 1.5,
             color: Vector3::new(0.01,0.01,0.9),
+            dispersion: spectrum::BK7,
+        });
+        //let mut triangles = mesh::load_mesh(path, Material::jade());
+        // the light and the floor get their own ids after the models
+        let object_id = triangles.iter().map(|triangle| triangle.object_id + 1).max().unwrap_or(0);
+
+        triangles.extend(Scene::<T>::light_and_floor(object_id));
+
+        // Rungholt large light
+        //triangles.push(Triangle{
+        //    position0: Point3::new(300.0,300.0,300.0),
+        //    position1: Point3::new(150.0,300.0,300.0),
+        //    position2: Point3::new(300.0,300.0,150.0),
+        //    normal0: Vector3::new(0.0,-1.0,0.0),
+        //    normal1: Vector3::new(0.0,-1.0,0.0),
+        //    normal2: Vector3::new(0.0,-1.0,0.0),
+        //    material: Material::Emissive {
+        //        color: 150.0 * material::LIGHT_COLOR,
+        //    },
+        //    object_id: object_id,
+        //});
+
+        let scene = try!(Scene::new(triangles));
+        Ok(scene)
+    }
+
+    /// the same as scene, but with the model kept as indexed meshes, so shared vertices
+    /// and the materials are stored once, for models too big to hold as separate triangles
+    pub fn indexed_scene(path: &Path) -> Result<Scene<MeshTriangle>, io::Error> {
+        let mut geometry = mesh::load_geometry(path, Material::Dielectric{
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.5,
+            color: Vector3::new(0.01,0.01,0.9),
+            dispersion: spectrum::BK7,
+        });
+        let object_id = geometry.meshes.iter().map(|mesh| mesh.object_id + 1).max().unwrap_or(0);
+        geometry.add_triangles(&Scene::<T>::light_and_floor(object_id));
+        Scene::new(Geometry::triangles(&Arc::new(geometry)))
+    }
+
+    /// indexed_scene, loaded with its BVH from the cache next to the model when it is up to date
+    pub fn cached_scene(path: &Path) -> Result<Scene<MeshTriangle>, io::Error> {
+        let bvh = try!(cache::load_or_build(path, Material::Dielectric{
+            refraction_index_n1: 1.0,
+            refraction_index_n2: 1.5,
+            color: Vector3::new(0.01,0.01,0.9),
+            dispersion: spectrum::BK7,
+        }, &Scene::<T>::light_and_floor));
+        Scene::from_bvh(bvh)
+    }
+
+    /// a field of copies of a model, which is loaded and gets its BVH only once
+    pub fn instanced_scene(path: &Path) -> Result<Scene<Instance<Triangle>>, io::Error> {
+        let triangles = mesh::load_mesh(path, Material::Diffuse {
+            speculaty: 0.2,
+            color: Vector3::new(0.8,0.6,0.4),
         });
+        let object_id = triangles.iter().map(|triangle| triangle.object_id + 1).max().unwrap_or(0);
+        let model = Arc::new(BVH::new(triangles));
+        let bounds = model.bounds();
+        let spacing = 1.5 * (bounds.max.x - bounds.min.x).max(bounds.max.z - bounds.min.z);
+        let mut instances = Vec::new();
+        for x in -2..3 {
+            for z in 0..5 {
+                // every copy turned differently
+                let transform = Matrix4::from_translation(Vector3::new(x as f32 * spacing, 0.0, z as f32 * spacing))
+                    * Matrix4::from_angle_y(Rad(0.7 * (x + 5 * z) as f32));
+                instances.extend(Instance::new(model.clone(), transform));
+            }
+        }
+        // the light is an instance of its own, so it can be sampled
+        let mut light_and_floor = Scene::<T>::light_and_floor(object_id);
+        let floor = light_and_floor.split_off(1);
+        instances.extend(Instance::new(Arc::new(BVH::new(light_and_floor)), Matrix4::identity()));
+        instances.extend(Instance::new(Arc::new(BVH::new(floor)), Matrix4::identity()));
+        Scene::new(instances)
+    }
+
+    /// a scene graph with the model standing on the floor in the middle, instead of
+    /// where the file put it, with nodes named "model", "light" and "floor"
+    pub fn model_graph(path: &Path) -> SceneGraph {
+        let mut graph = SceneGraph::new();
+        let triangles = mesh::load_mesh(path, Material::jade());
+        let object_id = triangles.iter().map(|triangle| triangle.object_id + 1).max().unwrap_or(0);
+        let model = graph.add_mesh(triangles);
+        let mut light = Scene::<T>::light_and_floor(object_id);
+        let floor = graph.add_mesh(light.split_off(1));
+        let light = graph.add_mesh(light);
+        let bounds = graph.mesh_bounds(model);
+        let centre = bounds.min + 0.5 * (bounds.max - bounds.min);
+        graph.root = Node::new("root")
+            .with_child(Node::new("model").with_mesh(model).with_translation(Vector3::new(-centre.x, -0.3 - bounds.min.y, -centre.z)))
+            .with_child(Node::new("light").with_mesh(light))
+            .with_child(Node::new("floor").with_mesh(floor));
+        graph
+    }
+
+    /// the triangles of a scene graph, copied into the world
+    pub fn from_graph(graph: &SceneGraph) -> Result<Scene<Triangle>, io::Error> {
+        Scene::new(graph.flatten())
+    }
 
+    /// a scene graph with a BVH for every mesh and one over their instances
+    pub fn from_graph_instanced(graph: &SceneGraph) -> Result<Scene<Instance<Triangle>>, io::Error> {
+        Scene::new(graph.instances())
+    }
+
+    // the light above the model and the floor below it, with the given and the next object id.
+    // The floor stays two large triangles instead of a `Plane`: the mesh scenes hold a single
+    // kind of primitive, which a `Shape` would make bigger for every triangle of the model, and
+    // the scene cache stores triangles only, so a plane would mean building the BVH again
+    fn light_and_floor(object_id: u32) -> Vec<Triangle> {
+        let mut triangles = Vec::new();
         // Light
         triangles.push(Triangle{
             position0: Point3::new(2.0,2.0,2.0),
@@ -50,6 +238,7 @@ impl<T: Primitive> Scene<T> {
             material: Material::Emissive {
                 color: material::LIGHT_COLOR,
             },
+            object_id: object_id,
         });
         // Floor
         triangles.push(Triangle{
@@ -63,6 +252,7 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.5,
                 color: Vector3::new(0.9,0.9,0.9),
             },
+            object_id: object_id + 1,
         });
         triangles.push(Triangle{
             position0: Point3::new(-200.0,-0.3,-200.0),
@@ -75,28 +265,121 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.5,
                 color: Vector3::new(0.9,0.9,0.9),
             },
+            object_id: object_id + 1,
         });
+        triangles
+    }
 
+    /// every analytic primitive on a real plane as floor, lit by a rectangular light
+    pub fn analytic_scene() -> Result<Scene<Shape>, io::Error> {
+        print!("Setting up analytic_scene\n");
+        let diffuse = |color: Vector3<f32>| Material::Diffuse { speculaty: 0.1, color: color };
+        let shapes: Vec<Shape> = vec![
+            Quad::light(Point3::new(-0.5,2.0,-0.5), Vector3::new(1.0,0.0,0.0), Vector3::new(0.0,0.0,1.0)).into(),
+            Plane { position: Point3::new(0.0,-0.3,0.0), normal: Vector3::new(0.0,1.0,0.0), material: diffuse(Vector3::new(0.9,0.9,0.9)), object_id: 1 }.into(),
+            Cuboid { min: Point3::new(0.2,-0.3,-0.2), max: Point3::new(0.6,0.1,0.2), material: diffuse(Vector3::new(0.9,0.2,0.2)), object_id: 2 }.into(),
+            Cylinder { position: Point3::new(0.5,-0.3,0.8), radius: 0.2, height: 0.5, material: diffuse(Vector3::new(0.2,0.9,0.2)), object_id: 3 }.into(),
+            Cone { position: Point3::new(1.2,-0.3,0.3), radius: 0.25, height: 0.6, material: diffuse(Vector3::new(0.2,0.2,0.9)), object_id: 4 }.into(),
+            Torus { position: Point3::new(0.9,-0.2,-0.6), major_radius: 0.3, minor_radius: 0.1, material: Material::jade(), object_id: 5 }.into(),
+            Disk { position: Point3::new(0.0,-0.29,0.8), normal: Vector3::new(0.0,1.0,0.0), radius: 0.3, material: diffuse(Vector3::new(0.9,0.9,0.2)), object_id: 6 }.into(),
+        ];
+        Scene::new(shapes)
+    }
 
-        // Rungholt large light
-        //triangles.push(Triangle{
-        //    position0: Point3::new(300.0,300.0,300.0),
-        //    position1: Point3::new(150.0,300.0,300.0),
-        //    position2: Point3::new(300.0,300.0,150.0),
-        //    normal0: Vector3::new(0.0,-1.0,0.0),
-        //    normal1: Vector3::new(0.0,-1.0,0.0),
-        //    normal2: Vector3::new(0.0,-1.0,0.0),
-        //    material: Material::Emissive {
-        //        color: 150.0 * material::LIGHT_COLOR,
-        //    },
-        //});
+    /// strands of hair from a file as round curves, with the light and the floor of `Scene::scene`
+    pub fn hair_scene(path: &Path) -> Result<Scene<Shape>, io::Error> {
+        print!("Setting up hair_scene\n");
+        let curves = try!(Curve::load(path, CurveKind::Round, Material::hair(1.3, 0.2)));
+        let object_id = curves.iter().map(|curve| curve.bezier.object_id + 1).max().unwrap_or(0);
+        let mut shapes: Vec<Shape> = curves.into_iter().map(Shape::from).collect();
+        shapes.extend(Scene::<T>::light_and_floor(object_id).into_iter().map(Shape::from));
+        Scene::new(shapes)
+    }
 
-        let scene = try!(Scene::new(triangles));
-        Ok(scene)
+    /// distance functions traced next to a plane and a quad light
+    pub fn sdf_scene() -> Result<Scene<Shape>, io::Error> {
+        print!("Setting up sdf_scene\n");
+        let diffuse = |color: Vector3<f32>| Material::Diffuse { speculaty: 0.1, color: color };
+        // a ball melting into a rounded box with a scoop out of one corner
+        let blob = Distance::SmoothSubtraction(
+            Box::new(Distance::SmoothUnion(
+                Box::new(Distance::RoundedBox { half_size: Vector3::new(0.25,0.2,0.25), radius: 0.05 }),
+                Box::new(Distance::Translate(Box::new(Distance::Sphere { radius: 0.18 }), Vector3::new(0.0,0.25,0.0))), 0.1)),
+            Box::new(Distance::Translate(Box::new(Distance::Sphere { radius: 0.2 }), Vector3::new(0.25,0.2,0.25))), 0.02);
+        // a row of rings, the bounds cut the endless repetition off after three
+        let rings = Distance::Repeat(Box::new(Distance::Torus { major_radius: 0.12, minor_radius: 0.04 }), Vector3::new(0.35,0.0,0.0));
+        // a sphere with bumps, the distance is scaled down as the bumps make it too large
+        let bumpy = Distance::Custom(DistanceFn::new("bumpy sphere", |p: Point3<f32>| {
+            let bumps = 0.03 * (12.0 * p.x).sin() * (12.0 * p.y).sin() * (12.0 * p.z).sin();
+            0.6 * ((p.x * p.x + p.y * p.y + p.z * p.z).sqrt() - 0.25 + bumps)
+        }));
+        let shapes: Vec<Shape> = vec![
+            Quad::light(Point3::new(-0.5,2.0,-0.5), Vector3::new(1.0,0.0,0.0), Vector3::new(0.0,0.0,1.0)).into(),
+            Plane { position: Point3::new(0.0,-0.3,0.0), normal: Vector3::new(0.0,1.0,0.0), material: diffuse(Vector3::new(0.9,0.9,0.9)), object_id: 1 }.into(),
+            Sdf { distance: blob, position: Point3::new(0.4,-0.1,0.0),
+                  bounds: AABB { min: Point3::new(0.1,-0.35,-0.35), max: Point3::new(0.7,0.4,0.35) },
+                  material: diffuse(Vector3::new(0.9,0.2,0.2)), object_id: 2 }.into(),
+            Sdf { distance: rings, position: Point3::new(0.6,-0.26,0.7),
+                  bounds: AABB { min: Point3::new(0.075,-0.31,0.53), max: Point3::new(1.125,-0.21,0.87) },
+                  material: Material::jade(), object_id: 3 }.into(),
+            Sdf { distance: bumpy, position: Point3::new(1.2,0.0,-0.4),
+                  bounds: AABB { min: Point3::new(0.9,-0.3,-0.7), max: Point3::new(1.5,0.3,-0.1) },
+                  material: diffuse(Vector3::new(0.2,0.2,0.9)), object_id: 4 }.into(),
+        ];
+        Scene::new(shapes)
+    }
+
+    /// solids combined into a lens, a box with a bite out of it and a tube
+    pub fn csg_scene() -> Result<Scene<Shape>, io::Error> {
+        print!("Setting up csg_scene\n");
+        let diffuse = |color: Vector3<f32>| Material::Diffuse { speculaty: 0.1, color: color };
+        let glass = Material::Dielectric {
+            refraction_index_n1: 1.,
+            refraction_index_n2: 1.5,
+            color: Vector3::new(1.0,1.0,1.0),
+            dispersion: Dispersion::None,
+        };
+        let sphere = |position: Point3<f32>, radius: f32, material: Material| Shape::from(Sphere { position: position, radius: radius, material: material, object_id: 2 });
+        // where two large spheres overlap is a thin lens standing on its edge
+        let lens = Csg::new(Operation::Intersection,
+                            sphere(Point3::new(0.3,0.05,-0.6), 0.6, glass),
+                            sphere(Point3::new(0.3,0.05,0.4), 0.6, glass));
+        let bite = Csg::new(Operation::Difference,
+                            Shape::from(Cuboid { min: Point3::new(0.7,-0.3,-0.2), max: Point3::new(1.1,0.1,0.2), material: diffuse(Vector3::new(0.9,0.2,0.2)), object_id: 3 }),
+                            sphere(Point3::new(1.1,0.1,-0.2), 0.25, diffuse(Vector3::new(0.9,0.9,0.2))));
+        let tube = Csg::new(Operation::Difference,
+                            Shape::from(Cylinder { position: Point3::new(0.4,-0.3,0.7), radius: 0.2, height: 0.4, material: diffuse(Vector3::new(0.2,0.9,0.2)), object_id: 4 }),
+                            Shape::from(Cylinder { position: Point3::new(0.4,-0.4,0.7), radius: 0.15, height: 0.6, material: diffuse(Vector3::new(0.9,0.9,0.9)), object_id: 4 }));
+        let shapes: Vec<Shape> = vec![
+            Quad::light(Point3::new(-0.5,2.0,-0.5), Vector3::new(1.0,0.0,0.0), Vector3::new(0.0,0.0,1.0)).into(),
+            Plane { position: Point3::new(0.0,-0.3,0.0), normal: Vector3::new(0.0,1.0,0.0), material: diffuse(Vector3::new(0.9,0.9,0.9)), object_id: 1 }.into(),
+            lens.into(),
+            bite.into(),
+            tube.into(),
+        ];
+        Scene::new(shapes)
     }
 
     pub fn default_scene() -> Result<Scene<Sphere>, io::Error> {
         print!("Setting up default_scene\n");
+        Scene::new(Scene::<T>::default_spheres())
+    }
+
+    /// the default scene with the mirror spheres moving during the frame, for motion blur
+    pub fn motion_scene() -> Result<Scene<Moving<Sphere>>, io::Error> {
+        print!("Setting up motion_scene\n");
+        let spheres = Scene::<T>::default_spheres().into_iter().map(|sphere| {
+            let end = match sphere.object_id {
+                1 => transform::translation(Vector3::new(0.0, 0.4, 0.0)),
+                3 => transform::translation(Vector3::new(0.3, 0.0, 0.3)),
+                _ => transform::identity(),
+            };
+            Moving::new(sphere, transform::identity(), end)
+        }).collect();
+        Scene::new(spheres)
+    }
+
+    fn default_spheres() -> Vec<Sphere> {
         let mut spheres = Vec::new();
         spheres.push(Sphere::light(Point3::new(2.7,1.7,-0.5), 0.3));
 
@@ -108,6 +391,7 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.8,
                 color: Vector3::new(1.0,0.2,0.2),
             },
+            object_id: 1,
         });
 
         spheres.push(Sphere {
@@ -117,7 +401,9 @@ impl<T: Primitive> Scene<T> {
                 refraction_index_n1: 1.,
                 refraction_index_n2: 1.3,
                 color: Vector3::new(0.1,1.0,0.1),
+                dispersion: Dispersion::Cauchy { b: 0.0042 },
             },
+            object_id: 2,
         });
 
 
@@ -128,6 +414,7 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.8,
                 color: Vector3::new(0.2, 0.2, 1.0),
             },
+            object_id: 3,
         });
 
         spheres.push(Sphere {
@@ -137,6 +424,7 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.,
                 color: Vector3::new(1.0,1.0,1.0),
             },
+            object_id: 4,
         });
         spheres.push(Sphere {
             position: Point3::new(-0.0,-0.8,-2.0),
@@ -145,6 +433,7 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.,
                 color: Vector3::new(1.0,1.0,1.0),
             },
+            object_id: 5,
         });
         spheres.push(Sphere {
             position: Point3::new(0.8,-0.8,-2.0),
@@ -153,13 +442,15 @@ impl<T: Primitive> Scene<T> {
                 speculaty: 0.,
                 color: Vector3::new(1.0,1.0,1.0),
             },
+            object_id: 6,
         });
-
-        let scene = try!(Scene::new(spheres));
-        Ok(scene)
+        spheres
     }
 
     pub fn sample_skybox(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        if self.skybox.is_empty() {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
         let u = (2500.0 * 0.5 * (1.0 + direction.x.atan2(-direction.z) * FRAC_1_PI)) as usize;
         let v = (1250.0 * (direction.y.acos() * FRAC_1_PI)) as usize;
         let idx = u + v * 2500;
@@ -0,0 +1,211 @@
+extern crate cgmath;
+use self::cgmath::Vector3;
+use std::f32;
+use random::random;
+use rand::Closed01;
+
+use medium::Medium;
+
+// visible range of wavelengths in nanometres
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+// wavelength of the sodium d-line, refraction indices are specified at this wavelength
+pub const LAMBDA_D: f32 = 589.3;
+// integral of the CIE y colour matching function over the visible range
+const CIE_Y_INTEGRAL: f32 = 106.922;
+// scales the equal energy white of a constant spectrum to white in sRGB
+const WHITE_SCALE: Vector3<f32> = Vector3 { x: 0.833147, y: 1.052965, z: 1.100963 };
+
+// Smits' basis spectra for RGB to spectrum upsampling, 10 bins from 380 to 720nm
+const SMITS_MIN: f32 = 380.0;
+const SMITS_MAX: f32 = 720.0;
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Wavelength dependent refraction index of a dielectric
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    None,
+    // n(λ) = n_d + b (1/λ² - 1/λ_d²) with λ in micrometres
+    Cauchy { b: f32 },
+    // Sellmeier equation with λ in micrometres, replaces the refraction index entirely
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn is_none(&self) -> bool {
+        match *self {
+            Dispersion::None => true,
+            _ => false,
+        }
+    }
+
+    /// refraction index at the given wavelength in nanometres,
+    /// `refraction_index` is the index at the sodium d-line
+    pub fn refraction_index(&self, refraction_index: f32, lambda: f32) -> f32 {
+        let micrometres = lambda / 1000.;
+        match *self {
+            Dispersion::None => refraction_index,
+            Dispersion::Cauchy { b } => {
+                let d = LAMBDA_D / 1000.;
+                refraction_index + b * (1. / (micrometres * micrometres) - 1. / (d * d))
+            },
+            Dispersion::Sellmeier { b, c } => {
+                let l2 = micrometres * micrometres;
+                (1. + b[0] * l2 / (l2 - c[0]) + b[1] * l2 / (l2 - c[1]) + b[2] * l2 / (l2 - c[2])).sqrt()
+            },
+        }
+    }
+}
+
+/// Schott BK7 crown glass
+pub const BK7: Dispersion = Dispersion::Sellmeier {
+    b: [1.03961212, 0.231792344, 1.01046945],
+    c: [0.00600069867, 0.0200179144, 103.560653],
+};
+
+/// The wavelengths a camera ray carries. In RGB mode colours are used as is,
+/// in spectral mode the three channels of a colour hold the spectrum at a hero
+/// wavelength and two wavelengths rotated by a third of the visible range.
+#[derive(Debug, Copy, Clone)]
+pub enum Wavelengths {
+    Rgb,
+    Spectral { lambda: Vector3<f32> },
+}
+
+impl Wavelengths {
+    /// samples a hero wavelength uniformly over the visible range
+    pub fn sample() -> Wavelengths {
+        let Closed01(r0) = random::<Closed01<f32>>();
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let rotate = |offset: f32| LAMBDA_MIN + ((r0 + offset) % 1.) * range;
+        Wavelengths::Spectral { lambda: Vector3::new(rotate(0.), rotate(1. / 3.), rotate(2. / 3.)) }
+    }
+
+    pub fn hero(&self) -> Option<f32> {
+        match *self {
+            Wavelengths::Rgb => None,
+            Wavelengths::Spectral { lambda } => Some(lambda.x),
+        }
+    }
+
+    /// the colour at the carried wavelengths
+    pub fn upsample(&self, rgb: Vector3<f32>) -> Vector3<f32> {
+        match *self {
+            Wavelengths::Rgb => rgb,
+            Wavelengths::Spectral { lambda } => Vector3::new(rgb_to_spectrum(rgb, lambda.x),
+                                                             rgb_to_spectrum(rgb, lambda.y),
+                                                             rgb_to_spectrum(rgb, lambda.z)),
+        }
+    }
+
+    /// the medium with its coefficients at the carried wavelengths
+    pub fn medium(&self, medium: Medium) -> Medium {
+        Medium {
+            absorption: self.upsample(medium.absorption),
+            scattering: self.upsample(medium.scattering),
+            anisotropy: medium.anisotropy,
+        }
+    }
+
+    /// converts radiance at the carried wavelengths to linear sRGB
+    pub fn to_rgb(&self, radiance: Vector3<f32>) -> Vector3<f32> {
+        match *self {
+            Wavelengths::Rgb => radiance,
+            Wavelengths::Spectral { lambda } => {
+                // monte carlo estimate of the XYZ integrals with uniform wavelength pdf
+                let scale = (LAMBDA_MAX - LAMBDA_MIN) / (3. * CIE_Y_INTEGRAL);
+                let xyz = scale * (radiance.x * cie_xyz(lambda.x) + radiance.y * cie_xyz(lambda.y) + radiance.z * cie_xyz(lambda.z));
+                let rgb = xyz_to_rgb(xyz);
+                Vector3::new(rgb.x * WHITE_SCALE.x, rgb.y * WHITE_SCALE.y, rgb.z * WHITE_SCALE.z)
+            },
+        }
+    }
+}
+
+fn smits(basis: &[f32; 10], lambda: f32) -> f32 {
+    // linear interpolation between the bin centres
+    let bin = ((lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * 10. - 0.5).max(0.).min(9.);
+    let first = (bin as usize).min(8);
+    let t = bin - first as f32;
+    basis[first] + t * (basis[first + 1] - basis[first])
+}
+
+/// Smits' RGB to spectrum conversion, evaluated at a single wavelength
+pub fn rgb_to_spectrum(rgb: Vector3<f32>, lambda: f32) -> f32 {
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    if r <= g && r <= b {
+        r * smits(&SMITS_WHITE, lambda) + if g <= b {
+            (g - r) * smits(&SMITS_CYAN, lambda) + (b - g) * smits(&SMITS_BLUE, lambda)
+        } else {
+            (b - r) * smits(&SMITS_CYAN, lambda) + (g - b) * smits(&SMITS_GREEN, lambda)
+        }
+    } else if g <= r && g <= b {
+        g * smits(&SMITS_WHITE, lambda) + if r <= b {
+            (r - g) * smits(&SMITS_MAGENTA, lambda) + (b - r) * smits(&SMITS_BLUE, lambda)
+        } else {
+            (b - g) * smits(&SMITS_MAGENTA, lambda) + (r - b) * smits(&SMITS_RED, lambda)
+        }
+    } else {
+        b * smits(&SMITS_WHITE, lambda) + if r <= g {
+            (r - b) * smits(&SMITS_YELLOW, lambda) + (g - r) * smits(&SMITS_GREEN, lambda)
+        } else {
+            (g - b) * smits(&SMITS_YELLOW, lambda) + (r - g) * smits(&SMITS_RED, lambda)
+        }
+    }
+}
+
+fn piecewise_gaussian(lambda: f32, mean: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let t = (lambda - mean) / if lambda < mean { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions, the multi-lobe fit of Wyman, Sloan and Shirley
+pub fn cie_xyz(lambda: f32) -> Vector3<f32> {
+    Vector3::new(1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0) +
+                 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7) -
+                 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2),
+                 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5) +
+                 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1),
+                 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0) +
+                 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8))
+}
+
+/// XYZ to linear sRGB with a D65 white point
+pub fn xyz_to_rgb(xyz: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new( 3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+                 -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+                  0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z)
+}
+
+#[test]
+fn spectral_round_trip() {
+    // averaging many spectral samples of an upsampled colour returns the colour
+    for rgb in [Vector3::new(1.0, 1.0, 1.0), Vector3::new(0.9, 0.2, 0.1), Vector3::new(0.1, 0.3, 0.8)].iter() {
+        let steps = 3000;
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            let offset = i as f32 / (3 * steps) as f32;
+            let lambda = |t: f32| LAMBDA_MIN + (t + offset) * (LAMBDA_MAX - LAMBDA_MIN);
+            let lambda = Vector3::new(lambda(0.), lambda(1. / 3.), lambda(2. / 3.));
+            let wavelengths = Wavelengths::Spectral { lambda: lambda };
+            sum += wavelengths.to_rgb(wavelengths.upsample(*rgb));
+        }
+        let average = sum / steps as f32;
+        assert!((average.x - rgb.x).abs() < 0.1 && (average.y - rgb.y).abs() < 0.1 && (average.z - rgb.z).abs() < 0.1);
+    }
+}
+
+#[test]
+fn dispersion_refraction_index() {
+    // BK7 is 1.5168 at the d-line and disperses blue more than red
+    assert!((BK7.refraction_index(1.5, LAMBDA_D) - 1.5168).abs() < 1e-3);
+    assert!(BK7.refraction_index(1.5, 450.) > BK7.refraction_index(1.5, 650.));
+    let cauchy = Dispersion::Cauchy { b: 0.0042 };
+    assert!((cauchy.refraction_index(1.5, LAMBDA_D) - 1.5).abs() < 1e-6);
+}
@@ -0,0 +1,80 @@
+extern crate cgmath;
+use self::cgmath::{Vector3, Point3, Quaternion, Decomposed, One, InnerSpace};
+use self::cgmath::Transform as TransformTrait;
+use std::f32;
+
+use primitive::aabb::AABB;
+
+/// Uniform scale, then rotation, then translation
+pub type Transform = Decomposed<Vector3<f32>, Quaternion<f32>>;
+
+pub fn identity() -> Transform {
+    Decomposed {
+        scale: 1.,
+        rot: Quaternion::one(),
+        disp: Vector3::new(0., 0., 0.),
+    }
+}
+
+pub fn translation(displacement: Vector3<f32>) -> Transform {
+    Decomposed { disp: displacement, ..identity() }
+}
+
+/// the transform a fraction `t` of the way from `start` to `end`, the rotation turns at a constant speed
+pub fn interpolate(start: &Transform, end: &Transform, t: f32) -> Transform {
+    // q and -q are the same rotation, take the short way round
+    let end_rot = if start.rot.dot(end.rot) < 0. { -end.rot } else { end.rot };
+    Decomposed {
+        scale: start.scale + t * (end.scale - start.scale),
+        rot: start.rot.slerp(end_rot, t),
+        disp: start.disp + t * (end.disp - start.disp),
+    }
+}
+
+/// bounds of a box under any affine transform
+pub fn transform_bounds<T: TransformTrait<Point3<f32>>>(transform: &T, bounds: &AABB) -> AABB {
+    let mut transformed = AABB::new();
+    for i in 0..8 {
+        let corner = Point3::new(if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+                                 if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+                                 if i & 4 == 0 { bounds.min.z } else { bounds.max.z });
+        transformed = transformed.extent(&transform.transform_point(corner));
+    }
+    transformed
+}
+
+/// Bounds of a box over a motion from `start` to `end`. The box is bounded at a number of
+/// steps, between two steps the translation and scale are linear and stay inside, the
+/// corners of a rotating box bulge out on their arc by at most the sagitta of one step.
+pub fn motion_bounds(start: &Transform, end: &Transform, bounds: &AABB) -> AABB {
+    const STEPS: u32 = 16;
+    let cos_half = start.rot.dot(end.rot).abs().min(1.);
+    let step_angle = 2. * cos_half.acos() / STEPS as f32;
+    let corner = [bounds.min.x.abs().max(bounds.max.x.abs()), bounds.min.y.abs().max(bounds.max.y.abs()), bounds.min.z.abs().max(bounds.max.z.abs())];
+    let radius = (corner[0] * corner[0] + corner[1] * corner[1] + corner[2] * corner[2]).sqrt() * start.scale.max(end.scale);
+    let bulge = radius * (1. - (0.5 * step_angle).cos());
+    let mut motion = AABB::new();
+    for step in 0..STEPS + 1 {
+        motion = motion.combine(&transform_bounds(&interpolate(start, end, step as f32 / STEPS as f32), bounds));
+    }
+    AABB {
+        min: motion.min + Vector3::new(-bulge, -bulge, -bulge),
+        max: motion.max + Vector3::new(bulge, bulge, bulge),
+    }
+}
+
+#[test]
+fn motion_bounds_contain_motion() {
+    use self::cgmath::{Rotation3, Rad};
+    let bounds = AABB { min: Point3::new(-1., -1., -1.), max: Point3::new(1., 1., 1.) };
+    let start = translation(Vector3::new(-2., 0., 0.));
+    let end = Decomposed { rot: Quaternion::from_angle_y(Rad(f32::consts::FRAC_PI_2)), ..translation(Vector3::new(2., 0., 0.)) };
+    let motion = motion_bounds(&start, &end, &bounds);
+    for i in 0..101 {
+        let transform = interpolate(&start, &end, i as f32 / 100.);
+        let corner = transform.transform_point(Point3::new(1., 1., 1.));
+        assert!(corner.x >= motion.min.x && corner.x <= motion.max.x);
+        assert!(corner.z >= motion.min.z && corner.z <= motion.max.z);
+    }
+    assert_eq!(interpolate(&start, &end, 0.5).disp, Vector3::new(0., 0., 0.));
+}
@@ -0,0 +1,378 @@
+extern crate cgmath;
+extern crate memmap;
+use self::cgmath::{Vector3, Point3};
+use self::memmap::*;
+use std::f32;
+use std::io;
+use std::path::Path;
+use random::random;
+use rand::Closed01;
+
+use primitive::aabb::AABB;
+use ray::Ray;
+
+// number of majorant cells along each axis of a volume
+const MAJORANT_RESOLUTION: usize = 16;
+// magic of the sparse brick format, dense grids have no magic
+const BRICK_MAGIC: &'static [u8] = b"BRCK";
+
+/// Voxel grid of densities, stored densely with x running fastest.
+#[derive(Debug)]
+pub struct DensityGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    densities: Vec<f32>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, io::Error> {
+    if offset + 4 > bytes.len() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "density grid is truncated"));
+    }
+    Ok(bytes[offset] as u32 | (bytes[offset + 1] as u32) << 8 |
+       (bytes[offset + 2] as u32) << 16 | (bytes[offset + 3] as u32) << 24)
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> Result<f32, io::Error> {
+    read_u32(bytes, offset).map(f32::from_bits)
+}
+
+impl DensityGrid {
+    /// a grid of the densities, which have to fill it exactly, with at least one voxel
+    pub fn new(nx: usize, ny: usize, nz: usize, densities: Vec<f32>) -> Result<DensityGrid, io::Error> {
+        if try!(DensityGrid::voxels(nx, ny, nz)) != densities.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "density grid doesn't have a density for every voxel"));
+        }
+        Ok(DensityGrid {
+            nx: nx,
+            ny: ny,
+            nz: nz,
+            densities: densities,
+        })
+    }
+
+    // the number of voxels of a grid that isn't empty
+    fn voxels(nx: usize, ny: usize, nz: usize) -> Result<usize, io::Error> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "density grid is empty"));
+        }
+        nx.checked_mul(ny).and_then(|n| n.checked_mul(nz))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "density grid is too large"))
+    }
+
+    /// Loads a density grid, all values are little endian.
+    /// Dense:  `nx ny nz` as u32 followed by `nx * ny * nz` f32 densities.
+    /// Sparse: `BRCK nx ny nz brick_size brick_count` as u32 followed by the
+    ///         bricks, each brick is its `bx by bz` brick coordinate as u32 and
+    ///         `brick_size^3` f32 densities. Missing bricks are empty.
+    pub fn load(path: &Path) -> Result<DensityGrid, io::Error> {
+        let file = try!(Mmap::open_path(path, Protection::Read));
+        let bytes: &[u8] = unsafe { file.as_slice() };
+        if bytes.starts_with(BRICK_MAGIC) {
+            DensityGrid::load_bricks(bytes)
+        } else {
+            DensityGrid::load_dense(bytes)
+        }
+    }
+
+    fn load_dense(bytes: &[u8]) -> Result<DensityGrid, io::Error> {
+        let nx = try!(read_u32(bytes, 0)) as usize;
+        let ny = try!(read_u32(bytes, 4)) as usize;
+        let nz = try!(read_u32(bytes, 8)) as usize;
+        let voxels = try!(DensityGrid::voxels(nx, ny, nz));
+        if voxels > (bytes.len() - 12) / 4 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "density grid is truncated"));
+        }
+        let mut densities = Vec::with_capacity(voxels);
+        for index in 0..voxels {
+            densities.push(try!(read_f32(bytes, 12 + 4 * index)));
+        }
+        DensityGrid::new(nx, ny, nz, densities)
+    }
+
+    fn load_bricks(bytes: &[u8]) -> Result<DensityGrid, io::Error> {
+        let nx = try!(read_u32(bytes, 4)) as usize;
+        let ny = try!(read_u32(bytes, 8)) as usize;
+        let nz = try!(read_u32(bytes, 12)) as usize;
+        let brick_size = try!(read_u32(bytes, 16)) as usize;
+        let brick_count = try!(read_u32(bytes, 20)) as usize;
+        let mut densities = vec![0.0; try!(DensityGrid::voxels(nx, ny, nz))];
+        let mut offset = 24;
+        for _ in 0..brick_count {
+            let bx = try!(read_u32(bytes, offset)) as usize * brick_size;
+            let by = try!(read_u32(bytes, offset + 4)) as usize * brick_size;
+            let bz = try!(read_u32(bytes, offset + 8)) as usize * brick_size;
+            offset += 12;
+            for z in 0..brick_size {
+                for y in 0..brick_size {
+                    for x in 0..brick_size {
+                        let density = try!(read_f32(bytes, offset));
+                        offset += 4;
+                        if bx + x < nx && by + y < ny && bz + z < nz {
+                            densities[(bx + x) + (by + y) * nx + (bz + z) * nx * ny] = density;
+                        }
+                    }
+                }
+            }
+        }
+        DensityGrid::new(nx, ny, nz, densities)
+    }
+
+    /// A puffy cloud made of a few octaves of value noise inside a sphere
+    pub fn cloud(resolution: usize) -> DensityGrid {
+        let resolution = resolution.max(1);
+        let mut densities = Vec::with_capacity(resolution * resolution * resolution);
+        for z in 0..resolution {
+            for y in 0..resolution {
+                for x in 0..resolution {
+                    let p = Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) / resolution as f32;
+                    let centre = p - Vector3::new(0.5, 0.5, 0.5);
+                    let falloff = 1. - 2. * (centre.x * centre.x + centre.y * centre.y + centre.z * centre.z).sqrt();
+                    let noise = 0.5 * value_noise(4. * p) + 0.25 * value_noise(8. * p) + 0.125 * value_noise(16. * p);
+                    densities.push((2. * falloff + noise - 0.4).max(0.).min(1.));
+                }
+            }
+        }
+        DensityGrid::new(resolution, resolution, resolution, densities).expect("cloud fills its grid")
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.densities[x + y * self.nx + z * self.nx * self.ny]
+    }
+
+    /// Trilinear interpolation between voxel centres, `p` is in `[0, 1]^3`
+    pub fn density(&self, p: Vector3<f32>) -> f32 {
+        let x = (p.x * self.nx as f32 - 0.5).max(0.).min(self.nx as f32 - 1.);
+        let y = (p.y * self.ny as f32 - 0.5).max(0.).min(self.ny as f32 - 1.);
+        let z = (p.z * self.nz as f32 - 0.5).max(0.).min(self.nz as f32 - 1.);
+        let (x0, y0, z0) = (x as usize, y as usize, z as usize);
+        let (x1, y1, z1) = ((x0 + 1).min(self.nx - 1), (y0 + 1).min(self.ny - 1), (z0 + 1).min(self.nz - 1));
+        let (fx, fy, fz) = (x - x0 as f32, y - y0 as f32, z - z0 as f32);
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        lerp(lerp(lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx),
+                  lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx), fy),
+             lerp(lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx),
+                  lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx), fy), fz)
+    }
+
+    /// maximum density of all voxels that influence the box `[min, max]` in `[0, 1]^3`
+    fn max_density(&self, min: Vector3<f32>, max: Vector3<f32>) -> f32 {
+        let range = |min: f32, max: f32, n: usize| {
+            let first = (min * n as f32 - 1.).max(0.) as usize;
+            let last = ((max * n as f32 + 1.) as usize).min(n - 1);
+            first..last + 1
+        };
+        let mut max_density = 0.0f32;
+        for z in range(min.z, max.z, self.nz) {
+            for y in range(min.y, max.y, self.ny) {
+                for x in range(min.x, max.x, self.nx) {
+                    max_density = max_density.max(self.voxel(x, y, z));
+                }
+            }
+        }
+        max_density
+    }
+}
+
+fn hash(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663) ^ z.wrapping_mul(83492791)) as u32;
+    h = (h ^ (h >> 13)).wrapping_mul(0x5bd1e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 65535.
+}
+
+fn value_noise(p: Vector3<f32>) -> f32 {
+    let (x, y, z) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let (fx, fy, fz) = (smooth(p.x - x), smooth(p.y - y), smooth(p.z - z));
+    let (x, y, z) = (x as i32, y as i32, z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+    lerp(lerp(lerp(hash(x, y, z), hash(x + 1, y, z), fx),
+              lerp(hash(x, y + 1, z), hash(x + 1, y + 1, z), fx), fy),
+         lerp(lerp(hash(x, y, z + 1), hash(x + 1, y, z + 1), fx),
+              lerp(hash(x, y + 1, z + 1), hash(x + 1, y + 1, z + 1), fx), fy), fz)
+}
+
+/// A heterogeneous medium, a density grid stretched over a box in the scene.
+/// The extinction at a point is the density times the density scale,
+/// the albedo is the fraction of the extinction that scatters.
+#[derive(Debug)]
+pub struct GridVolume {
+    pub grid: DensityGrid,
+    pub bounds: AABB,
+    pub density_scale: f32,
+    pub albedo: Vector3<f32>,
+    pub anisotropy: f32,
+    majorants: Vec<f32>,
+}
+
+impl GridVolume {
+    pub fn new(grid: DensityGrid, bounds: AABB, density_scale: f32, albedo: Vector3<f32>, anisotropy: f32) -> GridVolume {
+        let mut majorants = Vec::with_capacity(MAJORANT_RESOLUTION * MAJORANT_RESOLUTION * MAJORANT_RESOLUTION);
+        let cell = 1. / MAJORANT_RESOLUTION as f32;
+        for z in 0..MAJORANT_RESOLUTION {
+            for y in 0..MAJORANT_RESOLUTION {
+                for x in 0..MAJORANT_RESOLUTION {
+                    let min = Vector3::new(x as f32, y as f32, z as f32) * cell;
+                    majorants.push(grid.max_density(min, min + Vector3::new(cell, cell, cell)));
+                }
+            }
+        }
+        GridVolume {
+            grid: grid,
+            bounds: bounds,
+            density_scale: density_scale,
+            albedo: albedo,
+            anisotropy: anisotropy,
+            majorants: majorants,
+        }
+    }
+
+    fn density(&self, point: Point3<f32>) -> f32 {
+        let size = self.bounds.size();
+        let local = point - self.bounds.min;
+        self.grid.density(Vector3::new(local.x / size.x, local.y / size.y, local.z / size.z))
+    }
+
+    /// Walks the majorant cells along the ray with a 3D DDA. The callback gets
+    /// the start and end distance of the ray inside each cell and the majorant
+    /// extinction of that cell, it returns false to stop the walk.
+    fn traverse<F>(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32, mut f: F)
+        where F: FnMut(f32, f32, f32) -> bool {
+        let ray = Ray::new(origin, direction, max_distance);
+        let (tmin, tmax) = match self.bounds.intersect(&ray) {
+            Some(t) => t,
+            None => return,
+        };
+        let t_start = tmin.max(0.);
+        let t_end = tmax.min(max_distance);
+        if t_start >= t_end {
+            return;
+        }
+        let resolution = MAJORANT_RESOLUTION as f32;
+        let size = self.bounds.size();
+        let start = (origin + t_start * direction) - self.bounds.min;
+        let mut cell = [0i32; 3];
+        let mut step = [0i32; 3];
+        let mut delta = [f32::INFINITY; 3];
+        let mut next = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            // position and direction in cell units
+            let p = start[axis] / size[axis] * resolution;
+            let d = direction[axis] / size[axis] * resolution;
+            cell[axis] = (p.floor() as i32).max(0).min(MAJORANT_RESOLUTION as i32 - 1);
+            if d > 0. {
+                step[axis] = 1;
+                delta[axis] = 1. / d;
+                next[axis] = t_start + (cell[axis] as f32 + 1. - p) / d;
+            } else if d < 0. {
+                step[axis] = -1;
+                delta[axis] = -1. / d;
+                next[axis] = t_start + (cell[axis] as f32 - p) / d;
+            }
+        }
+        let mut t = t_start;
+        loop {
+            let axis = if next[0] < next[1] {
+                if next[0] < next[2] { 0 } else { 2 }
+            } else {
+                if next[1] < next[2] { 1 } else { 2 }
+            };
+            let t_next = next[axis].min(t_end);
+            let index = cell[0] as usize + MAJORANT_RESOLUTION * (cell[1] as usize + MAJORANT_RESOLUTION * cell[2] as usize);
+            if t_next > t && !f(t, t_next, self.majorants[index] * self.density_scale) {
+                return;
+            }
+            if t_next >= t_end {
+                return;
+            }
+            t = t_next;
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= MAJORANT_RESOLUTION as i32 {
+                return;
+            }
+            next[axis] += delta[axis];
+        }
+    }
+
+    /// Delta tracking, returns the distance of the first real collision
+    /// before `max_distance` along the ray
+    pub fn sample_collision(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<f32> {
+        let mut collision = None;
+        self.traverse(origin, direction, max_distance, |t0, t1, majorant| {
+            if majorant <= 0. {
+                return true;
+            }
+            let mut t = t0;
+            loop {
+                let Closed01(r0) = random::<Closed01<f32>>();
+                t -= (1. - r0).max(f32::MIN_POSITIVE).ln() / majorant;
+                if t >= t1 {
+                    return true;
+                }
+                let Closed01(r1) = random::<Closed01<f32>>();
+                if r1 * majorant < self.density(origin + t * direction) * self.density_scale {
+                    collision = Some(t);
+                    return false;
+                }
+            }
+        });
+        collision
+    }
+
+    /// Ratio tracking estimate of the transmittance over `distance` along the ray
+    pub fn transmittance(&self, origin: Point3<f32>, direction: Vector3<f32>, distance: f32) -> f32 {
+        let mut transmittance = 1.;
+        self.traverse(origin, direction, distance, |t0, t1, majorant| {
+            if majorant <= 0. {
+                return true;
+            }
+            let mut t = t0;
+            loop {
+                let Closed01(r0) = random::<Closed01<f32>>();
+                t -= (1. - r0).max(f32::MIN_POSITIVE).ln() / majorant;
+                if t >= t1 {
+                    return true;
+                }
+                transmittance *= 1. - self.density(origin + t * direction) * self.density_scale / majorant;
+                if transmittance < 0.1 {
+                    // russian roulette on the remaining transmittance
+                    let Closed01(russian_roulette) = random::<Closed01<f32>>();
+                    if russian_roulette < 0.5 {
+                        transmittance *= 2.;
+                    } else {
+                        transmittance = 0.;
+                        return false;
+                    }
+                }
+            }
+        });
+        transmittance
+    }
+}
+
+#[test]
+fn ratio_tracking_constant_density() {
+    // a constant density grid has the analytic transmittance of Beer's law
+    let bounds = AABB::new().extent(&Point3::new(0.0, 0.0, 0.0)).extent(&Point3::new(1.0, 1.0, 1.0));
+    let volume = GridVolume::new(DensityGrid::new(2, 2, 2, vec![1.0; 8]).unwrap(), bounds, 2.0, Vector3::new(1.0, 1.0, 1.0), 0.0);
+    let origin = Point3::new(-1.0, 0.5, 0.5);
+    let direction = Vector3::new(1.0, 0.0, 0.0);
+    let samples = 20000;
+    let estimate = (0..samples).map(|_| volume.transmittance(origin, direction, 10.0)).sum::<f32>() / samples as f32;
+    assert!((estimate - (-2.0f32).exp()).abs() < 0.01);
+    let collisions = (0..samples).filter(|_| volume.sample_collision(origin, direction, 10.0).is_some()).count();
+    assert!((collisions as f32 / samples as f32 - (1. - (-2.0f32).exp())).abs() < 0.02);
+}
+
+#[test]
+fn malformed_density_grids() {
+    let dense = |values: &[u32]| values.iter().flat_map(|value| vec![*value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]).collect::<Vec<u8>>();
+    assert!(DensityGrid::load_dense(&dense(&[1, 1, 2, 0, 0])).is_ok());
+    // empty, truncated or too large grids are errors instead of panics
+    assert!(DensityGrid::load_dense(&dense(&[0, 1, 1])).is_err());
+    assert!(DensityGrid::load_dense(&dense(&[1, 1, 2, 0])).is_err());
+    assert!(DensityGrid::load_dense(&dense(&[0xffffffff, 0xffffffff, 0xffffffff])).is_err());
+    assert!(DensityGrid::load_bricks(&dense(&[0, 0, 4, 4, 2, 0])).is_err());
+    assert!(DensityGrid::new(2, 2, 2, vec![1.0; 7]).is_err());
+}